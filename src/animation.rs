@@ -0,0 +1,139 @@
+use json::JsonValue;
+
+/// Returns a copy of a parsed scene file with every animated value replaced by its value at a given frame.
+///
+/// Any value in the scene file can be animated by replacing it with an object of the form
+///
+/// ```json
+/// {
+///     "animate": [
+///         { "frame": 1, "value": "0.0 1.0 0.0" },
+///         { "frame": 120, "value": "0.0 5.0 0.0" }
+///     ],
+///     "interpolation": "bezier"
+/// }
+/// ```
+///
+/// Numbers and vector strings are interpolated per component, either linearly (the default) or along a smooth
+/// cubic bezier curve through the keyframes. Any other value (e.g. a texture name) holds until the next keyframe.
+/// Frames before the first or after the last keyframe hold the nearest keyframe.
+pub fn resolve(value: &JsonValue, frame: f64) -> JsonValue {
+    match value {
+        JsonValue::Object(object) => {
+            if object.get("animate").is_some() {
+                return evaluate(value, frame);
+            }
+
+            let mut resolved = JsonValue::new_object();
+            for (key, entry) in object.iter() {
+                resolved[key] = resolve(entry, frame);
+            }
+            resolved
+        }
+        JsonValue::Array(array) => {
+            JsonValue::Array(array.iter().map(|entry| resolve(entry, frame)).collect())
+        }
+        _ => value.clone(),
+    }
+}
+
+/// Interpolation mode between two keyframes
+#[derive(PartialEq)]
+enum Interpolation {
+    Linear,
+    Bezier,
+}
+
+/// Evaluates a single animated value at a frame
+fn evaluate(animated: &JsonValue, frame: f64) -> JsonValue {
+    let interpolation = match animated["interpolation"].as_str() {
+        Some("linear") | None => Interpolation::Linear,
+        Some("bezier") => Interpolation::Bezier,
+        Some(other) => panic!("Unknown interpolation {}", other),
+    };
+
+    let mut keyframes: Vec<(f64, &JsonValue)> = animated["animate"]
+        .members()
+        .map(|keyframe| (keyframe["frame"].as_f64().unwrap(), &keyframe["value"]))
+        .collect();
+    if keyframes.is_empty() {
+        panic!("Animated value has no keyframes");
+    }
+    keyframes.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let last = keyframes.len() - 1;
+    if frame <= keyframes[0].0 {
+        return keyframes[0].1.clone();
+    }
+    if frame >= keyframes[last].0 {
+        return keyframes[last].1.clone();
+    }
+
+    let next = keyframes.iter().position(|k| k.0 > frame).unwrap();
+    let (f0, v0) = keyframes[next - 1];
+    let (f1, v1) = keyframes[next];
+
+    // Only numbers and vectors with the same number of components can be interpolated
+    let components: Vec<Option<Vec<f64>>> = keyframes.iter().map(|k| components(k.1)).collect();
+    let (c0, c1) = match (&components[next - 1], &components[next]) {
+        (Some(c0), Some(c1)) if c0.len() == c1.len() => (c0, c1),
+        _ => return v0.clone(),
+    };
+
+    let s = (frame - f0) / (f1 - f0);
+    let mut result = Vec::new();
+    for i in 0..c0.len() {
+        let value = match interpolation {
+            Interpolation::Linear => (1.0 - s) * c0[i] + s * c1[i],
+            Interpolation::Bezier => {
+                // Place the control points along the Catmull-Rom tangent of each keyframe. The first and last
+                // keyframes get flat tangents so the animation eases in and out.
+                let slope = |k: usize| -> f64 {
+                    if k == 0 || k == last {
+                        return 0.0;
+                    }
+                    match (&components[k - 1], &components[k + 1]) {
+                        (Some(prev), Some(next))
+                            if prev.len() == c0.len() && next.len() == c0.len() =>
+                        {
+                            (next[i] - prev[i]) / (keyframes[k + 1].0 - keyframes[k - 1].0)
+                        }
+                        _ => 0.0,
+                    }
+                };
+                let p0 = c0[i];
+                let p1 = c0[i] + slope(next - 1) * (f1 - f0) / 3.0;
+                let p2 = c1[i] - slope(next) * (f1 - f0) / 3.0;
+                let p3 = c1[i];
+
+                let t = 1.0 - s;
+                t * t * t * p0 + 3.0 * t * t * s * p1 + 3.0 * t * s * s * p2 + s * s * s * p3
+            }
+        };
+        result.push(value);
+    }
+
+    match v1 {
+        JsonValue::Number(_) => JsonValue::from(result[0]),
+        _ => JsonValue::from(
+            result
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<String>>()
+                .join(" "),
+        ),
+    }
+}
+
+/// Returns the numeric components of a number or vector string ("[x] [y] [z]")
+fn components(value: &JsonValue) -> Option<Vec<f64>> {
+    if let Some(number) = value.as_f64() {
+        return Some(vec![number]);
+    }
+
+    value
+        .as_str()?
+        .split_whitespace()
+        .map(|c| c.parse::<f64>().ok())
+        .collect()
+}
@@ -0,0 +1,809 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use half::f16;
+
+use crate::color::{linear_to_srgb, srgb_to_linear, ColorSpace, WorkingSpace};
+use crate::image::{read_image, to_working_space, ImageError, RowSink};
+use crate::mipmap::downsample;
+use crate::vector::Color;
+
+/// Width and height of the tiles of tiled images, in texels
+const TILE_SIZE: usize = 64;
+
+/// Memory images may take up, unless the scene sets another budget
+const DEFAULT_MEMORY_BUDGET: usize = 1024 * 1024 * 1024;
+
+/// Precision texels are stored at
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TexelFormat {
+    /// 8 bits per channel, clamped to [0, 1] and encoded with the sRGB curve. Enough for 8 bit color images.
+    U8,
+    /// Half floats. Keep hdr values up to 65504.
+    F16,
+    F32,
+}
+
+impl TexelFormat {
+    /// Returns the bytes a texel takes up
+    fn size(self) -> usize {
+        match self {
+            TexelFormat::U8 => 3,
+            TexelFormat::F16 => 6,
+            TexelFormat::F32 => 12,
+        }
+    }
+}
+
+/// Texels of a level or tile in one of the formats
+enum Texels {
+    U8(Vec<[u8; 3]>),
+    F16(Vec<[f16; 3]>),
+    F32(Vec<[f32; 3]>),
+}
+
+impl Texels {
+    fn new(format: TexelFormat, colors: &[Color]) -> Texels {
+        let mut texels = Texels::black(format, colors.len());
+        texels.write(0, colors);
+        texels
+    }
+
+    fn black(format: TexelFormat, len: usize) -> Texels {
+        match format {
+            TexelFormat::U8 => Texels::U8(vec![[0; 3]; len]),
+            TexelFormat::F16 => Texels::F16(vec![[f16::ZERO; 3]; len]),
+            TexelFormat::F32 => Texels::F32(vec![[0.0; 3]; len]),
+        }
+    }
+
+    /// Encodes colors into the texels from an index on
+    fn write(&mut self, start: usize, colors: &[Color]) {
+        let channels = colors.iter().map(|c| [c.x(), c.y(), c.z()]);
+        match self {
+            Texels::U8(texels) => {
+                for (texel, c) in texels[start..].iter_mut().zip(channels) {
+                    *texel = c.map(encode_u8);
+                }
+            }
+            Texels::F16(texels) => {
+                let max = f16::MAX.to_f64();
+                for (texel, c) in texels[start..].iter_mut().zip(channels) {
+                    *texel = c.map(|value| f16::from_f64(f64::clamp(value, -max, max)));
+                }
+            }
+            Texels::F32(texels) => {
+                for (texel, c) in texels[start..].iter_mut().zip(channels) {
+                    *texel = c.map(|value| value as f32);
+                }
+            }
+        }
+    }
+
+    fn get(&self, index: usize) -> Color {
+        match self {
+            Texels::U8(texels) => {
+                let table = srgb_table();
+                let [r, g, b] = texels[index];
+                Color::new(table[r as usize], table[g as usize], table[b as usize])
+            }
+            Texels::F16(texels) => {
+                let [r, g, b] = texels[index];
+                Color::new(r.to_f64(), g.to_f64(), b.to_f64())
+            }
+            Texels::F32(texels) => {
+                let [r, g, b] = texels[index];
+                Color::new(r as f64, g as f64, b as f64)
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Texels::U8(texels) => texels.len(),
+            Texels::F16(texels) => texels.len(),
+            Texels::F32(texels) => texels.len(),
+        }
+    }
+
+    fn format(&self) -> TexelFormat {
+        match self {
+            Texels::U8(_) => TexelFormat::U8,
+            Texels::F16(_) => TexelFormat::F16,
+            Texels::F32(_) => TexelFormat::F32,
+        }
+    }
+
+    /// Returns the memory the texels take up, in bytes
+    fn memory(&self) -> usize {
+        self.len() * self.format().size()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Texels::U8(texels) => texels.iter().flatten().cloned().collect(),
+            Texels::F16(texels) => texels
+                .iter()
+                .flatten()
+                .flat_map(|value| value.to_le_bytes())
+                .collect(),
+            Texels::F32(texels) => texels
+                .iter()
+                .flatten()
+                .flat_map(|value| value.to_le_bytes())
+                .collect(),
+        }
+    }
+
+    fn from_bytes(format: TexelFormat, bytes: &[u8]) -> Texels {
+        let texels = bytes.chunks_exact(format.size());
+        match format {
+            TexelFormat::U8 => Texels::U8(texels.map(|t| [t[0], t[1], t[2]]).collect()),
+            TexelFormat::F16 => Texels::F16(
+                texels
+                    .map(|t| [0, 2, 4].map(|i| f16::from_le_bytes([t[i], t[i + 1]])))
+                    .collect(),
+            ),
+            TexelFormat::F32 => Texels::F32(
+                texels
+                    .map(|t| [0, 4, 8].map(|i| f32::from_le_bytes(t[i..i + 4].try_into().unwrap())))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+fn encode_u8(value: f64) -> u8 {
+    (255.0 * linear_to_srgb(f64::clamp(value, 0.0, 1.0))).round() as u8
+}
+
+/// Linear values of the 8 bit sRGB codes
+fn srgb_table() -> &'static [f64; 256] {
+    static TABLE: OnceLock<[f64; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0.0; 256];
+        for (code, value) in table.iter_mut().enumerate() {
+            *value = srgb_to_linear(code as f64 / 255.0);
+        }
+        table
+    })
+}
+
+/// The mip levels of an image, stored bottom row first. Levels are either kept in memory, or split into tiles that
+/// are written to a temporary file and read back as lookups need them, so only recently used tiles take up memory.
+pub struct Pyramid {
+    sizes: Vec<(usize, usize)>,
+    storage: Storage,
+}
+
+enum Storage {
+    Resident(Vec<Texels>),
+    Tiled(TileFile),
+}
+
+impl Pyramid {
+    /// Builds the pyramid of an image from its full size level. Each level is averaged from the compact texels of
+    /// the one above it, so no level is held at full precision other than while it is being built.
+    fn new(width: usize, height: usize, first: Texels) -> Pyramid {
+        let format = first.format();
+        let mut sizes = vec![(width, height)];
+        let mut levels = vec![first];
+        loop {
+            let (width, height) = *sizes.last().unwrap();
+            let level = levels.last().unwrap();
+            match next_level(width, height, format, |index| level.get(index)) {
+                Some((width, height, next)) => {
+                    sizes.push((width, height));
+                    levels.push(next);
+                }
+                None => break,
+            }
+        }
+
+        Pyramid {
+            sizes,
+            storage: Storage::Resident(levels),
+        }
+    }
+
+    /// Builds the pyramid of a tiled image whose full size level was already written to its tile file. The next
+    /// level is averaged from tiles read back from the file, so the full size level is never in memory in full, and
+    /// only the level being averaged down is kept after that.
+    fn new_tiled(mut file: TileFile, width: usize, height: usize) -> io::Result<Pyramid> {
+        file.flush()?;
+        let format = file.format;
+        let mut sizes = vec![(width, height)];
+        let mut next = {
+            let first = TileReader::new(&file, 0, width);
+            next_level(width, height, format, |index| first.get(index))
+        };
+        while let Some((width, height, level)) = next {
+            sizes.push((width, height));
+            file.write_level(width, height, &level)?;
+            next = next_level(width, height, format, |index| level.get(index));
+        }
+        file.finish()?;
+
+        Ok(Pyramid {
+            sizes,
+            storage: Storage::Tiled(file),
+        })
+    }
+
+    /// Returns the memory the levels kept in memory take up, in bytes. Tiles are counted by the tile cache as they
+    /// are read instead.
+    fn memory(&self) -> usize {
+        match &self.storage {
+            Storage::Resident(levels) => levels.iter().map(Texels::memory).sum(),
+            Storage::Tiled(_) => 0,
+        }
+    }
+
+    pub fn level_count(&self) -> usize {
+        self.sizes.len()
+    }
+
+    /// Returns the width and height of a level
+    pub fn size(&self, level: usize) -> (usize, usize) {
+        self.sizes[level]
+    }
+
+    /// Returns the texel at a position inside a level
+    pub fn texel(&self, level: usize, x: usize, y: usize) -> Color {
+        let width = self.sizes[level].0;
+        match &self.storage {
+            Storage::Resident(levels) => levels[level].get(y * width + x),
+            Storage::Tiled(file) => {
+                let tiles_across = width.div_ceil(TILE_SIZE);
+                let index =
+                    file.first_tiles[level] + (y / TILE_SIZE) * tiles_across + x / TILE_SIZE;
+                let tile = cache().tile(file, index);
+                tile.get((y % TILE_SIZE) * TILE_SIZE + x % TILE_SIZE)
+            }
+        }
+    }
+}
+
+/// Returns the level below one of a pyramid, or None if the level is a single texel
+fn next_level(
+    width: usize,
+    height: usize,
+    format: TexelFormat,
+    texel: impl Fn(usize) -> Color,
+) -> Option<(usize, usize, Texels)> {
+    if width == 1 && height == 1 {
+        return None;
+    }
+    let (width, height, colors) = downsample(width, height, texel);
+    Some((width, height, Texels::new(format, &colors)))
+}
+
+/// Tiles of every level of a pyramid in a temporary file. Edge tiles are padded to the full tile size, so all
+/// tiles take up the same number of bytes.
+struct TileFile {
+    /// Tells the tiles of different files apart in the tile cache
+    id: u64,
+    file: Mutex<File>,
+    path: PathBuf,
+    format: TexelFormat,
+    /// Index of the first tile of each level
+    first_tiles: Vec<usize>,
+    tile_count: usize,
+    writer: Option<BufWriter<File>>,
+}
+
+impl TileFile {
+    fn create(format: TexelFormat) -> io::Result<TileFile> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("raytracer-tiles-{}-{}", process::id(), id));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        Ok(TileFile {
+            id,
+            writer: Some(BufWriter::new(file.try_clone()?)),
+            file: Mutex::new(file),
+            path,
+            format,
+            first_tiles: Vec::new(),
+            tile_count: 0,
+        })
+    }
+
+    fn tile_bytes(&self) -> usize {
+        TILE_SIZE * TILE_SIZE * self.format.size()
+    }
+
+    /// Adds a level to the end of the file, whose tiles are written afterwards
+    fn add_level(&mut self, width: usize, height: usize) {
+        self.first_tiles.push(self.tile_count);
+        self.tile_count += width.div_ceil(TILE_SIZE) * height.div_ceil(TILE_SIZE);
+    }
+
+    fn write_level(&mut self, width: usize, height: usize, texels: &Texels) -> io::Result<()> {
+        self.add_level(width, height);
+        let level = self.first_tiles.len() - 1;
+        for tile_y in 0..height.div_ceil(TILE_SIZE) {
+            self.write_tiles(level, width, height, tile_y, |x, y| {
+                texels.get(y * width + x)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Writes a row of tiles of a level. Rows of tiles can be written in any order, and texel is only called for
+    /// positions inside the row.
+    fn write_tiles(
+        &mut self,
+        level: usize,
+        width: usize,
+        height: usize,
+        tile_y: usize,
+        texel: impl Fn(usize, usize) -> Color,
+    ) -> io::Result<()> {
+        let tiles_across = width.div_ceil(TILE_SIZE);
+        let first = self.first_tiles[level] + tile_y * tiles_across;
+        let tile_bytes = self.tile_bytes();
+        let writer = self.writer.as_mut().unwrap();
+        writer.seek(SeekFrom::Start((first * tile_bytes) as u64))?;
+
+        let mut tile = Vec::with_capacity(TILE_SIZE * TILE_SIZE);
+        for tile_x in 0..tiles_across {
+            tile.clear();
+            for y in 0..TILE_SIZE {
+                for x in 0..TILE_SIZE {
+                    let x = usize::min(tile_x * TILE_SIZE + x, width - 1);
+                    let y = usize::min(tile_y * TILE_SIZE + y, height - 1);
+                    tile.push(texel(x, y));
+                }
+            }
+            writer.write_all(&Texels::new(self.format, &tile).to_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the tiles written so far, so they can be read back
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.as_mut().unwrap().flush()
+    }
+
+    /// Flushes the tiles, and removes the file from the file system. It stays readable through its handle on unix,
+    /// and is removed for good once closed, even if the renderer exits without dropping it.
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.take().unwrap().flush()?;
+        let _ = fs::remove_file(&self.path);
+        Ok(())
+    }
+
+    fn read(&self, index: usize) -> Texels {
+        let mut bytes = vec![0; self.tile_bytes()];
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start((index * self.tile_bytes()) as u64))
+            .and_then(|_| file.read_exact(&mut bytes))
+            .unwrap_or_else(|error| panic!("Failed to read texture tile: {}", error));
+        Texels::from_bytes(self.format, &bytes)
+    }
+}
+
+impl Drop for TileFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Texels of a level already written to a tile file, read back a row of tiles at a time
+struct TileReader<'a> {
+    file: &'a TileFile,
+    level: usize,
+    width: usize,
+    /// The rows of tiles read last, by the parity of their index. Averaging a level down reads from at most two
+    /// neighbouring rows at a time.
+    rows: RefCell<[Option<TileRow>; 2]>,
+}
+
+/// Index of a row of tiles, and its tiles from left to right
+type TileRow = (usize, Vec<Texels>);
+
+impl TileReader<'_> {
+    fn new(file: &TileFile, level: usize, width: usize) -> TileReader<'_> {
+        TileReader {
+            file,
+            level,
+            width,
+            rows: RefCell::new([None, None]),
+        }
+    }
+
+    fn get(&self, index: usize) -> Color {
+        let x = index % self.width;
+        let y = index / self.width;
+        let tile_y = y / TILE_SIZE;
+
+        let mut rows = self.rows.borrow_mut();
+        let row = &mut rows[tile_y % 2];
+        if !matches!(row, Some((read, _)) if *read == tile_y) {
+            let tiles_across = self.width.div_ceil(TILE_SIZE);
+            let first = self.file.first_tiles[self.level] + tile_y * tiles_across;
+            let tiles = (first..first + tiles_across)
+                .map(|index| self.file.read(index))
+                .collect();
+            *row = Some((tile_y, tiles));
+        }
+
+        let (_, tiles) = row.as_ref().unwrap();
+        tiles[x / TILE_SIZE].get((y % TILE_SIZE) * TILE_SIZE + x % TILE_SIZE)
+    }
+}
+
+/// Which channels of an image a texture reads
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageChannel {
+    Color,
+    /// The alpha channel, as a grey image
+    Alpha,
+}
+
+/// Settings that change the texels an image is loaded into. Images loaded with the same path and options share
+/// their texels.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ImageOptions {
+    pub channel: ImageChannel,
+    /// Overrides the color space the file declares
+    pub colorspace: Option<ColorSpace>,
+    pub working_space: WorkingSpace,
+    pub format: TexelFormat,
+    pub tiled: bool,
+}
+
+impl Default for ImageOptions {
+    fn default() -> ImageOptions {
+        ImageOptions {
+            channel: ImageChannel::Color,
+            colorspace: None,
+            working_space: WorkingSpace::Rec709,
+            format: TexelFormat::F16,
+            tiled: false,
+        }
+    }
+}
+
+/// Where the full size level of an image is encoded to
+enum Level {
+    /// The whole level, in memory
+    Resident(Texels),
+    /// The tile file of a tiled image, and the rows of the row of tiles being decoded, bottom row first
+    Tiled {
+        file: TileFile,
+        band: Texels,
+        tile_y: Option<usize>,
+    },
+}
+
+/// Encodes the rows of an image into the full size level of its pyramid as they are decoded, bottom row first.
+/// Tiled images are written to their tile file a row of tiles at a time, so only one row of tiles is in memory.
+struct LevelSink<'a> {
+    options: &'a ImageOptions,
+    colorspace: ColorSpace,
+    width: usize,
+    height: usize,
+    level: Option<Level>,
+    row: Vec<Color>,
+    /// The first error writing tiles, which rows can't return
+    result: io::Result<()>,
+}
+
+impl LevelSink<'_> {
+    /// Writes the row of tiles decoded last to the tile file of a tiled image
+    fn write_band(&mut self) {
+        if let Some(Level::Tiled { file, band, tile_y }) = &mut self.level {
+            if let Some(tile_y) = tile_y.take() {
+                let width = self.width;
+                let written = file.write_tiles(0, width, self.height, tile_y, |x, y| {
+                    band.get((y % TILE_SIZE) * width + x)
+                });
+                if self.result.is_ok() {
+                    self.result = written;
+                }
+            }
+        }
+    }
+
+    /// Returns the pyramid of the decoded image
+    fn finish(mut self) -> io::Result<Pyramid> {
+        self.write_band();
+        self.result?;
+        match self.level {
+            Some(Level::Resident(texels)) => Ok(Pyramid::new(self.width, self.height, texels)),
+            Some(Level::Tiled { file, .. }) => Pyramid::new_tiled(file, self.width, self.height),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "image has no rows",
+            )),
+        }
+    }
+}
+
+impl RowSink for LevelSink<'_> {
+    fn begin(&mut self, width: usize, height: usize, srgb: bool) {
+        let declared = if srgb {
+            ColorSpace::Srgb
+        } else {
+            ColorSpace::Linear
+        };
+        self.colorspace = self.options.colorspace.unwrap_or(declared);
+        self.width = width;
+        self.height = height;
+
+        let format = self.options.format;
+        self.level = if self.options.tiled {
+            match TileFile::create(format) {
+                Ok(mut file) => {
+                    file.add_level(width, height);
+                    Some(Level::Tiled {
+                        file,
+                        band: Texels::black(format, TILE_SIZE * width),
+                        tile_y: None,
+                    })
+                }
+                Err(error) => {
+                    self.result = Err(error);
+                    None
+                }
+            }
+        } else {
+            Some(Level::Resident(Texels::black(format, width * height)))
+        };
+    }
+
+    fn row(&mut self, y: usize, pixels: &[Color], alpha: &[f64]) {
+        self.row.clear();
+        match self.options.channel {
+            ImageChannel::Color => self.row.extend(pixels.iter().map(|&color| {
+                to_working_space(color, self.colorspace, self.options.working_space)
+            })),
+            ImageChannel::Alpha => self.row.extend(alpha.iter().map(|&a| Color::new(a, a, a))),
+        }
+
+        let y = self.height - 1 - y;
+        // Rows come in order, so a row of tiles is complete once a row of the next one arrives
+        if let Some(Level::Tiled { tile_y, .. }) = &self.level {
+            if tile_y.is_some_and(|tile_y| tile_y != y / TILE_SIZE) {
+                self.write_band();
+            }
+        }
+        match &mut self.level {
+            Some(Level::Resident(texels)) => texels.write(y * self.width, &self.row),
+            Some(Level::Tiled { band, tile_y, .. }) => {
+                *tile_y = Some(y / TILE_SIZE);
+                band.write((y % TILE_SIZE) * self.width, &self.row);
+            }
+            None => {}
+        }
+    }
+}
+
+/// Loads the pyramid of an image, or returns the one loaded before with the same options. The image is encoded
+/// into its texel format row by row as it is decoded, and tiled images are written to tiles as they are decoded.
+///
+/// Loaded images are kept so the frames of an animation don't decode them again. Images that aren't tiled count
+/// towards the memory budget along with the tiles of tiled images, and the least recently used are dropped from the
+/// cache once they go over it. They are freed once no texture uses them, so large images should still be tiled.
+pub fn load_image(path: &str, options: &ImageOptions) -> Result<Arc<Pyramid>, ImageError> {
+    let key = (path.to_string(), options.clone());
+    let slot = cache().images.lock().unwrap().slot(&key);
+
+    // Only loads of the same image wait on each other. Other images are loaded meanwhile.
+    let mut loaded = slot.lock().unwrap();
+    if let Some(pyramid) = &*loaded {
+        return Ok(Arc::clone(pyramid));
+    }
+
+    let mut sink = LevelSink {
+        options,
+        colorspace: ColorSpace::Raw,
+        width: 0,
+        height: 0,
+        level: None,
+        row: Vec::new(),
+        result: Ok(()),
+    };
+    read_image(path, &mut sink)?;
+    let pyramid = Arc::new(sink.finish()?);
+
+    *loaded = Some(Arc::clone(&pyramid));
+    drop(loaded);
+
+    cache().loaded(&key, pyramid.memory());
+    Ok(pyramid)
+}
+
+/// Sets how much memory images may take up, in bytes. The least recently used tiles, and images that aren't
+/// tiled, are dropped to stay under it.
+pub fn set_memory_budget(bytes: usize) {
+    let mut images = cache().images.lock().unwrap();
+    let mut tiles = cache().tiles.lock().unwrap();
+    tiles.budget = bytes;
+    images.evict(&mut tiles);
+    tiles.evict();
+}
+
+/// An image, once it has loaded. Failed loads leave it empty, so the next load tries again.
+type ImageSlot = Arc<Mutex<Option<Arc<Pyramid>>>>;
+
+/// Path and options of an image
+type ImageKey = (String, ImageOptions);
+
+struct CachedImage {
+    slot: ImageSlot,
+    /// Memory the image takes up once loaded, if it isn't tiled
+    memory: usize,
+    /// Time the image was last asked for
+    used: u64,
+}
+
+/// Images loaded so far
+struct ImageCache {
+    clock: u64,
+    images: HashMap<ImageKey, CachedImage>,
+}
+
+impl ImageCache {
+    /// Returns the slot of an image, adding an empty one if it isn't cached
+    fn slot(&mut self, key: &ImageKey) -> ImageSlot {
+        self.clock += 1;
+        let image = self
+            .images
+            .entry(key.clone())
+            .or_insert_with(|| CachedImage {
+                slot: ImageSlot::default(),
+                memory: 0,
+                used: 0,
+            });
+        image.used = self.clock;
+        Arc::clone(&image.slot)
+    }
+
+    /// Drops the least recently used images that aren't tiled until they fit the budget of the tile cache, keeping
+    /// at least the newest
+    fn evict(&mut self, tiles: &mut TileCache) {
+        while tiles.resident > tiles.budget {
+            let resident = || self.images.iter().filter(|(_, image)| image.memory > 0);
+            if resident().count() <= 1 {
+                break;
+            }
+            let key = resident()
+                .min_by_key(|(_, image)| image.used)
+                .map(|(key, _)| key.clone())
+                .unwrap();
+            let image = self.images.remove(&key).unwrap();
+            tiles.resident -= image.memory;
+        }
+    }
+}
+
+struct AssetCache {
+    images: Mutex<ImageCache>,
+    tiles: Mutex<TileCache>,
+}
+
+/// Id of a tile file and the index of a tile in it
+type TileKey = (u64, usize);
+
+/// Tiles read from tile files
+struct TileCache {
+    budget: usize,
+    used: usize,
+    /// Memory taken up by the cached images that aren't tiled, which counts towards the budget too
+    resident: usize,
+    clock: u64,
+    tiles: HashMap<TileKey, (Arc<Texels>, u64)>,
+    /// Keys of the tiles by the time they were last used, oldest first
+    order: BTreeMap<u64, TileKey>,
+}
+
+impl TileCache {
+    fn touch(&mut self, key: TileKey) -> Option<Arc<Texels>> {
+        self.clock += 1;
+        let (tile, used) = self.tiles.get_mut(&key)?;
+        self.order.remove(used);
+        *used = self.clock;
+        self.order.insert(self.clock, key);
+        Some(Arc::clone(tile))
+    }
+
+    fn insert(&mut self, key: TileKey, tile: Arc<Texels>) {
+        if self.touch(key).is_some() {
+            return;
+        }
+        self.used += tile.memory();
+        self.tiles.insert(key, (tile, self.clock));
+        self.order.insert(self.clock, key);
+        self.evict();
+    }
+
+    /// Drops the least recently used tiles until the cache fits its budget, keeping at least the newest
+    fn evict(&mut self) {
+        while self.used + self.resident > self.budget && self.order.len() > 1 {
+            let (_, key) = self.order.pop_first().unwrap();
+            let (tile, _) = self.tiles.remove(&key).unwrap();
+            self.used -= tile.memory();
+        }
+    }
+}
+
+thread_local! {
+    /// The tile the thread looked up last. Lookups mostly fall in the same tile as the one before, and skip
+    /// locking the cache when they do.
+    static LAST_TILE: RefCell<Option<(TileKey, Arc<Texels>)>> = const { RefCell::new(None) };
+}
+
+impl AssetCache {
+    /// Counts the memory of an image that just loaded, and drops images and tiles to stay under the budget
+    fn loaded(&self, key: &ImageKey, memory: usize) {
+        let mut images = self.images.lock().unwrap();
+        let mut tiles = self.tiles.lock().unwrap();
+        images.clock += 1;
+        let clock = images.clock;
+        // The image was dropped while it was loading, so it's left out
+        if let Some(image) = images.images.get_mut(key) {
+            image.memory = memory;
+            image.used = clock;
+            tiles.resident += memory;
+        }
+        images.evict(&mut tiles);
+        tiles.evict();
+    }
+
+    fn tile(&self, file: &TileFile, index: usize) -> Arc<Texels> {
+        let key = (file.id, index);
+        let last = LAST_TILE.with(|last| match &*last.borrow() {
+            Some((last_key, tile)) if *last_key == key => Some(Arc::clone(tile)),
+            _ => None,
+        });
+        if let Some(tile) = last {
+            return tile;
+        }
+
+        let cached = self.tiles.lock().unwrap().touch(key);
+        let tile = match cached {
+            Some(tile) => tile,
+            None => {
+                // Read without holding the lock, so other threads can use the cache meanwhile
+                let tile = Arc::new(file.read(index));
+                self.tiles.lock().unwrap().insert(key, Arc::clone(&tile));
+                tile
+            }
+        };
+        LAST_TILE.with(|last| *last.borrow_mut() = Some((key, Arc::clone(&tile))));
+        tile
+    }
+}
+
+fn cache() -> &'static AssetCache {
+    static CACHE: OnceLock<AssetCache> = OnceLock::new();
+    CACHE.get_or_init(|| AssetCache {
+        images: Mutex::new(ImageCache {
+            clock: 0,
+            images: HashMap::new(),
+        }),
+        tiles: Mutex::new(TileCache {
+            budget: DEFAULT_MEMORY_BUDGET,
+            used: 0,
+            resident: 0,
+            clock: 0,
+            tiles: HashMap::new(),
+            order: BTreeMap::new(),
+        }),
+    })
+}
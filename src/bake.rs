@@ -0,0 +1,203 @@
+use std::sync::Arc;
+
+use crate::buffer::Buffer;
+use crate::hit::{HitRecord, Hittable};
+use crate::lights::Light;
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::texture::Texture;
+use crate::utility::random_float_1;
+use crate::vector::{random_cosine_direction, zero_vec, Color, Vec3};
+
+/// What is baked into each texel
+pub enum BakeMode {
+    /// The value of a texture at the surface
+    Texture(Arc<dyn Texture>),
+    /// Fraction of the hemisphere above the surface that is open up to a distance
+    AmbientOcclusion { distance: f64 },
+    /// Light reaching the surface from the lights and from the cosine weighted hemisphere above it. Multiplying it
+    /// by the albedo of a diffuse surface gives the shading the path tracer would render.
+    Irradiance,
+    /// Light leaving the surface along its normal, as shaded by its material
+    Lighting,
+}
+
+/// Settings of a bake into the texture space of a named object
+pub struct BakeSettings {
+    pub object: String,
+    pub mode: BakeMode,
+    pub width: u32,
+    pub height: u32,
+    /// Samples per texel. Each sample looks up a random point of the texel.
+    pub samples: i32,
+    /// Number of texels to grow the baked area by, so that filtering across its edges doesn't pick up empty
+    /// texels
+    pub padding: u32,
+    /// Path of the image to write. Images ending in .exr are written unclamped, anything else as a png.
+    pub output: String,
+}
+
+impl Scene {
+    /// Bakes the scene into the texture space of an object with the settings in the scene file, and writes the
+    /// result to its output image
+    pub fn bake(&self) {
+        let settings = match &self.bake {
+            Some(settings) => settings,
+            None => panic!("The scene has no bake settings"),
+        };
+        let object = match self.named_objects.get(&settings.object) {
+            Some(object) => object,
+            None => panic!("No object is named {}", settings.object),
+        };
+        if !object.bakeable() {
+            panic!(
+                "{} can't be baked, as its texture coordinates don't pick out a single point of its surface. Boxes \
+                 use the same coordinates on every side.",
+                settings.object
+            );
+        }
+
+        let mut buffer = self.bake_object(object.as_ref(), settings);
+        for y in 0..buffer.height() {
+            for x in 0..buffer.width() {
+                let color = self.working_space.to_rec709(buffer.at(x, y));
+                buffer.write(color, x, y);
+            }
+        }
+
+        if settings.output.ends_with(".exr") {
+            buffer.buffer_to_exr(settings.output.clone());
+        } else {
+            buffer.buffer_to_png(settings.output.clone());
+        }
+    }
+
+    /// Returns the baked texture space of an object. Texels without any point of the surface in them are filled
+    /// in from their neighbours up to the padding, and left black beyond it.
+    fn bake_object(&self, object: &dyn Hittable, settings: &BakeSettings) -> Buffer {
+        let width = settings.width as usize;
+        let height = settings.height as usize;
+        let num_threads = usize::max(num_cpus::get(), 1);
+        let rows_per_thread = height.div_ceil(num_threads);
+        let time = self.shutter().0;
+
+        // Texels are stored bottom row first, matching v
+        let mut texels: Vec<Option<Color>> = vec![None; width * height];
+        crossbeam::scope(|scope| {
+            for (chunk_index, chunk) in texels.chunks_mut(rows_per_thread * width).enumerate() {
+                scope.spawn(move |_| {
+                    for (index, texel) in chunk.iter_mut().enumerate() {
+                        let index = chunk_index * rows_per_thread * width + index;
+                        let x = index % width;
+                        let y = index / width;
+
+                        let mut color = zero_vec();
+                        let mut covered = 0;
+                        for _ in 0..settings.samples {
+                            let u = (x as f64 + random_float_1()) / width as f64;
+                            let v = (y as f64 + random_float_1()) / height as f64;
+                            if let Some(mut rec) = object.point_at_uv(u, v, time) {
+                                rec.uv.time = time;
+                                // Textures are filtered over the texel
+                                rec.uv.dudx = 1.0 / width as f64;
+                                rec.uv.dvdy = 1.0 / height as f64;
+                                rec.uv.dpdx = rec.dpdu / width as f64;
+                                rec.uv.dpdy = rec.dpdv / height as f64;
+
+                                color += self.bake_sample(&rec, &settings.mode, time);
+                                covered += 1;
+                            }
+                        }
+
+                        if covered > 0 {
+                            *texel = Some(color / covered as f64);
+                        }
+                    }
+                });
+            }
+        })
+        .unwrap();
+
+        if texels.iter().all(|texel| texel.is_none()) {
+            eprintln!(
+                "{} has no surface in texture space, so nothing was baked",
+                settings.object
+            );
+        }
+
+        for _ in 0..settings.padding {
+            texels = dilate(&texels, width, height);
+        }
+
+        let mut buffer = Buffer::new(settings.width, settings.height);
+        for (index, texel) in texels.iter().enumerate() {
+            let color = texel.unwrap_or_else(zero_vec);
+            buffer.write(color, (index % width) as u32, (index / width) as u32);
+        }
+
+        buffer
+    }
+
+    /// Returns the baked value of a single point of the surface
+    fn bake_sample(&self, rec: &HitRecord, mode: &BakeMode, time: f64) -> Color {
+        // The baked point is the first bounce of a path, like the hit of a camera ray
+        let depth = self.max_depth() - 1;
+        match mode {
+            BakeMode::Texture(texture) => texture.value(&rec.uv, &rec.p),
+            BakeMode::AmbientOcclusion { distance } => {
+                let ray = Ray::new(rec.p, cosine_direction(rec), time);
+                if self.any_hit(&ray, 0.001, *distance) {
+                    zero_vec()
+                } else {
+                    Color::new(1.0, 1.0, 1.0)
+                }
+            }
+            BakeMode::Irradiance => {
+                // Lights are looked up as if seen head on
+                let incoming = Ray::new(rec.p + rec.normal, -rec.normal, time);
+                let direct = self.lights.apply(&incoming, rec, self).contribution;
+                let ray = Ray::new(rec.p, cosine_direction(rec), time);
+                direct + self.ray_color(&ray, depth)
+            }
+            BakeMode::Lighting => {
+                let incoming = Ray::new(rec.p + rec.normal, -rec.normal, time);
+                rec.material.apply(&incoming, rec, self, depth)
+            }
+        }
+    }
+}
+
+/// Returns a random direction above a surface, with density proportional to its cosine to the normal
+fn cosine_direction(rec: &HitRecord) -> Vec3 {
+    Onb::build_from_w(&rec.normal).local(&random_cosine_direction())
+}
+
+/// Fills every empty texel next to a baked one with the average of its baked neighbours
+fn dilate(texels: &[Option<Color>], width: usize, height: usize) -> Vec<Option<Color>> {
+    let mut dilated = texels.to_vec();
+    for y in 0..height {
+        for x in 0..width {
+            if texels[y * width + x].is_some() {
+                continue;
+            }
+
+            let mut color = zero_vec();
+            let mut count = 0;
+            for ny in y.saturating_sub(1)..usize::min(y + 2, height) {
+                for nx in x.saturating_sub(1)..usize::min(x + 2, width) {
+                    if let Some(neighbour) = texels[ny * width + nx] {
+                        color += neighbour;
+                        count += 1;
+                    }
+                }
+            }
+
+            if count > 0 {
+                dilated[y * width + x] = Some(color / count as f64);
+            }
+        }
+    }
+
+    dilated
+}
@@ -1,5 +1,6 @@
+use crate::color::linear_to_srgb;
 use crate::utility::clamp;
-use crate::vector::{zero_vec, Color, Vec3};
+use crate::vector::{zero_vec, Color};
 use std::fs::File;
 use std::io::BufWriter;
 use std::path::Path;
@@ -21,36 +22,6 @@ impl Buffer {
         }
     }
 
-    pub fn new_from_png(path: String) -> Buffer {
-        // read in the png file
-        let decoder = png::Decoder::new(File::open(path).unwrap());
-        let mut reader = decoder.read_info().unwrap();
-        let mut buf = vec![0; reader.output_buffer_size()];
-        let info = reader.next_frame(&mut buf).unwrap();
-        let bytes = &buf[..info.buffer_size()];
-        // fetch the width and height
-        let header = reader.info();
-        let width = header.width;
-        let height = header.height;
-
-        // allocate the Color buffer
-        let mut buffer = Buffer {
-            e: Vec::new(),
-            width,
-            height,
-        };
-
-        for i in (0..reader.output_buffer_size()).step_by(3) {
-            buffer.e.push(Vec3::new(
-                bytes[i] as f64 / 256.0,
-                bytes[i + 1] as f64 / 256.0,
-                bytes[i + 2] as f64 / 256.0,
-            ))
-        }
-
-        return buffer;
-    }
-
     /// Returns the 1d array index of [r(ow), c(olumn)]
     fn index(&self, r: u32, c: u32) -> usize {
         return ((self.height - r - 1) * self.width + c) as usize;
@@ -88,7 +59,7 @@ impl Buffer {
         self.height
     }
 
-    /// Outputs the buffer to a png file
+    /// Outputs the buffer to an sRGB encoded png file. Colors are linear Rec.709 and are clamped to [0, 1].
     pub fn buffer_to_png(&self, path: String) {
         let path = Path::new(&path);
         let file = File::create(path).unwrap();
@@ -97,18 +68,27 @@ impl Buffer {
         let mut encoder = png::Encoder::new(w, self.width, self.height);
         encoder.set_color(png::ColorType::Rgb);
         encoder.set_depth(png::BitDepth::Eight);
-        encoder.set_source_gamma(png::ScaledFloat::new(1.0 / 2.2));
+        encoder.set_srgb(png::SrgbRenderingIntent::Perceptual);
 
         let mut writer = encoder.write_header().unwrap();
 
-        // Copy buffer as i32
+        let encode = |value: f64| (255.0 * linear_to_srgb(clamp(value, 0.0, 1.0))).round() as u8;
         let mut data: Vec<u8> = Vec::new();
         for element in &self.e {
-            data.push((256.0 * clamp(element.x(), 0.0, 0.999)) as u8);
-            data.push((256.0 * clamp(element.y(), 0.0, 0.999)) as u8);
-            data.push((256.0 * clamp(element.z(), 0.0, 0.999)) as u8);
+            data.push(encode(element.x()));
+            data.push(encode(element.y()));
+            data.push(encode(element.z()));
         }
 
         writer.write_image_data(&data).unwrap();
     }
+
+    /// Outputs the buffer to an exr file. Colors are linear Rec.709 and are stored unclamped as 32 bit floats.
+    pub fn buffer_to_exr(&self, path: String) {
+        exr::prelude::write_rgb_file(path, self.width as usize, self.height as usize, |x, y| {
+            let color = self.e[y * self.width as usize + x];
+            (color.x() as f32, color.y() as f32, color.z() as f32)
+        })
+        .unwrap();
+    }
 }
@@ -15,9 +15,15 @@ pub struct BVHNode {
 
 impl BVHNode {
     /// Compares two boxes along an axis (0=x, 1=y, 2=z)
-    fn box_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>, axis: u32) -> Ordering {
-        let box_a = a.bounding_box();
-        let box_b = b.bounding_box();
+    fn box_compare(
+        a: &Arc<dyn Hittable>,
+        b: &Arc<dyn Hittable>,
+        axis: u32,
+        time0: f64,
+        time1: f64,
+    ) -> Ordering {
+        let box_a = a.bounding_box(time0, time1);
+        let box_b = b.bounding_box(time0, time1);
 
         if box_a.min().e[axis as usize] + box_a.max().e[axis as usize] / 2.0
             == box_b.min().e[axis as usize] + box_b.max().e[axis as usize] / 2.0
@@ -33,9 +39,10 @@ impl BVHNode {
         }
     }
 
-    pub fn new(src_objects: &Vec<Arc<dyn Hittable>>, axis: u32) -> BVHNode {
+    /// Builds a BVH over objects whose bounding boxes cover the shutter interval [time0, time1]
+    pub fn new(src_objects: &Vec<Arc<dyn Hittable>>, axis: u32, time0: f64, time1: f64) -> BVHNode {
         let mut objects = src_objects.clone();
-        objects.sort_by(|a, b| BVHNode::box_compare(a, b, axis));
+        objects.sort_by(|a, b| BVHNode::box_compare(a, b, axis, time0, time1));
 
         let length = objects.len();
 
@@ -43,26 +50,40 @@ impl BVHNode {
             BVHNode {
                 left: Arc::clone(&objects[0]),
                 right: Arc::clone(&objects[0]),
-                aabb: src_objects[0].bounding_box(),
+                aabb: src_objects[0].bounding_box(time0, time1),
             }
         } else if length == 2 {
             BVHNode {
                 left: Arc::clone(&objects[0]),
                 right: Arc::clone(&objects[1]),
-                aabb: AABB::surround(&objects[0].bounding_box(), &objects[1].bounding_box()),
+                aabb: AABB::surround(
+                    &objects[0].bounding_box(time0, time1),
+                    &objects[1].bounding_box(time0, time1),
+                ),
             }
         } else {
             let mid = length / 2;
 
-            let left: Arc<dyn Hittable> =
-                Arc::new(BVHNode::new(&objects[0..mid].to_vec(), (axis + 1) % 3));
-            let right: Arc<dyn Hittable> =
-                Arc::new(BVHNode::new(&objects[mid..length].to_vec(), (axis + 1) % 3));
+            let left: Arc<dyn Hittable> = Arc::new(BVHNode::new(
+                &objects[0..mid].to_vec(),
+                (axis + 1) % 3,
+                time0,
+                time1,
+            ));
+            let right: Arc<dyn Hittable> = Arc::new(BVHNode::new(
+                &objects[mid..length].to_vec(),
+                (axis + 1) % 3,
+                time0,
+                time1,
+            ));
 
             BVHNode {
                 left: Arc::clone(&left),
                 right: Arc::clone(&right),
-                aabb: AABB::surround(&left.bounding_box(), &right.bounding_box()),
+                aabb: AABB::surround(
+                    &left.bounding_box(time0, time1),
+                    &right.bounding_box(time0, time1),
+                ),
             }
         }
     }
@@ -97,7 +118,7 @@ impl Hittable for BVHNode {
         }
     }
 
-    fn bounding_box(&self) -> AABB {
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> AABB {
         return self.aabb;
     }
 }
@@ -1,10 +1,34 @@
-use crate::ray::Ray;
-use crate::utility::{degrees_to_radians, random_in_unit_disk};
+use crate::ray::{Ray, RayDifferential};
+use crate::utility::{degrees_to_radians, random_float, random_in_unit_disk};
 use crate::vector::*;
 
 /// Trait for a Camera class that generates rays for the scene
 pub trait Camera: Sync + Send {
     fn get_ray(&self, s: f64, t: f64) -> Ray;
+
+    /// Returns a ray along with rays offset by ds and dt in screen space, all through the same point on the lens
+    fn get_ray_differential(&self, s: f64, t: f64, ds: f64, dt: f64) -> (Ray, RayDifferential);
+
+    /// Returns the times the shutter opens and closes at
+    fn shutter(&self) -> (f64, f64);
+}
+
+/// Interval a camera's shutter is open for. Rays are cast at random times within it.
+#[derive(Copy, Clone)]
+pub struct Shutter {
+    pub open: f64,
+    pub close: f64,
+}
+
+impl Shutter {
+    /// Returns a random time in the shutter interval [open, close]
+    fn time(&self) -> f64 {
+        if self.close > self.open {
+            random_float(self.open, self.close)
+        } else {
+            self.open
+        }
+    }
 }
 
 /// Simple perspective camera
@@ -13,10 +37,11 @@ pub struct PerspectiveCamera {
     horizontal: Vec3,
     vertical: Vec3,
     lower_left_corner: Vec3,
+    shutter: Shutter,
 }
 
 impl PerspectiveCamera {
-    /// Creates a new PerspectiveCamera
+    /// Creates a new PerspectiveCamera with a shutter that opens and closes at time 0
     pub fn new(
         lookfrom: Point3,
         lookat: Point3,
@@ -43,16 +68,47 @@ impl PerspectiveCamera {
             horizontal,
             vertical,
             lower_left_corner,
+            shutter: Shutter {
+                open: 0.0,
+                close: 0.0,
+            },
         }
     }
+
+    /// Sets the interval the shutter is open for
+    pub fn with_shutter(mut self, shutter: Shutter) -> PerspectiveCamera {
+        self.shutter = shutter;
+        self
+    }
 }
 
 impl Camera for PerspectiveCamera {
     fn get_ray(&self, s: f64, t: f64) -> Ray {
         let origin = self.origin;
         let direction = self.lower_left_corner + s * self.horizontal + t * self.vertical - origin;
+        let time = self.shutter.time();
 
-        Ray { origin, direction }
+        Ray {
+            origin,
+            direction,
+            time,
+            wavelength: None,
+        }
+    }
+
+    fn get_ray_differential(&self, s: f64, t: f64, ds: f64, dt: f64) -> (Ray, RayDifferential) {
+        let ray = self.get_ray(s, t);
+        let differential = RayDifferential {
+            rx_origin: self.origin,
+            rx_direction: ray.direction + ds * self.horizontal,
+            ry_origin: self.origin,
+            ry_direction: ray.direction + dt * self.vertical,
+        };
+        (ray, differential)
+    }
+
+    fn shutter(&self) -> (f64, f64) {
+        (self.shutter.open, self.shutter.close)
     }
 }
 
@@ -67,6 +123,7 @@ pub struct RTOWCamera {
     v: Vec3,
     w: Vec3,
     lens_radius: f64,
+    shutter: Shutter,
 }
 
 impl RTOWCamera {
@@ -76,6 +133,10 @@ impl RTOWCamera {
     /// vup: up vector
     /// vfov: Vertical field of view in degrees
     /// aspect_ratio: aspect ratio of the image
+    /// aperture: diameter of the lens
+    /// focus_dist: distance to the plane in focus
+    ///
+    /// The shutter opens and closes at time 0, see with_shutter.
     pub fn new(
         lookfrom: Point3,
         lookat: Point3,
@@ -110,8 +171,18 @@ impl RTOWCamera {
             v,
             w,
             lens_radius,
+            shutter: Shutter {
+                open: 0.0,
+                close: 0.0,
+            },
         }
     }
+
+    /// Sets the interval the shutter is open for
+    pub fn with_shutter(mut self, shutter: Shutter) -> RTOWCamera {
+        self.shutter = shutter;
+        self
+    }
 }
 
 impl Camera for RTOWCamera {
@@ -124,6 +195,24 @@ impl Camera for RTOWCamera {
             direction: self.lower_left_corner + s * self.horizontal + t * self.vertical
                 - self.origin
                 - offset,
+            time: self.shutter.time(),
+            wavelength: None,
+        };
+    }
+
+    fn get_ray_differential(&self, s: f64, t: f64, ds: f64, dt: f64) -> (Ray, RayDifferential) {
+        // All three rays leave from the same point on the lens, so they meet on the plane of focus
+        let ray = self.get_ray(s, t);
+        let differential = RayDifferential {
+            rx_origin: ray.origin,
+            rx_direction: ray.direction + ds * self.horizontal,
+            ry_origin: ray.origin,
+            ry_direction: ray.direction + dt * self.vertical,
         };
+        (ray, differential)
+    }
+
+    fn shutter(&self) -> (f64, f64) {
+        (self.shutter.open, self.shutter.close)
     }
 }
@@ -0,0 +1,126 @@
+use crate::vector::Color;
+
+/// Linear sRGB (Rec.709 primaries, D65) to ACEScg (AP1 primaries, D60), with a Bradford white point adaptation
+const REC709_TO_ACESCG: [[f64; 3]; 3] = [
+    [0.6130974, 0.3395231, 0.0473795],
+    [0.0701937, 0.9163539, 0.0134524],
+    [0.0206156, 0.1095698, 0.8698147],
+];
+
+/// Inverse of REC709_TO_ACESCG
+const ACESCG_TO_REC709: [[f64; 3]; 3] = [
+    [1.7050510, -0.6217921, -0.0832589],
+    [-0.1302564, 1.1408048, -0.0105485],
+    [-0.0240033, -0.1289690, 1.1529723],
+];
+
+/// How the values of an image are encoded
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    /// sRGB transfer curve with Rec.709 primaries, as 8 bit color images usually are
+    Srgb,
+    /// Linear values with Rec.709 primaries, as hdr and exr images usually are
+    Linear,
+    /// Data that isn't a color, like normal maps. Used as is.
+    Raw,
+}
+
+/// The linear rgb space colors are rendered in. Scene colors and textures are given with Rec.709 primaries and are
+/// converted into it when the scene is read, and the image is converted back out of it when it is written.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WorkingSpace {
+    /// Linear sRGB
+    Rec709,
+    /// ACEScg. Its wider gamut gives more plausible results for saturated colors bouncing between surfaces.
+    AcesCg,
+}
+
+impl WorkingSpace {
+    /// Converts a linear Rec.709 color into the working space
+    pub fn to_working(self, color: Color) -> Color {
+        match self {
+            WorkingSpace::Rec709 => color,
+            WorkingSpace::AcesCg => transform(&REC709_TO_ACESCG, color),
+        }
+    }
+
+    /// Converts a color in the working space to linear Rec.709
+    pub fn to_rec709(self, color: Color) -> Color {
+        match self {
+            WorkingSpace::Rec709 => color,
+            WorkingSpace::AcesCg => transform(&ACESCG_TO_REC709, color),
+        }
+    }
+
+    /// Returns the luminance of a color in the working space
+    pub fn luminance(self, color: Color) -> f64 {
+        let weights = match self {
+            WorkingSpace::Rec709 => [0.2126, 0.7152, 0.0722],
+            WorkingSpace::AcesCg => [0.2722287, 0.6740818, 0.0536895],
+        };
+        weights[0] * color.x() + weights[1] * color.y() + weights[2] * color.z()
+    }
+}
+
+fn transform(matrix: &[[f64; 3]; 3], color: Color) -> Color {
+    Color::new(
+        matrix[0][0] * color.x() + matrix[0][1] * color.y() + matrix[0][2] * color.z(),
+        matrix[1][0] * color.x() + matrix[1][1] * color.y() + matrix[1][2] * color.z(),
+        matrix[2][0] * color.x() + matrix[2][1] * color.y() + matrix[2][2] * color.z(),
+    )
+}
+
+/// Converts an sRGB encoded value in [0, 1] to linear
+pub fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        f64::powf((value + 0.055) / 1.055, 2.4)
+    }
+}
+
+/// Encodes a linear value in [0, 1] with the sRGB transfer curve
+pub fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * f64::powf(value, 1.0 / 2.4) - 0.055
+    }
+}
+
+/// Converts an rgb color to hue in [0, 1), saturation and value. Values above 1 are kept, so it works on hdr colors.
+pub fn rgb_to_hsv(color: Color) -> (f64, f64, f64) {
+    let (r, g, b) = (color.x(), color.y(), color.z());
+    let max = f64::max(r, f64::max(g, b));
+    let min = f64::min(r, f64::min(g, b));
+    let delta = max - min;
+
+    let hue = if delta <= 0.0 {
+        0.0
+    } else if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    let saturation = if max > 0.0 { delta / max } else { 0.0 };
+    (hue / 6.0, saturation, max)
+}
+
+/// Inverse of rgb_to_hsv. Hues wrap around.
+pub fn hsv_to_rgb(hue: f64, saturation: f64, value: f64) -> Color {
+    let h = 6.0 * hue.rem_euclid(1.0);
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - f64::abs(h.rem_euclid(2.0) - 1.0));
+    let (r, g, b) = match h as i32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = value - chroma;
+    Color::new(r + m, g + m, b + m)
+}
@@ -0,0 +1,153 @@
+use std::sync::Arc;
+
+use crate::aabb::AABB;
+use crate::hit::{HitRecord, Hittable};
+use crate::materials::{Material, ScatterRecord};
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::spectrum::{SampledSpectrum, SampledWavelengths};
+use crate::texture::Texture;
+use crate::utility::random_float_1;
+use crate::vector::{Color, Vec3};
+
+/// Distance to step past a cut out hit before looking for the next one
+pub const CUTOUT_EPSILON: f64 = 0.001;
+
+/// How an opacity mask decides whether a hit is solid
+#[derive(Clone, Copy)]
+pub enum AlphaMode {
+    /// Solid wherever the opacity is at least the threshold. Gives hard edges, as for leaves and fences.
+    Threshold(f64),
+    /// Solid with probability equal to the opacity. Averages out to partial transparency, as for decals.
+    Stochastic,
+}
+
+/// Opacity read from the first channel of a texture
+pub struct OpacityMask {
+    pub opacity: Arc<dyn Texture>,
+    pub mode: AlphaMode,
+}
+
+impl OpacityMask {
+    pub fn new(opacity: Arc<dyn Texture>, mode: AlphaMode) -> OpacityMask {
+        OpacityMask { opacity, mode }
+    }
+
+    /// Returns true if the surface is solid at a hit
+    pub fn opaque(&self, rec: &HitRecord) -> bool {
+        let opacity = self.opacity.value(&rec.uv, &rec.p).x();
+        match self.mode {
+            AlphaMode::Threshold(threshold) => opacity >= threshold,
+            AlphaMode::Stochastic => random_float_1() < opacity,
+        }
+    }
+}
+
+/// Cuts holes into a shape where its opacity mask is transparent. Rays, including shadow rays, pass through the
+/// holes to whatever is behind them.
+pub struct Cutout {
+    ptr: Arc<dyn Hittable>,
+    mask: OpacityMask,
+}
+
+impl Cutout {
+    pub fn new(ptr: Arc<dyn Hittable>, mask: OpacityMask) -> Cutout {
+        Cutout { ptr, mask }
+    }
+}
+
+impl Hittable for Cutout {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut t_min = t_min;
+        loop {
+            let rec = self.ptr.hit(r, t_min, t_max)?;
+            if self.mask.opaque(&rec) {
+                return Some(rec);
+            }
+            t_min = rec.t + CUTOUT_EPSILON;
+        }
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> AABB {
+        self.ptr.bounding_box(time0, time1)
+    }
+
+    fn random_surface_point(&self, time: f64) -> Option<HitRecord> {
+        self.ptr.random_surface_point(time)
+    }
+
+    fn area(&self) -> f64 {
+        self.ptr.area()
+    }
+
+    fn bakeable(&self) -> bool {
+        self.ptr.bakeable()
+    }
+
+    /// Points in the holes of the mask aren't part of the surface
+    fn point_at_uv(&self, u: f64, v: f64, time: f64) -> Option<HitRecord> {
+        let rec = self.ptr.point_at_uv(u, v, time)?;
+        if self.mask.opaque(&rec) {
+            Some(rec)
+        } else {
+            None
+        }
+    }
+}
+
+/// Wraps a material with an opacity mask, cutting holes into every shape that uses it
+pub struct Masked {
+    material: Arc<dyn Material>,
+    mask: OpacityMask,
+}
+
+impl Masked {
+    pub fn new(material: Arc<dyn Material>, mask: OpacityMask) -> Masked {
+        Masked { material, mask }
+    }
+}
+
+impl Material for Masked {
+    fn apply(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene, depth: i32) -> Color {
+        self.material.apply(r_in, rec, scene, depth)
+    }
+
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.material.scatter(r_in, rec)
+    }
+
+    fn scatter_in_scene(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        scene: &Scene,
+    ) -> Option<ScatterRecord> {
+        self.material.scatter_in_scene(r_in, rec, scene)
+    }
+
+    fn scatter_spectral(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        scene: &Scene,
+        lambdas: &SampledWavelengths,
+    ) -> Option<(ScatterRecord, SampledSpectrum)> {
+        self.material.scatter_spectral(r_in, rec, scene, lambdas)
+    }
+
+    fn brdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        self.material.brdf(r_in, rec, direction)
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.material.emitted(rec)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.material.is_dispersive()
+    }
+
+    fn opaque(&self, rec: &HitRecord) -> bool {
+        self.mask.opaque(rec) && self.material.opaque(rec)
+    }
+}
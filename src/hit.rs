@@ -1,9 +1,10 @@
 use std::sync::Arc;
 
 use super::materials::Material;
-use super::ray::Ray;
+use super::ray::{Ray, RayDifferential};
 use crate::aabb::AABB;
 use crate::texture::TextureCoord;
+use crate::utility::random_float_1;
 use crate::{materials::Diffuse, vector::*};
 
 /// Hit record class
@@ -15,10 +16,21 @@ pub struct HitRecord {
     pub t: f64,
     pub front_face: bool,
     pub uv: TextureCoord,
+    /// Partial derivatives of the hit point with respect to u and v. They span the tangent plane and orient normal
+    /// maps. Either may be zero where the texture coordinates are degenerate.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
 }
 
 impl HitRecord {
+    /// Orients the normal against the ray. Shapes call this before any instance transforms the hit, so it also
+    /// records the hit in object space for textures projected there, along with the time of the ray for animated
+    /// textures.
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: &Vec3) {
+        self.uv.object_p = self.p;
+        self.uv.object_normal = *outward_normal;
+        self.uv.normal = *outward_normal;
+        self.uv.time = r.time;
         self.front_face = dot(&r.direction, outward_normal) < 0.0;
 
         if self.front_face {
@@ -27,6 +39,74 @@ impl HitRecord {
             self.normal = *outward_normal * -1.0;
         }
     }
+
+    /// Returns a record for a point found on a surface rather than by a ray, facing out of it. Like
+    /// set_face_normal, it records the point in object space.
+    pub fn at_surface_point(
+        p: Point3,
+        outward_normal: Vec3,
+        material: &Arc<dyn Material>,
+        uv: TextureCoord,
+        dpdu: Vec3,
+        dpdv: Vec3,
+    ) -> HitRecord {
+        let mut uv = uv;
+        uv.object_p = p;
+        uv.object_normal = outward_normal;
+        uv.normal = outward_normal;
+
+        HitRecord {
+            p,
+            normal: outward_normal,
+            material: Arc::clone(material),
+            t: 0.0,
+            front_face: true,
+            uv,
+            dpdu,
+            dpdv,
+        }
+    }
+
+    /// Sets the change in texture coordinates across a pixel, by intersecting the offset rays of a camera ray with
+    /// the tangent plane at the hit
+    pub fn set_texture_footprint(&mut self, differential: &RayDifferential) {
+        let n = self.normal;
+        let plane = dot(&n, &self.p);
+        let cos_x = dot(&n, &differential.rx_direction);
+        let cos_y = dot(&n, &differential.ry_direction);
+        if cos_x.abs() < 1e-12 || cos_y.abs() < 1e-12 {
+            return;
+        }
+
+        let tx = (plane - dot(&n, &differential.rx_origin)) / cos_x;
+        let ty = (plane - dot(&n, &differential.ry_origin)) / cos_y;
+        let dpdx = differential.rx_origin + tx * differential.rx_direction - self.p;
+        let dpdy = differential.ry_origin + ty * differential.ry_direction - self.p;
+
+        // Solve dp = dpdu * du + dpdv * dv for du and dv in the least squares sense
+        let a00 = dot(&self.dpdu, &self.dpdu);
+        let a01 = dot(&self.dpdu, &self.dpdv);
+        let a11 = dot(&self.dpdv, &self.dpdv);
+        let det = a00 * a11 - a01 * a01;
+        if det.abs() < 1e-20 {
+            return;
+        }
+
+        let solve = |dp: &Vec3| {
+            let b0 = dot(&self.dpdu, dp);
+            let b1 = dot(&self.dpdv, dp);
+            ((a11 * b0 - a01 * b1) / det, (a00 * b1 - a01 * b0) / det)
+        };
+        let (dudx, dvdx) = solve(&dpdx);
+        let (dudy, dvdy) = solve(&dpdy);
+
+        self.uv.dpdx = dpdx;
+        self.uv.dpdy = dpdy;
+        self.uv.dudx = dudx;
+        self.uv.dvdx = dvdx;
+        self.uv.dudy = dudy;
+        self.uv.dvdy = dvdy;
+    }
 }
 
 /// An object that can be intersected by a ray
@@ -34,7 +114,35 @@ pub trait Hittable: Sync + Send {
     /// Implements ray intersect function for a given object
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
 
-    fn bounding_box(&self) -> AABB;
+    /// Returns a bounding box that contains the object for the whole interval [time0, time1]
+    fn bounding_box(&self, time0: f64, time1: f64) -> AABB;
+
+    /// Returns a uniformly distributed random point on the surface of the object at a given time, or None if the
+    /// object can't be sampled.
+    ///
+    /// Used to emit photons from emissive shapes.
+    fn random_surface_point(&self, _time: f64) -> Option<HitRecord> {
+        None
+    }
+
+    /// Returns the surface area of the object
+    fn area(&self) -> f64 {
+        0.0
+    }
+
+    /// Returns the point of the surface with texture coordinates (u, v) at a given time, or None if there is no
+    /// such point or the object can't be searched by texture coordinates.
+    ///
+    /// Used to bake textures and lighting into the texture space of an object.
+    fn point_at_uv(&self, _u: f64, _v: f64, _time: f64) -> Option<HitRecord> {
+        None
+    }
+
+    /// Returns true if point_at_uv finds every point of the surface, and no two points share texture coordinates,
+    /// so the texture space of the object can be baked into
+    fn bakeable(&self) -> bool {
+        false
+    }
 }
 
 pub struct HittableList {
@@ -67,6 +175,8 @@ impl Hittable for HittableList {
             t: t_max,
             front_face: true,
             uv: TextureCoord::new(0.0, 0.0),
+            dpdu: zero_vec(),
+            dpdv: zero_vec(),
         };
         let mut hit_anything = false;
         let mut closest_so_far = t_max;
@@ -81,6 +191,9 @@ impl Hittable for HittableList {
                     temp_rec.normal = rec.normal;
                     temp_rec.p = rec.p;
                     temp_rec.front_face = rec.front_face;
+                    temp_rec.uv = rec.uv;
+                    temp_rec.dpdu = rec.dpdu;
+                    temp_rec.dpdv = rec.dpdv;
                 }
                 None => {}
             }
@@ -93,13 +206,36 @@ impl Hittable for HittableList {
         }
     }
 
-    fn bounding_box(&self) -> AABB {
+    fn bounding_box(&self, time0: f64, time1: f64) -> AABB {
         let mut temp_box = AABB::new(zero_vec(), zero_vec());
 
         for object in &self.objects {
-            temp_box = AABB::surround(&temp_box, &object.bounding_box());
+            temp_box = AABB::surround(&temp_box, &object.bounding_box(time0, time1));
         }
 
         return temp_box;
     }
+
+    /// Samples a point on one of the objects in the list, picking objects proportionally to their area
+    fn random_surface_point(&self, time: f64) -> Option<HitRecord> {
+        let total_area = self.area();
+        if total_area <= 0.0 {
+            return None;
+        }
+
+        let mut target = random_float_1() * total_area;
+        for object in &self.objects {
+            let area = object.area();
+            if target < area {
+                return object.random_surface_point(time);
+            }
+            target -= area;
+        }
+
+        None
+    }
+
+    fn area(&self) -> f64 {
+        self.objects.iter().map(|object| object.area()).sum()
+    }
 }
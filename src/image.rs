@@ -0,0 +1,364 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+
+use crate::color::{srgb_to_linear, ColorSpace, WorkingSpace};
+use crate::vector::{zero_vec, Color};
+
+/// Error returned when an image can't be loaded
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    Png(png::DecodingError),
+    Jpeg(jpeg_decoder::Error),
+    Exr(exr::error::Error),
+    Hdr(String),
+    /// The file is none of the supported formats, or uses a variant of one that isn't supported
+    Unsupported(String),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::Io(error) => write!(f, "{}", error),
+            ImageError::Png(error) => write!(f, "invalid png: {}", error),
+            ImageError::Jpeg(error) => write!(f, "invalid jpeg: {}", error),
+            ImageError::Exr(error) => write!(f, "invalid exr: {}", error),
+            ImageError::Hdr(message) => write!(f, "invalid hdr: {}", message),
+            ImageError::Unsupported(message) => write!(f, "unsupported image: {}", message),
+        }
+    }
+}
+
+impl From<io::Error> for ImageError {
+    fn from(error: io::Error) -> ImageError {
+        ImageError::Io(error)
+    }
+}
+
+impl From<png::DecodingError> for ImageError {
+    fn from(error: png::DecodingError) -> ImageError {
+        ImageError::Png(error)
+    }
+}
+
+impl From<jpeg_decoder::Error> for ImageError {
+    fn from(error: jpeg_decoder::Error) -> ImageError {
+        ImageError::Jpeg(error)
+    }
+}
+
+impl From<exr::error::Error> for ImageError {
+    fn from(error: exr::error::Error) -> ImageError {
+        ImageError::Exr(error)
+    }
+}
+
+/// Receives the rows of an image as they are decoded, so callers can store them in whatever form they need without
+/// a full precision copy of the whole image
+pub trait RowSink {
+    /// Called once before any rows, with the size of the image and whether its colors are still sRGB encoded, as
+    /// they are in 8 and 16 bit files
+    fn begin(&mut self, width: usize, height: usize, srgb: bool);
+    /// Called once for each row, with its index counted from the top. Rows come in order, from the top or from the
+    /// bottom. Colors of integer formats are scaled to [0, 1], and alpha is 1 for formats or files without it.
+    fn row(&mut self, y: usize, pixels: &[Color], alpha: &[f64]);
+}
+
+/// Converts a color from the given color space, overriding the encoding of the file, to a linear color in the
+/// working space. Raw colors are left as they are.
+pub fn to_working_space(
+    color: Color,
+    colorspace: ColorSpace,
+    working_space: WorkingSpace,
+) -> Color {
+    match colorspace {
+        ColorSpace::Srgb => working_space.to_working(Color::new(
+            srgb_to_linear(color.x()),
+            srgb_to_linear(color.y()),
+            srgb_to_linear(color.z()),
+        )),
+        ColorSpace::Linear => working_space.to_working(color),
+        ColorSpace::Raw => color,
+    }
+}
+
+/// Reads a PNG, JPEG, Radiance HDR or OpenEXR image, telling them apart by their contents rather than their
+/// extension, and hands its rows to a sink. Colors are passed on as they are stored, see to_working_space.
+pub fn read_image(path: &str, sink: &mut dyn RowSink) -> Result<(), ImageError> {
+    let mut magic = [0; 4];
+    File::open(path)?.read_exact(&mut magic)?;
+
+    match magic {
+        [0x89, b'P', b'N', b'G'] => read_png(path, sink),
+        [0xff, 0xd8, _, _] => read_jpeg(path, sink),
+        [0x76, 0x2f, 0x31, 0x01] => read_exr(path, sink),
+        [b'#', b'?', _, _] => read_hdr(path, sink),
+        _ => Err(ImageError::Unsupported(format!(
+            "{} is not a png, jpeg, hdr or exr file",
+            path
+        ))),
+    }
+}
+
+/// Reads a png of any color type and bit depth
+fn read_png(path: &str, sink: &mut dyn RowSink) -> Result<(), ImageError> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    // Expands palettes, grey below 8 bits and tRNS transparency into plain grey, rgb and alpha channels
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    let bytes = &buf[..info.buffer_size()];
+
+    // Files are sRGB unless they say they are linear
+    let srgb = match reader.info().source_gamma {
+        Some(gamma) if reader.info().srgb.is_none() => {
+            f64::abs(gamma.into_value() as f64 - 1.0) > 0.01
+        }
+        _ => true,
+    };
+
+    let (color_type, bit_depth) = reader.output_color_type();
+    let channels = color_type.samples();
+    let (sample_size, sample): (usize, fn(&[u8]) -> f64) = match bit_depth {
+        png::BitDepth::Eight => (1, |b| b[0] as f64 / 255.0),
+        png::BitDepth::Sixteen => (2, |b| u16::from_be_bytes([b[0], b[1]]) as f64 / 65535.0),
+        other => {
+            return Err(ImageError::Unsupported(format!(
+                "png bit depth {:?} after expansion",
+                other
+            )))
+        }
+    };
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    sink.begin(width, height, srgb);
+
+    // Rows are decoded one at a time from the stored bytes
+    let mut pixels = Vec::with_capacity(width);
+    let mut alpha = Vec::with_capacity(width);
+    for (y, row) in bytes.chunks(info.line_size).take(height).enumerate() {
+        pixels.clear();
+        alpha.clear();
+        for pixel in row.chunks(channels * sample_size).take(width) {
+            let s = |channel: usize| sample(&pixel[channel * sample_size..]);
+            let (color, a) = match color_type {
+                png::ColorType::Grayscale => (Color::new(s(0), s(0), s(0)), 1.0),
+                png::ColorType::GrayscaleAlpha => (Color::new(s(0), s(0), s(0)), s(1)),
+                png::ColorType::Rgb => (Color::new(s(0), s(1), s(2)), 1.0),
+                png::ColorType::Rgba => (Color::new(s(0), s(1), s(2)), s(3)),
+                png::ColorType::Indexed => {
+                    return Err(ImageError::Unsupported(
+                        "png palette was not expanded".to_string(),
+                    ))
+                }
+            };
+            pixels.push(color);
+            alpha.push(a);
+        }
+        sink.row(y, &pixels, &alpha);
+    }
+
+    Ok(())
+}
+
+fn read_jpeg(path: &str, sink: &mut dyn RowSink) -> Result<(), ImageError> {
+    let mut decoder = jpeg_decoder::Decoder::new(BufReader::new(File::open(path)?));
+    let bytes = decoder.decode()?;
+    let info = decoder.info().unwrap();
+
+    let (sample_size, channels): (usize, usize) = match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => (1, 1),
+        jpeg_decoder::PixelFormat::L16 => (2, 1),
+        jpeg_decoder::PixelFormat::RGB24 => (1, 3),
+        jpeg_decoder::PixelFormat::CMYK32 => {
+            return Err(ImageError::Unsupported("cmyk jpeg".to_string()))
+        }
+    };
+    let sample = |b: &[u8]| match sample_size {
+        1 => b[0] as f64 / 255.0,
+        _ => u16::from_be_bytes([b[0], b[1]]) as f64 / 65535.0,
+    };
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    sink.begin(width, height, true);
+
+    let alpha = vec![1.0; width];
+    let mut pixels = Vec::with_capacity(width);
+    for (y, row) in bytes
+        .chunks(width * channels * sample_size)
+        .take(height)
+        .enumerate()
+    {
+        pixels.clear();
+        pixels.extend(row.chunks(channels * sample_size).map(|pixel| {
+            let s = |channel: usize| sample(&pixel[channel * sample_size..]);
+            if channels == 1 {
+                Color::new(s(0), s(0), s(0))
+            } else {
+                Color::new(s(0), s(1), s(2))
+            }
+        }));
+        sink.row(y, &pixels, &alpha);
+    }
+
+    Ok(())
+}
+
+/// Reads the first rgb(a) layer of an OpenEXR file at its largest resolution
+fn read_exr(path: &str, sink: &mut dyn RowSink) -> Result<(), ImageError> {
+    // Blocks may come in any order, so the pixels are gathered at the precision of the file first
+    let image = exr::prelude::read_first_rgba_layer_from_file(
+        path,
+        |resolution, _| ExrPixels {
+            width: resolution.width(),
+            pixels: vec![(0.0, 0.0, 0.0, 1.0); resolution.width() * resolution.height()],
+        },
+        |image: &mut ExrPixels, position, (r, g, b, a): (f32, f32, f32, f32)| {
+            image.pixels[position.y() * image.width + position.x()] = (r, g, b, a);
+        },
+    )?;
+    let size = image.layer_data.size;
+    let data = image.layer_data.channel_data.pixels;
+
+    sink.begin(size.width(), size.height(), false);
+    let mut pixels = Vec::with_capacity(size.width());
+    let mut alpha = Vec::with_capacity(size.width());
+    for (y, row) in data.pixels.chunks(size.width()).enumerate() {
+        pixels.clear();
+        alpha.clear();
+        for &(r, g, b, a) in row {
+            pixels.push(Color::new(r as f64, g as f64, b as f64));
+            alpha.push(a as f64);
+        }
+        sink.row(y, &pixels, &alpha);
+    }
+
+    Ok(())
+}
+
+/// Pixels of an exr file as they are read, top row first
+struct ExrPixels {
+    width: usize,
+    pixels: Vec<(f32, f32, f32, f32)>,
+}
+
+/// Reads a Radiance rgbe file, flat or run length encoded
+fn read_hdr(path: &str, sink: &mut dyn RowSink) -> Result<(), ImageError> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    // The header is a list of lines ending in a blank one, followed by the resolution
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(ImageError::Hdr("header has no end".to_string()));
+        }
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            break;
+        }
+        if let Some(format) = trimmed.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(ImageError::Unsupported(format!("hdr format {}", format)));
+            }
+        }
+    }
+
+    line.clear();
+    reader.read_line(&mut line)?;
+    let resolution: Vec<&str> = line.split_whitespace().collect();
+    let (width, height, flip) = match resolution.as_slice() {
+        ["-Y", height, "+X", width] => (width, height, false),
+        ["+Y", height, "+X", width] => (width, height, true),
+        _ => {
+            return Err(ImageError::Unsupported(format!(
+                "hdr orientation {}",
+                line.trim()
+            )))
+        }
+    };
+    let parse = |s: &str| {
+        s.parse::<usize>()
+            .map_err(|_| ImageError::Hdr(format!("bad resolution {}", line.trim())))
+    };
+    let width = parse(width)?;
+    let height = parse(height)?;
+
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let mut bytes = data.into_iter();
+    let mut next = || {
+        bytes
+            .next()
+            .ok_or_else(|| ImageError::Hdr("unexpected end of file".to_string()))
+    };
+
+    sink.begin(width, height, false);
+    let alpha = vec![1.0; width];
+    let mut pixels = Vec::with_capacity(width);
+    let mut scanline = vec![[0u8; 4]; width];
+    for row in 0..height {
+        let first = [next()?, next()?, next()?, next()?];
+
+        if first[0] == 2 && first[1] == 2 && first[2] & 0x80 == 0 && (8..0x8000).contains(&width) {
+            // Run length encoded: each of the four components is stored separately
+            if ((first[2] as usize) << 8 | first[3] as usize) != width {
+                return Err(ImageError::Hdr("scanline width mismatch".to_string()));
+            }
+            for component in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = next()? as usize;
+                    if count > 128 {
+                        let count = count - 128;
+                        let value = next()?;
+                        if x + count > width {
+                            return Err(ImageError::Hdr("run overflows scanline".to_string()));
+                        }
+                        for pixel in &mut scanline[x..x + count] {
+                            pixel[component] = value;
+                        }
+                        x += count;
+                    } else {
+                        if count == 0 || x + count > width {
+                            return Err(ImageError::Hdr("bad run length".to_string()));
+                        }
+                        for pixel in &mut scanline[x..x + count] {
+                            pixel[component] = next()?;
+                        }
+                        x += count;
+                    }
+                }
+            }
+        } else {
+            // Flat pixels
+            scanline[0] = first;
+            for pixel in scanline.iter_mut().skip(1) {
+                *pixel = [next()?, next()?, next()?, next()?];
+            }
+        }
+
+        pixels.clear();
+        pixels.extend(scanline.iter().map(rgbe_to_color));
+        // +Y files store the bottom row first
+        let y = if flip { height - 1 - row } else { row };
+        sink.row(y, &pixels, &alpha);
+    }
+
+    Ok(())
+}
+
+/// Decodes a pixel with a shared exponent
+fn rgbe_to_color(rgbe: &[u8; 4]) -> Color {
+    if rgbe[3] == 0 {
+        return zero_vec();
+    }
+    let scale = f64::powi(2.0, rgbe[3] as i32 - 136);
+    Color::new(
+        (rgbe[0] as f64 + 0.5) * scale,
+        (rgbe[1] as f64 + 0.5) * scale,
+        (rgbe[2] as f64 + 0.5) * scale,
+    )
+}
@@ -23,33 +23,54 @@ impl Translate {
 }
 
 impl Hittable for Translate {
-    fn bounding_box(&self) -> AABB {
-        let aabb = self.ptr.bounding_box();
+    fn bounding_box(&self, time0: f64, time1: f64) -> AABB {
+        let aabb = self.ptr.bounding_box(time0, time1);
 
         AABB::new(aabb.min() + self.offset, aabb.max() + self.offset)
     }
 
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let moved = Ray::new(r.origin - self.offset, r.direction);
+        let moved = Ray::new(r.origin - self.offset, r.direction, r.time);
 
         match self.ptr.hit(&moved, t_min, t_max) {
             Some(rec) => {
+                // The child already oriented the normal, and its object space hit must be kept
                 let mut new_rec = rec.clone();
                 new_rec.p += self.offset;
-                new_rec.set_face_normal(&moved, &rec.normal);
 
                 return Some(new_rec);
             }
             None => return None,
         }
     }
+
+    fn random_surface_point(&self, time: f64) -> Option<HitRecord> {
+        let mut rec = self.ptr.random_surface_point(time)?;
+        rec.p += self.offset;
+
+        Some(rec)
+    }
+
+    fn area(&self) -> f64 {
+        self.ptr.area()
+    }
+
+    fn bakeable(&self) -> bool {
+        self.ptr.bakeable()
+    }
+
+    fn point_at_uv(&self, u: f64, v: f64, time: f64) -> Option<HitRecord> {
+        let mut rec = self.ptr.point_at_uv(u, v, time)?;
+        rec.p += self.offset;
+
+        Some(rec)
+    }
 }
 
 pub struct RotateY {
     ptr: Arc<dyn Hittable>,
     sin_theta: f64,
     cos_theta: f64,
-    aabb: AABB,
 }
 
 impl RotateY {
@@ -57,87 +78,270 @@ impl RotateY {
         let radians = degrees_to_radians(angle);
         let sin_theta = f64::sin(radians);
         let cos_theta = f64::cos(radians);
-        let aabb = p.bounding_box();
-
-        let mut min = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
-        let mut max = Vec3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
-
-        for i in 0..2 {
-            for j in 0..2 {
-                for k in 0..2 {
-                    let i = i as f64;
-                    let j = j as f64;
-                    let k = k as f64;
-                    let x = i * aabb.max().x() + (1.0 - i) * aabb.min().x();
-                    let y = j * aabb.max().y() + (1.0 - i) * aabb.min().y();
-                    let z = k * aabb.max().z() + (1.0 - i) * aabb.min().z();
-
-                    let newx = cos_theta * x + sin_theta * z;
-                    let newz = -sin_theta * x + cos_theta * z;
-
-                    let tester = Vec3::new(newx, y, newz);
-
-                    for c in 0..3 {
-                        min[c] = f64::min(min[c], tester[c]);
-                        max[c] = f64::max(min[c], tester[c]);
-                    }
-                }
-            }
-        }
-
-        let aabb = AABB::new(min, max);
 
         RotateY {
             ptr: p,
             sin_theta,
             cos_theta,
-            aabb,
         }
     }
-}
 
-impl Hittable for RotateY {
-    fn bounding_box(&self) -> AABB {
-        self.aabb
+    /// Rotates a direction from object space into world space
+    fn rotate(&self, v: &Vec3) -> Vec3 {
+        rotate_y(v, self.sin_theta, self.cos_theta)
     }
 
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let mut origin = r.origin;
-        let mut direction = r.direction;
+    /// Rotates a direction from world space into object space
+    fn unrotate(&self, v: &Vec3) -> Vec3 {
+        rotate_y(v, -self.sin_theta, self.cos_theta)
+    }
+}
 
-        origin[0] = self.cos_theta * r.origin[0] - self.sin_theta * r.origin[2];
-        origin[2] = self.sin_theta * r.origin[0] + self.cos_theta * r.origin[2];
+/// Rotates a vector around the y axis by the angle with the given sine and cosine
+fn rotate_y(v: &Vec3, sin_theta: f64, cos_theta: f64) -> Vec3 {
+    Vec3::new(
+        cos_theta * v[0] + sin_theta * v[2],
+        v[1],
+        -sin_theta * v[0] + cos_theta * v[2],
+    )
+}
 
-        direction[0] = self.cos_theta * r.direction[0] - self.sin_theta * r.direction[2];
-        direction[2] = self.sin_theta * r.direction[0] + self.cos_theta * r.direction[2];
+/// Returns the bounding box of aabb after rotating it around the y axis
+fn rotate_y_box(aabb: &AABB, sin_theta: f64, cos_theta: f64) -> AABB {
+    let mut min = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+    let mut max = Vec3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
 
-        let rotated_r = Ray::new(origin, direction);
+    for i in 0..2 {
+        for j in 0..2 {
+            for k in 0..2 {
+                let i = i as f64;
+                let j = j as f64;
+                let k = k as f64;
+                let x = i * aabb.max().x() + (1.0 - i) * aabb.min().x();
+                let y = j * aabb.max().y() + (1.0 - j) * aabb.min().y();
+                let z = k * aabb.max().z() + (1.0 - k) * aabb.min().z();
 
-        match self.ptr.hit(&rotated_r, t_min, t_max) {
-            Some(rec) => {
-                let mut p = rec.p;
-                let mut normal = rec.normal;
+                let tester = rotate_y(&Vec3::new(x, y, z), sin_theta, cos_theta);
 
-                p[0] = self.cos_theta * rec.p[0] + self.sin_theta * rec.p[2];
-                p[2] = -self.sin_theta * rec.p[0] + self.cos_theta * rec.p[2];
+                for c in 0..3 {
+                    min[c] = f64::min(min[c], tester[c]);
+                    max[c] = f64::max(max[c], tester[c]);
+                }
+            }
+        }
+    }
 
-                normal[0] = self.cos_theta * rec.normal[0] + self.sin_theta * rec.normal[2];
-                normal[2] = -self.sin_theta * rec.normal[0] + self.cos_theta * rec.normal[2];
+    AABB::new(min, max)
+}
 
-                let mut rec = HitRecord {
-                    p,
-                    normal,
+impl Hittable for RotateY {
+    fn bounding_box(&self, time0: f64, time1: f64) -> AABB {
+        rotate_y_box(
+            &self.ptr.bounding_box(time0, time1),
+            self.sin_theta,
+            self.cos_theta,
+        )
+    }
+
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let rotated_r = Ray::new(
+            self.unrotate(&r.origin),
+            self.unrotate(&r.direction),
+            r.time,
+        );
+
+        match self.ptr.hit(&rotated_r, t_min, t_max) {
+            Some(rec) => {
+                // The child already oriented the normal against the ray, so its front_face still holds
+                Some(HitRecord {
+                    p: self.rotate(&rec.p),
+                    normal: self.rotate(&rec.normal),
                     material: rec.material,
                     t: rec.t,
                     front_face: rec.front_face,
                     uv: rec.uv,
-                };
+                    dpdu: self.rotate(&rec.dpdu),
+                    dpdv: self.rotate(&rec.dpdv),
+                })
+            }
+            None => None,
+        }
+    }
+
+    fn random_surface_point(&self, time: f64) -> Option<HitRecord> {
+        let mut rec = self.ptr.random_surface_point(time)?;
+        rec.p = self.rotate(&rec.p);
+        rec.normal = self.rotate(&rec.normal);
+        rec.dpdu = self.rotate(&rec.dpdu);
+        rec.dpdv = self.rotate(&rec.dpdv);
+
+        Some(rec)
+    }
+
+    fn area(&self) -> f64 {
+        self.ptr.area()
+    }
 
-                rec.set_face_normal(&rotated_r, &normal);
+    fn bakeable(&self) -> bool {
+        self.ptr.bakeable()
+    }
+
+    fn point_at_uv(&self, u: f64, v: f64, time: f64) -> Option<HitRecord> {
+        let mut rec = self.ptr.point_at_uv(u, v, time)?;
+        rec.p = self.rotate(&rec.p);
+        rec.normal = self.rotate(&rec.normal);
+        rec.dpdu = self.rotate(&rec.dpdu);
+        rec.dpdv = self.rotate(&rec.dpdv);
 
-                Some(rec)
+        Some(rec)
+    }
+}
+
+/// A single keyframe of a KeyframedTransform
+#[derive(Clone, Copy)]
+pub struct Keyframe {
+    pub time: f64,
+    pub translate: Vec3,
+    /// Rotation around the y axis in degrees
+    pub rotate_y: f64,
+}
+
+/// Rotates an object around the y axis and then translates it, linearly interpolating between keyframes based on
+/// the ray time. Times before the first or after the last keyframe hold that keyframe.
+pub struct KeyframedTransform {
+    ptr: Arc<dyn Hittable>,
+    keyframes: Vec<Keyframe>,
+}
+
+impl KeyframedTransform {
+    /// Creates a transform from at least one keyframe. Scene parsing rejects empty keyframe lists before they get
+    /// here.
+    pub fn new(ptr: Arc<dyn Hittable>, keyframes: Vec<Keyframe>) -> KeyframedTransform {
+        let mut keyframes = keyframes;
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        KeyframedTransform { ptr, keyframes }
+    }
+
+    /// Returns the translation and rotation (in radians) at a given time
+    fn at(&self, time: f64) -> (Vec3, f64) {
+        let first = &self.keyframes[0];
+        let last = &self.keyframes[self.keyframes.len() - 1];
+        if time <= first.time {
+            return (first.translate, degrees_to_radians(first.rotate_y));
+        }
+        if time >= last.time {
+            return (last.translate, degrees_to_radians(last.rotate_y));
+        }
+
+        let next = self.keyframes.iter().position(|k| k.time > time).unwrap();
+        let k0 = &self.keyframes[next - 1];
+        let k1 = &self.keyframes[next];
+        let f = (time - k0.time) / (k1.time - k0.time);
+
+        (
+            (1.0 - f) * k0.translate + f * k1.translate,
+            degrees_to_radians((1.0 - f) * k0.rotate_y + f * k1.rotate_y),
+        )
+    }
+
+    /// Moves a hit on the child from object space into world space at a given time
+    fn to_world(&self, mut rec: HitRecord, time: f64) -> HitRecord {
+        let (offset, angle) = self.at(time);
+        let sin_theta = f64::sin(angle);
+        let cos_theta = f64::cos(angle);
+
+        rec.p = rotate_y(&rec.p, sin_theta, cos_theta) + offset;
+        rec.normal = rotate_y(&rec.normal, sin_theta, cos_theta);
+        rec.dpdu = rotate_y(&rec.dpdu, sin_theta, cos_theta);
+        rec.dpdv = rotate_y(&rec.dpdv, sin_theta, cos_theta);
+
+        rec
+    }
+}
+
+impl Hittable for KeyframedTransform {
+    fn bounding_box(&self, time0: f64, time1: f64) -> AABB {
+        let aabb = self.ptr.bounding_box(time0, time1);
+
+        // Translation is linear between keyframes, so sampling the interval ends and the keyframes in between is
+        // enough for it. Rotation is subdivided into small steps, and the boxes are padded by the largest distance
+        // a corner can bulge out of the chord between two steps.
+        let max_step = degrees_to_radians(5.0);
+        let mut times = vec![time0];
+        for keyframe in &self.keyframes {
+            if keyframe.time > time0 && keyframe.time < time1 {
+                times.push(keyframe.time);
             }
-            None => None,
         }
+        times.push(time1);
+
+        let mut samples = Vec::new();
+        for pair in times.windows(2) {
+            let angle0 = self.at(pair[0]).1;
+            let angle1 = self.at(pair[1]).1;
+            let steps = f64::ceil(f64::abs(angle1 - angle0) / max_step).max(1.0) as usize;
+            for step in 0..=steps {
+                samples.push(pair[0] + (pair[1] - pair[0]) * step as f64 / steps as f64);
+            }
+        }
+
+        let mut radius: f64 = 0.0;
+        for x in [aabb.min().x(), aabb.max().x()] {
+            for z in [aabb.min().z(), aabb.max().z()] {
+                radius = radius.max(f64::sqrt(x * x + z * z));
+            }
+        }
+        let pad = radius * (1.0 / f64::cos(max_step / 2.0) - 1.0);
+        let pad = Vec3::new(pad, 0.0, pad);
+
+        let mut min = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Vec3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
+        for time in samples {
+            let (offset, angle) = self.at(time);
+            let rotated = rotate_y_box(&aabb, f64::sin(angle), f64::cos(angle));
+            for c in 0..3 {
+                min[c] = f64::min(min[c], rotated.min()[c] + offset[c] - pad[c]);
+                max[c] = f64::max(max[c], rotated.max()[c] + offset[c] + pad[c]);
+            }
+        }
+
+        AABB::new(min, max)
+    }
+
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (offset, angle) = self.at(r.time);
+        let sin_theta = f64::sin(angle);
+        let cos_theta = f64::cos(angle);
+
+        // Move the ray into object space
+        let origin = rotate_y(&(r.origin - offset), -sin_theta, cos_theta);
+        let direction = rotate_y(&r.direction, -sin_theta, cos_theta);
+
+        let rec = self
+            .ptr
+            .hit(&Ray::new(origin, direction, r.time), t_min, t_max)?;
+
+        // The child already oriented the normal against the ray
+        Some(self.to_world(rec, r.time))
+    }
+
+    fn random_surface_point(&self, time: f64) -> Option<HitRecord> {
+        let rec = self.ptr.random_surface_point(time)?;
+        Some(self.to_world(rec, time))
+    }
+
+    /// Rotating and translating don't change the area
+    fn area(&self) -> f64 {
+        self.ptr.area()
+    }
+
+    fn bakeable(&self) -> bool {
+        self.ptr.bakeable()
+    }
+
+    fn point_at_uv(&self, u: f64, v: f64, time: f64) -> Option<HitRecord> {
+        let rec = self.ptr.point_at_uv(u, v, time)?;
+        Some(self.to_world(rec, time))
     }
 }
@@ -13,6 +13,11 @@ pub struct LightDetails {
 /// Abstract Light trait.
 pub trait Light: Sync + Send {
     fn apply(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene) -> LightDetails;
+
+    /// Returns the position and color of the light if photons can be emitted from a single point
+    fn point_emitter(&self) -> Option<(Point3, Color)> {
+        None
+    }
 }
 
 /// List of lights in a scene
@@ -34,25 +39,48 @@ impl LightList {
     pub fn len(&self) -> usize {
         self.lights.len()
     }
-}
 
-impl Light for LightList {
-    /// Calculates light contribution from all lights in the scene
-    fn apply(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene) -> LightDetails {
+    /// Returns the unclamped sum of the contributions of every light
+    pub fn direct(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene) -> Color {
         let mut cont = zero_vec();
 
         for light in &self.lights {
-            cont = cont + light.apply(r_in, rec, scene).contribution;
+            cont += light.apply(r_in, rec, scene).contribution;
         }
 
+        cont
+    }
+
+    /// Returns the light reflected off of a hit towards the start of r_in, evaluating its material's brdf towards
+    /// each light. Like direct, the sum isn't clamped, so estimators that have to agree with photon maps can use it.
+    pub fn reflected(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene) -> Color {
+        let mut reflected = zero_vec();
+
+        for light in &self.lights {
+            let details = light.apply(r_in, rec, scene);
+            let direction = details.position - rec.p;
+            reflected += rec.material.brdf(r_in, rec, &direction) * details.contribution;
+        }
+
+        reflected
+    }
+}
+
+impl Light for LightList {
+    /// Calculates light contribution from all lights in the scene, clamped to [0, 1]
+    fn apply(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene) -> LightDetails {
         LightDetails {
-            contribution: vec_clamp(cont, 0.0, 1.0),
+            contribution: vec_clamp(self.direct(r_in, rec, scene), 0.0, 1.0),
             position: zero_vec(),
         }
     }
 }
 
-/// Point light
+/// Point light.
+///
+/// Point lights don't fall off with distance. A surface facing a light receives an irradiance of
+/// `pi * color * cos(theta)` wherever it is, so a white lambertian surface facing it head on shades to `color`. The
+/// contribution returned by apply is that irradiance divided by pi, ready to be scaled by an albedo.
 pub struct PointLight {
     pub position: Vec3,
     pub color: Color,
@@ -61,7 +89,7 @@ pub struct PointLight {
 impl Light for PointLight {
     /// Returns light contribution based off of lambert's law in the form of a Color
 
-    fn apply(&self, _r_in: &Ray, rec: &HitRecord, scene: &Scene) -> LightDetails {
+    fn apply(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene) -> LightDetails {
         let n = rec.normal;
         let l = unit_vector(self.position - rec.p);
 
@@ -70,6 +98,8 @@ impl Light for PointLight {
             &Ray {
                 origin: rec.p,
                 direction: l,
+                time: r_in.time,
+                wavelength: r_in.wavelength,
             },
             0.001,
             (self.position - rec.p).length(),
@@ -85,4 +115,8 @@ impl Light for PointLight {
             position: self.position,
         };
     }
+
+    fn point_emitter(&self) -> Option<(Point3, Color)> {
+        Some((self.position, self.color))
+    }
 }
@@ -1,35 +1,115 @@
 #![allow(dead_code)]
 mod aabb;
+mod animation;
+mod assets;
 mod background;
+mod bake;
 mod buffer;
 mod bvh;
 mod camera;
+mod color;
+mod cutout;
 mod hit;
+mod image;
 mod instance;
 mod lights;
 mod materials;
+mod microfacet;
+mod mipmap;
+mod noise;
+mod normal_map;
+mod onb;
+mod patterns;
 mod perlin;
+mod photon;
+mod principled;
+mod projection;
 mod ray;
 mod scene;
 mod scenes;
 mod shapes;
+mod spectrum;
+mod subsurface;
 mod texture;
 mod utility;
 mod vector;
 mod volumes;
 
 use std::env;
+use std::path::Path;
+use std::process;
 use std::time::Instant;
 
 use hit::*;
 use ray::Ray;
 use scene::Scene;
 
+/// Parses a frame range of the form "[first]-[last]" (or a single frame)
+fn parse_frame_range(range: &str) -> Result<(i32, i32), String> {
+    let (first, last) = range.split_once('-').unwrap_or((range, range));
+    let parse = |frame: &str| {
+        frame
+            .parse::<i32>()
+            .map_err(|_| format!("\"{}\" is not a frame number", frame))
+    };
+    let (first, last) = (parse(first)?, parse(last)?);
+    if first > last {
+        return Err(format!("{} ends before it starts", range));
+    }
+
+    Ok((first, last))
+}
+
+/// Returns the path of a frame of an animation, numbering the path stills are written to ("image.png" becomes
+/// "image_0001.png")
+fn frame_path(path: &str, frame: i32) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}_{:04}.{}", stem, frame, extension.to_string_lossy()),
+        None => format!("{}_{:04}", stem, frame),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
     let scene_file = &args[1];
+
+    // Render a numbered image per frame with --frames [first]-[last]
+    if let Some(index) = args.iter().position(|arg| arg == "--frames") {
+        let range = args.get(index + 1).map(String::as_str).unwrap_or("");
+        let (first, last) = parse_frame_range(range).unwrap_or_else(|error| {
+            eprintln!(
+                "Usage: {} [scene] --frames [first]-[last]: {}",
+                args[0], error
+            );
+            process::exit(1);
+        });
+        let start = Instant::now();
+        for frame in first..=last {
+            eprintln!("\nFrame {}", frame);
+            let scene = Scene::read_scene_file_at_frame(scene_file, frame as f64);
+            scene.render_to_file(frame_path(scene::OUTPUT_PATH, frame));
+        }
+        let elapsed_time = start.elapsed().as_secs_f64();
+        println!("Render time was {} s", elapsed_time);
+
+        eprintln!("\nDone");
+        return;
+    }
+
     let scene = Scene::read_scene_file(scene_file);
 
+    // Bake into the texture space of an object with --bake, using the scene's bake settings
+    if args.iter().any(|arg| arg == "--bake") {
+        let start = Instant::now();
+        scene.bake();
+        let elapsed_time = start.elapsed().as_secs_f64();
+        println!("Bake time was {} s", elapsed_time);
+        return;
+    }
+
     // Render
     //println!("P3\n{} {}\n255\n", image_width, image_height);
     let start = Instant::now();
@@ -1,8 +1,16 @@
+use std::f64::consts::PI;
 use std::sync::Arc;
 
 use crate::lights::Light;
+use crate::microfacet::TrowbridgeReitz;
+use crate::onb::Onb;
 use crate::scene::Scene;
+use crate::spectrum::{
+    rgb_wavelengths_to_spectrum, SampledSpectrum, SampledWavelengths, DEFAULT_WAVELENGTH,
+    RGB_WAVELENGTHS,
+};
 use crate::texture::{SolidColor, Texture};
+use crate::utility::random_float_1;
 
 use super::hit::*;
 use super::ray::Ray;
@@ -10,6 +18,94 @@ use super::vector::*;
 
 pub trait Material: Sync + Send {
     fn apply(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene, depth: i32) -> Color;
+
+    /// Samples a single scattered ray for integrators that trace paths one bounce at a time (e.g. photon mapping).
+    ///
+    /// Returns None if the material absorbs the path.
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<ScatterRecord> {
+        None
+    }
+
+    /// Samples a scattered ray for materials that trace rays through the scene themselves, such as a random walk
+    /// beneath the surface. Integrators call this rather than scatter.
+    fn scatter_in_scene(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        _scene: &Scene,
+    ) -> Option<ScatterRecord> {
+        self.scatter(r_in, rec)
+    }
+
+    /// Samples a scattered ray for the spectral integrator, along with its throughput at each of the path's
+    /// wavelengths. Defaults to upsampling the rgb attenuation of scatter_in_scene.
+    fn scatter_spectral(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        scene: &Scene,
+        lambdas: &SampledWavelengths,
+    ) -> Option<(ScatterRecord, SampledSpectrum)> {
+        let scattered = self.scatter_in_scene(r_in, rec, scene)?;
+        let attenuation = scene.upsample(scattered.attenuation, lambdas);
+        Some((scattered, attenuation))
+    }
+
+    /// Returns the brdf times pi for light arriving at a hit from direction and leaving back along r_in. Lights
+    /// already fold the 1 / pi of a lambertian surface into their contribution, so this scales them directly.
+    ///
+    /// Only the parts of the material that point lights and photons can reach are included. Materials that only
+    /// scatter specularly return zero.
+    fn brdf(&self, _r_in: &Ray, _rec: &HitRecord, _direction: &Vec3) -> Color {
+        zero_vec()
+    }
+
+    /// Returns the light emitted by the material at a hit
+    fn emitted(&self, _rec: &HitRecord) -> Color {
+        zero_vec()
+    }
+
+    /// Returns true if the direction of a scattered ray depends on its wavelength. Spectral paths keep only their
+    /// hero wavelength after bouncing off of a dispersive material.
+    fn is_dispersive(&self) -> bool {
+        false
+    }
+
+    /// Returns false if the surface is cut out at a hit, letting rays pass through to whatever is behind it
+    fn opaque(&self, _rec: &HitRecord) -> bool {
+        true
+    }
+}
+
+/// A sampled bounce returned by Material::scatter
+pub struct ScatterRecord {
+    /// The scattered ray
+    pub ray: Ray,
+    /// Path throughput of the bounce (brdf * cos / pdf)
+    pub attenuation: Color,
+    /// True if the bounce is specular. SPPM camera paths only follow specular bounces.
+    pub specular: bool,
+}
+
+/// Returns a cosine weighted diffuse bounce off of a surface
+pub fn diffuse_scatter(r_in: &Ray, rec: &HitRecord, albedo: Color) -> ScatterRecord {
+    let mut scatter_direction = rec.normal + random_unit_vector();
+
+    // Catch degenerate scatter direction
+    if scatter_direction.near_zero() {
+        scatter_direction = rec.normal;
+    }
+
+    ScatterRecord {
+        ray: Ray {
+            origin: rec.p,
+            direction: scatter_direction,
+            time: r_in.time,
+            wavelength: r_in.wavelength,
+        },
+        attenuation: albedo,
+        specular: false,
+    }
 }
 
 pub struct Metal {
@@ -39,12 +135,193 @@ impl Material for Metal {
         let scattered = Ray {
             origin: rec.p,
             direction: reflected + self.fuzz * random_in_unit_sphere(),
+            time: r_in.time,
+            wavelength: r_in.wavelength,
         };
 
         return self.albedo.value(&rec.uv, &rec.p) * scene.ray_color(&scattered, depth);
     }
+
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let reflected = reflect(&unit_vector(r_in.direction), &rec.normal);
+        let direction = reflected + self.fuzz * random_in_unit_sphere();
+
+        // Fuzzed reflections below the surface are absorbed
+        if dot(&direction, &rec.normal) <= 0.0 {
+            return None;
+        }
+
+        Some(ScatterRecord {
+            ray: Ray {
+                origin: rec.p,
+                direction,
+                time: r_in.time,
+                wavelength: r_in.wavelength,
+            },
+            attenuation: self.albedo.value(&rec.uv, &rec.p),
+            specular: true,
+        })
+    }
 }
 
+/// Physically based metal using a GGX microfacet distribution and the conductor Fresnel equations
+pub struct Conductor {
+    /// Real part of the index of refraction per channel
+    pub eta: Color,
+    /// Imaginary part (absorption coefficient) of the index of refraction per channel
+    pub k: Color,
+    distribution: TrowbridgeReitz,
+}
+
+impl Conductor {
+    /// Creates a conductor from a complex ior and roughness along the two surface tangents
+    pub fn new(eta: Color, k: Color, roughness_u: f64, roughness_v: f64) -> Conductor {
+        Conductor {
+            eta,
+            k,
+            distribution: TrowbridgeReitz::new(roughness_u, roughness_v),
+        }
+    }
+
+    /// Returns the complex ior (eta, k) of a named metal at the red, green and blue wavelengths, or None if the
+    /// name is unknown.
+    ///
+    /// Known presets are gold, silver, copper, aluminium and chrome.
+    pub fn preset(name: &str) -> Option<(Color, Color)> {
+        match name {
+            "gold" => Some((
+                Color::new(0.143, 0.374, 1.442),
+                Color::new(3.983, 2.385, 1.603),
+            )),
+            "silver" => Some((
+                Color::new(0.155, 0.117, 0.138),
+                Color::new(4.828, 3.122, 2.147),
+            )),
+            "copper" => Some((
+                Color::new(0.200, 0.924, 1.102),
+                Color::new(3.912, 2.452, 2.142),
+            )),
+            "aluminium" => Some((
+                Color::new(1.657, 0.880, 0.521),
+                Color::new(9.224, 6.270, 4.837),
+            )),
+            "chrome" => Some((
+                Color::new(3.107, 3.181, 2.323),
+                Color::new(3.331, 3.329, 3.135),
+            )),
+            _ => None,
+        }
+    }
+
+    /// Samples a visible microfacet and reflects off of it. Returns the reflected ray, the cosine between the
+    /// incoming direction and the microfacet, and the shadowing weight of the bounce.
+    ///
+    /// With visible normal sampling the pdf cancels D and leaves F * G2 / G1 as the weight, where only the Fresnel
+    /// term depends on the wavelength.
+    fn sample(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, f64, f64)> {
+        // Roughness along u follows dpdu, so anisotropic highlights line up with the surface's texture coordinates
+        let onb = Onb::build_from_w_and_tangent(&rec.normal, &rec.dpdu);
+        let wo = onb.to_local(&-unit_vector(r_in.direction));
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let wm = self
+            .distribution
+            .sample_visible(&wo, random_float_1(), random_float_1());
+        let wi = reflect(&-wo, &wm);
+        if wi.z() <= 0.0 {
+            return None;
+        }
+
+        let ray = Ray {
+            origin: rec.p,
+            direction: onb.local(&wi),
+            time: r_in.time,
+            wavelength: r_in.wavelength,
+        };
+        let shadowing = self.distribution.g(&wo, &wi) / self.distribution.g1(&wo);
+        Some((ray, dot(&wo, &wm), shadowing))
+    }
+
+    /// Returns the Fresnel reflectance per channel
+    fn fresnel(&self, cos_theta: f64) -> Color {
+        Color::new(
+            fresnel_conductor(cos_theta, self.eta.x(), self.k.x()),
+            fresnel_conductor(cos_theta, self.eta.y(), self.k.y()),
+            fresnel_conductor(cos_theta, self.eta.z(), self.k.z()),
+        )
+    }
+}
+
+impl Material for Conductor {
+    fn apply(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene, depth: i32) -> Color {
+        match self.scatter(r_in, rec) {
+            Some(scattered) => scattered.attenuation * scene.ray_color(&scattered.ray, depth),
+            None => zero_vec(),
+        }
+    }
+
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let (ray, cos_theta, shadowing) = self.sample(r_in, rec)?;
+        Some(ScatterRecord {
+            ray,
+            attenuation: self.fresnel(cos_theta) * shadowing,
+            specular: true,
+        })
+    }
+
+    /// The complex ior is interpolated between the rgb wavelengths it is given at, rather than upsampling the rgb
+    /// reflectance
+    fn scatter_spectral(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        _scene: &Scene,
+        lambdas: &SampledWavelengths,
+    ) -> Option<(ScatterRecord, SampledSpectrum)> {
+        let (ray, cos_theta, shadowing) = self.sample(r_in, rec)?;
+        let eta = rgb_wavelengths_to_spectrum(&self.eta, lambdas);
+        let k = rgb_wavelengths_to_spectrum(&self.k, lambdas);
+        let mut attenuation = SampledSpectrum::new(0.0);
+        for i in 0..attenuation.e.len() {
+            attenuation.e[i] = fresnel_conductor(cos_theta, eta.e[i], k.e[i]) * shadowing;
+        }
+
+        let scattered = ScatterRecord {
+            ray,
+            attenuation: self.fresnel(cos_theta) * shadowing,
+            specular: true,
+        };
+        Some((scattered, attenuation))
+    }
+}
+
+/// Fresnel reflectance of a conductor with complex ior eta + ik, for unpolarized light
+fn fresnel_conductor(cos_theta: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = f64::clamp(cos_theta * cos_theta, 0.0, 1.0);
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = f64::sqrt(t0 * t0 + 4.0 * eta2 * k2);
+    let t1 = a2_plus_b2 + cos2;
+    let a = f64::sqrt(f64::max(0.0, 0.5 * (a2_plus_b2 + t0)));
+    let t2 = 2.0 * cos_theta * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rp + rs)
+}
+
+/// Lambertian surface lit by both the scene lights and bounced light.
+///
+/// `absorbance` is the fraction of incoming light absorbed at each bounce, so a surface reflects
+/// `albedo * (1 - absorbance)` of the light reaching it and never more than it receives.
 #[derive(Clone)]
 pub struct Diffuse {
     albedo: Arc<dyn Texture>,
@@ -65,95 +342,598 @@ impl Diffuse {
             absorbance,
         }
     }
+
+    /// Returns the fraction of light reflected at a hit
+    fn reflectance(&self, rec: &HitRecord) -> Color {
+        (1.0 - self.absorbance) * self.albedo.value(&rec.uv, &rec.p)
+    }
 }
 
 impl Material for Diffuse {
     fn apply(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene, depth: i32) -> Color {
-        // lambertian light contribution
-        let cr =
-            self.albedo.value(&rec.uv, &rec.p) * scene.lights.apply(r_in, rec, scene).contribution;
+        // Point lights can never be hit by a bounced ray, so direct and bounced light are summed rather than
+        // mixed. Both are scaled by the same reflectance to conserve energy.
+        let direct = scene.lights.apply(r_in, rec, scene).contribution;
+        let scattered = diffuse_scatter(r_in, rec, self.reflectance(rec));
+
+        return scattered.attenuation * (direct + scene.ray_color(&scattered.ray, depth));
+    }
 
-        let mut scatter_direction = rec.normal + random_unit_vector();
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        Some(diffuse_scatter(r_in, rec, self.reflectance(rec)))
+    }
 
-        // Catch degenerate scatter direction
-        if scatter_direction.near_zero() {
-            scatter_direction = rec.normal;
+    fn brdf(&self, _r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        if dot(direction, &rec.normal) <= 0.0 {
+            return zero_vec();
         }
+        self.reflectance(rec)
+    }
+}
 
-        let scattered = Ray {
-            origin: rec.p,
-            direction: scatter_direction,
+/// Rough diffuse surface using the qualitative Oren-Nayar model. Rough surfaces such as clay or the moon
+/// scatter more light back towards the light source than a lambertian surface, flattening their shading.
+pub struct OrenNayar {
+    albedo: Arc<dyn Texture>,
+    a: f64,
+    b: f64,
+}
+
+impl OrenNayar {
+    /// Creates an Oren-Nayar surface. sigma is the standard deviation of the microfacet slope angle in degrees;
+    /// a sigma of 0 is lambertian.
+    pub fn new(albedo: Arc<dyn Texture>, sigma: f64) -> OrenNayar {
+        let sigma2 = f64::powi(sigma.to_radians(), 2);
+        OrenNayar {
+            albedo: Arc::clone(&albedo),
+            a: 1.0 - sigma2 / (2.0 * (sigma2 + 0.33)),
+            b: 0.45 * sigma2 / (sigma2 + 0.09),
+        }
+    }
+
+    /// Returns the factor scaling lambertian reflection between two directions in shading space
+    fn factor(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        let sin_theta_i = f64::sqrt(f64::max(0.0, 1.0 - wi.z() * wi.z()));
+        let sin_theta_o = f64::sqrt(f64::max(0.0, 1.0 - wo.z() * wo.z()));
+
+        // Cosine of the azimuthal angle between the directions
+        let max_cos = if sin_theta_i > 1e-4 && sin_theta_o > 1e-4 {
+            f64::max(
+                0.0,
+                (wi.x() * wo.x() + wi.y() * wo.y()) / (sin_theta_i * sin_theta_o),
+            )
+        } else {
+            0.0
         };
 
-        let scattered_color = scene.ray_color(&scattered, depth);
+        let (sin_alpha, tan_beta) = if wi.z().abs() > wo.z().abs() {
+            (sin_theta_o, sin_theta_i / wi.z().abs())
+        } else {
+            (sin_theta_i, sin_theta_o / wo.z().abs())
+        };
 
-        return cr * self.absorbance
-            + (1.0 - self.absorbance) * self.albedo.value(&rec.uv, &rec.p) * scattered_color;
+        self.a + self.b * max_cos * sin_alpha * tan_beta
+    }
+}
+
+impl Material for OrenNayar {
+    fn apply(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene, depth: i32) -> Color {
+        // Unlike a lambertian surface the response depends on each light's direction
+        let direct = scene.lights.reflected(r_in, rec, scene);
+
+        let bounced = match self.scatter(r_in, rec) {
+            Some(scattered) => scattered.attenuation * scene.ray_color(&scattered.ray, depth),
+            None => zero_vec(),
+        };
+
+        direct + bounced
+    }
+
+    fn brdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        let onb = Onb::build_from_w(&rec.normal);
+        let wo = onb.to_local(&-unit_vector(r_in.direction));
+        let wi = onb.to_local(&unit_vector(*direction));
+        if wi.z() <= 0.0 {
+            return zero_vec();
+        }
+
+        self.factor(&wo, &wi) * self.albedo.value(&rec.uv, &rec.p)
+    }
+
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let onb = Onb::build_from_w(&rec.normal);
+        let wo = onb.to_local(&-unit_vector(r_in.direction));
+        let wi = random_cosine_direction();
+
+        // Cosine sampling cancels the lambertian term, leaving the Oren-Nayar factor as the weight
+        Some(ScatterRecord {
+            ray: Ray {
+                origin: rec.p,
+                direction: onb.local(&wi),
+                time: r_in.time,
+                wavelength: r_in.wavelength,
+            },
+            attenuation: self.factor(&wo, &wi) * self.albedo.value(&rec.uv, &rec.p),
+            specular: false,
+        })
+    }
+}
+
+/// Index of refraction of a dielectric
+pub enum Ior {
+    /// The same index at every wavelength
+    Constant(f64),
+    /// Measured (wavelength in nm, index) pairs sorted by wavelength, linearly interpolated in between and held
+    /// outside of the measured range. Needs at least one pair; scene parsing rejects empty or unsorted tables.
+    Tabulated(Vec<(f64, f64)>),
+    /// Cauchy's equation n = a + b / λ² + c / λ⁴ with λ in µm
+    Cauchy { a: f64, b: f64, c: f64 },
+    /// Sellmeier equation n² = 1 + Σ b λ² / (λ² - c) with λ in µm, as a list of (b, c) terms
+    Sellmeier(Vec<(f64, f64)>),
+}
+
+impl Ior {
+    /// Returns the Sellmeier coefficients of a named material, or None if the name is unknown.
+    ///
+    /// Known presets are bk7, fused_silica, sf11 (dense flint glass), diamond and water.
+    pub fn preset(name: &str) -> Option<Ior> {
+        let terms = match name {
+            "bk7" => vec![
+                (1.03961212, 0.00600069867),
+                (0.231792344, 0.0200179144),
+                (1.01046945, 103.560653),
+            ],
+            "fused_silica" => vec![
+                (0.6961663, 0.0046791483),
+                (0.4079426, 0.0135120631),
+                (0.8974794, 97.9340025),
+            ],
+            "sf11" => vec![
+                (1.73759695, 0.013188707),
+                (0.313747346, 0.0623068142),
+                (1.89878101, 155.23629),
+            ],
+            "diamond" => vec![(0.3306, 0.030625), (4.3356, 0.011236)],
+            // Daimon and Masumura 2007, at 20°C
+            "water" => vec![
+                (0.5684027565, 0.005101829712),
+                (0.1726177391, 0.01821153936),
+                (0.02086189578, 0.02620722293),
+                (0.1130748688, 10.69792721),
+            ],
+            _ => return None,
+        };
+
+        Some(Ior::Sellmeier(terms))
+    }
+
+    /// Returns the index of refraction at a wavelength in nm
+    pub fn at(&self, wavelength: f64) -> f64 {
+        match self {
+            Ior::Constant(ir) => *ir,
+            Ior::Tabulated(table) => {
+                let last = table.len() - 1;
+                if wavelength <= table[0].0 {
+                    return table[0].1;
+                }
+                if wavelength >= table[last].0 {
+                    return table[last].1;
+                }
+
+                let next = table.iter().position(|entry| entry.0 > wavelength).unwrap();
+                let (l0, n0) = table[next - 1];
+                let (l1, n1) = table[next];
+                let f = (wavelength - l0) / (l1 - l0);
+                (1.0 - f) * n0 + f * n1
+            }
+            Ior::Cauchy { a, b, c } => {
+                let l2 = (wavelength / 1000.0) * (wavelength / 1000.0);
+                a + b / l2 + c / (l2 * l2)
+            }
+            Ior::Sellmeier(terms) => {
+                let l2 = (wavelength / 1000.0) * (wavelength / 1000.0);
+                let n2 = 1.0 + terms.iter().map(|(b, c)| b * l2 / (l2 - c)).sum::<f64>();
+                f64::sqrt(n2)
+            }
+        }
+    }
+
+    /// Returns true if the index changes with wavelength
+    pub fn is_dispersive(&self) -> bool {
+        match self {
+            Ior::Constant(_) => false,
+            Ior::Tabulated(table) => table.iter().any(|entry| entry.1 != table[0].1),
+            Ior::Cauchy { b, c, .. } => *b != 0.0 || *c != 0.0,
+            Ior::Sellmeier(terms) => !terms.is_empty(),
+        }
+    }
+
+    /// Returns the wavelength a bounce off of this ior continues with and the throughput for it.
+    ///
+    /// Spectral rays keep their own wavelength. Rgb rays hitting a dispersive ior pick one of the color channels at
+    /// random and carry only that channel's wavelength from then on, so later dispersive bounces refract the same way.
+    pub fn sample_wavelength(&self, r_in: &Ray) -> (Option<f64>, Color) {
+        if r_in.wavelength.is_some() || !self.is_dispersive() {
+            return (r_in.wavelength, Color::new(1.0, 1.0, 1.0));
+        }
+
+        let channel = usize::min((random_float_1() * 3.0) as usize, 2);
+        let mut weight = zero_vec();
+        weight[channel] = 3.0;
+
+        (Some(RGB_WAVELENGTHS[channel]), weight)
     }
 }
 
 /// Dielectric material
 pub struct Dielectric {
     /// Index of refraction
-    pub ir: f64,
+    pub ior: Ior,
 }
 
 impl Dielectric {
     pub fn new(ir: f64) -> Dielectric {
-        Dielectric { ir }
+        Dielectric {
+            ior: Ior::Constant(ir),
+        }
+    }
+
+    pub fn new_from_ior(ior: Ior) -> Dielectric {
+        Dielectric { ior }
     }
 }
 
 impl Material for Dielectric {
     fn apply(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene, depth: i32) -> Color {
+        // Follow a single stochastic branch, tracing both reflection and refraction is exponential in depth
+        match self.scatter(r_in, rec) {
+            Some(scattered) => scattered.attenuation * scene.ray_color(&scattered.ray, depth),
+            None => zero_vec(),
+        }
+    }
+
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         // Let refraction ratio equal 1/ir if outside the object, or ir if inside the object
-        let refraction_ratio;
-        if rec.front_face {
-            refraction_ratio = 1.0 / self.ir;
-        } else {
-            refraction_ratio = self.ir;
-        };
+        let (wavelength, weight) = self.ior.sample_wavelength(r_in);
+        let ir = self.ior.at(wavelength.unwrap_or(DEFAULT_WAVELENGTH));
+        let refraction_ratio = if rec.front_face { 1.0 / ir } else { ir };
 
-        // Get the unit vector of the ray direction
         let unit_direction = unit_vector(r_in.direction);
-        // Get cos of the angle between -unit_direction and the normal vector (set to 1.0 if over 1.0 somehow)
         let cos_theta = f64::min(dot(&(-unit_direction), &rec.normal), 1.0);
-        // Get the sin of the angle
         let sin_theta = f64::sqrt(1.0 - cos_theta * cos_theta);
 
-        // If refraction_ratio * sin_theta is greater than 1, refraction is not possible
+        // Pick reflection or refraction with probability equal to the reflectance
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
+        let direction =
+            if cannot_refract || reflectance(cos_theta, refraction_ratio) > random_float_1() {
+                reflect(&unit_direction, &rec.normal)
+            } else {
+                refract(&unit_direction, &rec.normal, refraction_ratio)
+            };
 
-        // Full reflection
-        if cannot_refract {
-            let direction = reflect(&unit_direction, &rec.normal);
-            let ray = Ray {
+        Some(ScatterRecord {
+            ray: Ray {
                 origin: rec.p,
                 direction,
-            };
-            return scene.ray_color(&ray, depth);
-        } else {
-            // get reflectance ratio based
-            let reflectance = reflectance(cos_theta, refraction_ratio);
-            // reflect color
-            let reflect_direction = reflect(&unit_direction, &rec.normal);
-            let reflect_ray = Ray {
+                time: r_in.time,
+                wavelength,
+            },
+            attenuation: weight,
+            specular: true,
+        })
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.ior.is_dispersive()
+    }
+}
+
+/// Frosted glass, using GGX microfacet reflection and transmission (Walter et al. 2007)
+pub struct RoughDielectric {
+    pub ior: Ior,
+    /// Perceptual roughness in [0, 1], read from the first channel
+    pub roughness: Arc<dyn Texture>,
+}
+
+impl RoughDielectric {
+    pub fn new(ior: Ior, roughness: Arc<dyn Texture>) -> RoughDielectric {
+        RoughDielectric { ior, roughness }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn apply(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene, depth: i32) -> Color {
+        match self.scatter(r_in, rec) {
+            Some(scattered) => scattered.attenuation * scene.ray_color(&scattered.ray, depth),
+            None => zero_vec(),
+        }
+    }
+
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let (wavelength, weight) = self.ior.sample_wavelength(r_in);
+        let ir = self.ior.at(wavelength.unwrap_or(DEFAULT_WAVELENGTH));
+        // Ratio of the index on the far side of the surface to the index on the side of the ray
+        let eta = if rec.front_face { ir } else { 1.0 / ir };
+
+        let roughness = self.roughness.value(&rec.uv, &rec.p).x();
+        let distribution = TrowbridgeReitz::new(roughness, roughness);
+
+        let onb = Onb::build_from_w(&rec.normal);
+        let wo = onb.to_local(&-unit_vector(r_in.direction));
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let (wi, interface_weight) = sample_rough_interface(&distribution, &wo, eta)?;
+
+        Some(ScatterRecord {
+            ray: Ray {
                 origin: rec.p,
-                direction: reflect_direction,
+                direction: onb.local(&wi),
+                time: r_in.time,
+                wavelength,
+            },
+            attenuation: weight * interface_weight,
+            specular: true,
+        })
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.ior.is_dispersive()
+    }
+}
+
+/// Thin dielectric coat (varnish, lacquer, car paint clear coat) over any other material.
+///
+/// Light is followed through the layers with a random walk: it reflects off of or refracts into the coat, is
+/// absorbed on its way through, scatters off of the base and then either leaves the coat or reflects back down off
+/// of its underside. No energy is lost between the layers other than through absorption.
+pub struct Coated {
+    pub base: Arc<dyn Material>,
+    /// Perceptual roughness of the coat surface, read from the first channel
+    pub roughness: Arc<dyn Texture>,
+    pub ior: f64,
+    pub thickness: f64,
+    /// Color of light after passing straight through a coat of thickness 1
+    pub tint: Arc<dyn Texture>,
+}
+
+impl Coated {
+    /// Maximum number of bounces inside the coat before a path is considered absorbed
+    const MAX_BOUNCES: usize = 32;
+
+    pub fn new(
+        base: Arc<dyn Material>,
+        roughness: Arc<dyn Texture>,
+        ior: f64,
+        thickness: f64,
+        tint: Arc<dyn Texture>,
+    ) -> Coated {
+        Coated {
+            base,
+            roughness,
+            ior,
+            thickness,
+            tint,
+        }
+    }
+
+    /// Returns the light reflected off of the base towards wo for light arriving from wi, as a factor of a light's
+    /// contribution, with both directions in the local frame above the coat.
+    ///
+    /// Light refracts through a smooth coat on the way in and out, and is absorbed along both refracted paths. The
+    /// base's brdf is evaluated between the two refracted directions, and light bouncing between it and the
+    /// underside of the coat adds up as a geometric series, using the base's brdf towards the normal as its albedo.
+    /// Specular bases and the coat's own reflection can't be reached by point lights.
+    fn eval_base(&self, r_in: &Ray, rec: &HitRecord, onb: &Onb, wo: &Vec3, wi: &Vec3) -> Color {
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return zero_vec();
+        }
+
+        // Directions the view ray continues in and the light arrives from below the coat
+        let refracted_cos = |cos: f64| f64::sqrt(1.0 - (1.0 - cos * cos) / (self.ior * self.ior));
+        let cos_o = refracted_cos(wo.z());
+        let cos_i = refracted_cos(wi.z());
+        let below_o = Vec3::new(-wo.x() / self.ior, -wo.y() / self.ior, -cos_o);
+        let below_i = Vec3::new(wi.x() / self.ior, wi.y() / self.ior, cos_i);
+        let incoming = Ray {
+            origin: rec.p,
+            direction: onb.local(&below_o),
+            time: r_in.time,
+            wavelength: r_in.wavelength,
+        };
+        let reflected = self.base.brdf(&incoming, rec, &onb.local(&below_i));
+        let albedo = self.base.brdf(&incoming, rec, &rec.normal);
+
+        let tint = self.tint.value(&rec.uv, &rec.p);
+        let entering = 1.0 - fresnel_dielectric(wi.z(), self.ior);
+        let leaving = 1.0 - fresnel_dielectric(wo.z(), self.ior);
+        let absorbed = transmittance(&tint, self.thickness / cos_i)
+            * transmittance(&tint, self.thickness / cos_o);
+
+        // Fraction of diffuse light below the coat that reflects back down off of its underside (Egan and
+        // Hilgeman's fit)
+        let eta = self.ior;
+        let internal = -1.440 / (eta * eta) + 0.710 / eta + 0.668 + 0.0636 * eta;
+        // Diffuse light crosses the coat along twice its thickness on average each way
+        let round_trip = albedo * transmittance(&tint, 4.0 * self.thickness) * internal;
+        let bounces = Color::new(
+            1.0 / (1.0 - f64::min(round_trip.x(), 0.999)),
+            1.0 / (1.0 - f64::min(round_trip.y(), 0.999)),
+            1.0 / (1.0 - f64::min(round_trip.z(), 0.999)),
+        );
+
+        // Radiance spreads out over a wider solid angle as it leaves the denser coat
+        entering * leaving / (eta * eta) * absorbed * reflected * bounces
+    }
+
+    /// Walks a path through the coat and the base. With the scene the base can trace rays itself, and the path
+    /// leaves the coat above wherever the base sent it out from.
+    fn walk(&self, r_in: &Ray, rec: &HitRecord, scene: Option<&Scene>) -> Option<ScatterRecord> {
+        let roughness = self.roughness.value(&rec.uv, &rec.p).x();
+        let distribution = TrowbridgeReitz::new(roughness, roughness);
+        let tint = self.tint.value(&rec.uv, &rec.p);
+
+        let onb = Onb::build_from_w(&rec.normal);
+        let wo = onb.to_local(&-unit_vector(r_in.direction));
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let (mut w, weight) = sample_rough_interface(&distribution, &wo, self.ior)?;
+        let mut attenuation = Color::new(weight, weight, weight);
+        let mut specular = true;
+        let mut base_rec = rec.clone();
+
+        // Walk between the base and the underside of the coat until the path leaves the top of the coat
+        for _ in 0..Coated::MAX_BOUNCES {
+            if w.z() > 0.0 {
+                return Some(ScatterRecord {
+                    ray: Ray {
+                        origin: base_rec.p,
+                        direction: onb.local(&w),
+                        time: r_in.time,
+                        wavelength: r_in.wavelength,
+                    },
+                    attenuation,
+                    specular,
+                });
+            }
+
+            // Absorption on the way down
+            attenuation = attenuation * transmittance(&tint, self.thickness / f64::abs(w.z()));
+
+            let incoming = Ray {
+                origin: base_rec.p,
+                direction: onb.local(&w),
+                time: r_in.time,
+                wavelength: r_in.wavelength,
             };
-            let reflect_color = scene.ray_color(&reflect_ray, depth);
-            // refract color
-            let refract_direction = refract(&unit_direction, &rec.normal, refraction_ratio);
-            let refract_ray = Ray {
-                origin: rec.p,
-                direction: refract_direction,
+            let scattered = match scene {
+                Some(scene) => self.base.scatter_in_scene(&incoming, &base_rec, scene)?,
+                None => self.base.scatter(&incoming, &base_rec)?,
             };
-            let refract_color = scene.ray_color(&refract_ray, depth);
+            attenuation = attenuation * scattered.attenuation;
+            specular = specular && scattered.specular;
+
+            // The base transmitted the path into the object below
+            let base_w = onb.to_local(&scattered.ray.direction);
+            if base_w.z() <= 0.0 {
+                return Some(ScatterRecord {
+                    ray: scattered.ray,
+                    attenuation,
+                    specular,
+                });
+            }
+            base_rec.p = scattered.ray.origin;
+
+            // Absorption on the way up
+            let base_w = unit_vector(base_w);
+            attenuation = attenuation * transmittance(&tint, self.thickness / base_w.z());
 
-            // return combination of reflection and refraction
-            return reflect_color * reflectance + refract_color * (1.0 - reflectance);
+            // Hit the underside of the coat. Mirror the frame so the interface faces the path.
+            let flipped = Vec3::new(-base_w.x(), -base_w.y(), base_w.z());
+            let (wi, weight) = sample_rough_interface(&distribution, &flipped, 1.0 / self.ior)?;
+            attenuation *= weight;
+            w = Vec3::new(wi.x(), wi.y(), -wi.z());
         }
+
+        None
+    }
+}
+
+impl Material for Coated {
+    fn apply(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene, depth: i32) -> Color {
+        // Unlike the random walk, point lights are evaluated towards each light
+        let direct = scene.lights.reflected(r_in, rec, scene);
+
+        let bounced = match self.scatter_in_scene(r_in, rec, scene) {
+            Some(scattered) => scattered.attenuation * scene.ray_color(&scattered.ray, depth),
+            None => zero_vec(),
+        };
+
+        direct + bounced
+    }
+
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.walk(r_in, rec, None)
+    }
+
+    fn scatter_in_scene(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        scene: &Scene,
+    ) -> Option<ScatterRecord> {
+        self.walk(r_in, rec, Some(scene))
+    }
+
+    fn brdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        let onb = Onb::build_from_w(&rec.normal);
+        let wo = onb.to_local(&-unit_vector(r_in.direction));
+        let wi = onb.to_local(&unit_vector(*direction));
+        self.eval_base(r_in, rec, &onb, &wo, &wi)
+    }
+
+    fn opaque(&self, rec: &HitRecord) -> bool {
+        self.base.opaque(rec)
+    }
+}
+
+/// Fraction of light left after passing through a medium of the given tint over a distance
+fn transmittance(tint: &Color, distance: f64) -> Color {
+    Color::new(
+        f64::powf(tint.x(), distance),
+        f64::powf(tint.y(), distance),
+        f64::powf(tint.z(), distance),
+    )
+}
+
+/// Samples a bounce off of a rough dielectric interface, in a local frame where z is the normal on the side of wo.
+/// eta is the ratio of the index below the interface to the index above it.
+///
+/// A visible microfacet is sampled, and the bounce reflects or refracts through it with probability equal to its
+/// Fresnel reflectance. The Fresnel term and the pdf cancel out, leaving G2 / G1 as the weight for both branches.
+/// Reflections come back with a positive z and refractions with a negative z. Returns None if the sampled direction
+/// ends up on the wrong side of the macro surface.
+fn sample_rough_interface(
+    distribution: &TrowbridgeReitz,
+    wo: &Vec3,
+    eta: f64,
+) -> Option<(Vec3, f64)> {
+    let wm = distribution.sample_visible(wo, random_float_1(), random_float_1());
+    let cos_i = dot(wo, &wm);
+    let wi = if fresnel_dielectric(cos_i, eta) > random_float_1() {
+        let wi = reflect(&-*wo, &wm);
+        if wi.z() <= 0.0 {
+            return None;
+        }
+        wi
+    } else {
+        let cos_t = f64::sqrt(1.0 - (1.0 - cos_i * cos_i) / (eta * eta));
+        let wi = -*wo / eta + (cos_i / eta - cos_t) * wm;
+        if wi.z() >= 0.0 {
+            return None;
+        }
+        wi
+    };
+
+    Some((wi, distribution.g(wo, &wi) / distribution.g1(wo)))
+}
+
+/// Fresnel reflectance of a dielectric interface for unpolarized light. eta is the ratio of the index on the far
+/// side to the index on the incident side.
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        // Total internal reflection
+        return 1.0;
     }
+
+    let cos_t = f64::sqrt(1.0 - sin2_t);
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    (rs * rs + rp * rp) / 2.0
 }
 
 fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
@@ -178,39 +958,170 @@ impl Material for Lambertian {
         let cr = self.albedo * scene.lights.apply(r_in, rec, scene).contribution;
         vec_clamp(cr, 0.0, 1.0)
     }
+
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        Some(diffuse_scatter(r_in, rec, self.albedo))
+    }
+
+    fn brdf(&self, _r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        if dot(direction, &rec.normal) <= 0.0 {
+            return zero_vec();
+        }
+        self.albedo
+    }
 }
 
+/// Blinn-Phong material.
+///
+/// By default this is the energy-normalized Blinn-Phong BRDF `diffuse / pi + specular * (n + 8) / (8 pi) *
+/// cos(theta_h)^n`, importance sampled and lit by both the scene lights and bounced light. Colors are scaled down
+/// if `diffuse + specular` would reflect more light than arrives. Legacy materials keep the old shading that only
+/// sums direct light and clamps it.
 pub struct BlinnPhong {
     diffuse: Color,
     specular: Color,
     phong_exp: f64,
+    legacy: bool,
 }
 
 impl BlinnPhong {
     pub fn new(diffuse: Color, specular: Color, phong_exp: f64) -> BlinnPhong {
+        // Keep the sum of the two lobes' albedos at or below one in every channel
+        let max_albedo = (0..3).map(|i| diffuse[i] + specular[i]).fold(1.0, f64::max);
+
+        BlinnPhong {
+            diffuse: diffuse / max_albedo,
+            specular: specular / max_albedo,
+            phong_exp,
+            legacy: false,
+        }
+    }
+
+    /// Creates a material with the original direct-light-only shading, for scenes made before the normalized model
+    pub fn new_legacy(diffuse: Color, specular: Color, phong_exp: f64) -> BlinnPhong {
         BlinnPhong {
             diffuse,
             specular,
             phong_exp,
+            legacy: true,
+        }
+    }
+
+    /// Returns the brdf times pi for a pair of directions in shading space. Lights already fold the 1 / pi of a
+    /// lambertian surface into their contribution.
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        if wi.z() <= 0.0 {
+            return zero_vec();
+        }
+
+        let half = unit_vector(*wo + *wi);
+        let normalization = (self.phong_exp + 8.0) / 8.0;
+        self.diffuse
+            + normalization * f64::powf(f64::max(0.0, half.z()), self.phong_exp) * self.specular
+    }
+
+    /// Returns the probability of sampling the specular lobe
+    fn specular_probability(&self) -> f64 {
+        let diffuse = self.diffuse.x() + self.diffuse.y() + self.diffuse.z();
+        let specular = self.specular.x() + self.specular.y() + self.specular.z();
+        if diffuse + specular <= 0.0 {
+            return 0.0;
         }
+        specular / (diffuse + specular)
+    }
+
+    /// Returns the solid angle pdf of sampling wi from wo, for both lobes combined
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        if wi.z() <= 0.0 {
+            return 0.0;
+        }
+
+        let half = unit_vector(*wo + *wi);
+        let cos_h = f64::max(0.0, half.z());
+        let pdf_half = (self.phong_exp + 1.0) / (2.0 * PI) * f64::powf(cos_h, self.phong_exp);
+        let pdf_specular = pdf_half / (4.0 * dot(wo, &half));
+        let pdf_diffuse = wi.z() / PI;
+
+        let p = self.specular_probability();
+        (1.0 - p) * pdf_diffuse + p * pdf_specular
     }
 }
 
 impl Material for BlinnPhong {
-    fn apply(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene, _depth: i32) -> Color {
-        let mut l_r = zero_vec();
-        for light in &scene.lights.lights {
-            let detail = light.as_ref().apply(r_in, rec, scene);
-            let l = unit_vector(detail.position - rec.p);
-            let v = unit_vector(-1.0 * r_in.direction);
-            let half = unit_vector(l + v);
-            let n_dot_h_to_p = f64::powf(dot(&rec.normal, &half), self.phong_exp);
-            let spec_component = self.specular * f64::max(0.0, n_dot_h_to_p);
+    fn apply(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene, depth: i32) -> Color {
+        let direct = scene.lights.reflected(r_in, rec, scene);
+        if self.legacy {
+            return vec_clamp(direct, 0.0, 1.0);
+        }
+
+        let bounced = match self.scatter(r_in, rec) {
+            Some(scattered) => scattered.attenuation * scene.ray_color(&scattered.ray, depth),
+            None => zero_vec(),
+        };
+
+        direct + bounced
+    }
+
+    /// Legacy materials only bounce light off of their diffuse part
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        if self.legacy {
+            return Some(diffuse_scatter(r_in, rec, self.diffuse));
+        }
+
+        let onb = Onb::build_from_w(&rec.normal);
+        let wo = onb.to_local(&-unit_vector(r_in.direction));
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        // Pick a lobe to sample, but weight by the pdf of both so either choice gives the same estimate
+        let wi = if random_float_1() < self.specular_probability() {
+            let cos_theta = f64::powf(random_float_1(), 1.0 / (self.phong_exp + 1.0));
+            let sin_theta = f64::sqrt(f64::max(0.0, 1.0 - cos_theta * cos_theta));
+            let phi = 2.0 * PI * random_float_1();
+            let half = Vec3::new(
+                sin_theta * f64::cos(phi),
+                sin_theta * f64::sin(phi),
+                cos_theta,
+            );
+            reflect(&-wo, &half)
+        } else {
+            random_cosine_direction()
+        };
 
-            l_r = l_r + (self.diffuse + spec_component) * detail.contribution;
+        let pdf = self.pdf(&wo, &wi);
+        if pdf <= 0.0 {
+            return None;
         }
 
-        return vec_clamp(l_r, 0.0, 1.0);
+        // The brdf is stored premultiplied by pi
+        Some(ScatterRecord {
+            ray: Ray {
+                origin: rec.p,
+                direction: onb.local(&wi),
+                time: r_in.time,
+                wavelength: r_in.wavelength,
+            },
+            attenuation: self.eval(&wo, &wi) * (wi.z() / (PI * pdf)),
+            specular: false,
+        })
+    }
+
+    /// Legacy materials keep their unnormalized highlight
+    fn brdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        let onb = Onb::build_from_w(&rec.normal);
+        let wo = onb.to_local(&-unit_vector(r_in.direction));
+        let wi = onb.to_local(&unit_vector(*direction));
+        if wi.z() <= 0.0 {
+            return zero_vec();
+        }
+        if !self.legacy {
+            return self.eval(&wo, &wi);
+        }
+
+        let half = unit_vector(wo + wi);
+        let highlight = f64::max(0.0, f64::powf(half.z(), self.phong_exp));
+        self.diffuse + highlight * self.specular
     }
 }
 
@@ -231,4 +1142,127 @@ impl Material for Emissive {
     fn apply(&self, _r_in: &Ray, rec: &HitRecord, _scene: &Scene, _depth: i32) -> Color {
         self.emit.value(&rec.uv, &rec.p)
     }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.emit.value(&rec.uv, &rec.p)
+    }
+}
+
+/// How a Mix material combines its materials
+#[derive(Clone, Copy)]
+pub enum MixMode {
+    /// Each path picks one material at random in proportion to the weights. Only one material is shaded per hit.
+    Stochastic,
+    /// Every weighted material is shaded and the results are blended. Less noisy, but traces a ray per material.
+    Blend,
+}
+
+/// Mixes materials per hit, driven by a weight texture.
+///
+/// The materials are spread evenly along the [0, 1] range of the weight, like the stops of a color ramp. With two
+/// materials a weight of 0 is all the first material and a weight of 1 is all the second. The weight is read from
+/// the first channel.
+pub struct Mix {
+    materials: Vec<Arc<dyn Material>>,
+    weight: Arc<dyn Texture>,
+    mode: MixMode,
+}
+
+impl Mix {
+    /// Creates a mix of at least one material. Scene parsing rejects empty mixes before they get here.
+    pub fn new(materials: Vec<Arc<dyn Material>>, weight: Arc<dyn Texture>, mode: MixMode) -> Mix {
+        Mix {
+            materials,
+            weight,
+            mode,
+        }
+    }
+
+    /// Returns the indices of the two materials on either side of the weight at a hit, and how far the weight is
+    /// from the first to the second
+    fn neighbours(&self, rec: &HitRecord) -> (usize, usize, f64) {
+        if self.materials.len() == 1 {
+            return (0, 0, 0.0);
+        }
+
+        let weight = f64::clamp(self.weight.value(&rec.uv, &rec.p).x(), 0.0, 1.0);
+        let position = weight * (self.materials.len() - 1) as f64;
+        let first = usize::min(position as usize, self.materials.len() - 2);
+        (first, first + 1, position - first as f64)
+    }
+
+    /// Picks one of the materials at a hit at random in proportion to its weight
+    fn pick(&self, rec: &HitRecord) -> &Arc<dyn Material> {
+        let (first, second, t) = self.neighbours(rec);
+        if random_float_1() < t {
+            &self.materials[second]
+        } else {
+            &self.materials[first]
+        }
+    }
+}
+
+impl Material for Mix {
+    fn apply(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene, depth: i32) -> Color {
+        match self.mode {
+            MixMode::Stochastic => self.pick(rec).apply(r_in, rec, scene, depth),
+            MixMode::Blend => {
+                let (first, second, t) = self.neighbours(rec);
+                let mut color = zero_vec();
+                if t < 1.0 {
+                    color += (1.0 - t) * self.materials[first].apply(r_in, rec, scene, depth);
+                }
+                if t > 0.0 {
+                    color += t * self.materials[second].apply(r_in, rec, scene, depth);
+                }
+                color
+            }
+        }
+    }
+
+    /// Integrators follow a single path, so blended materials are sampled stochastically as well
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.pick(rec).scatter(r_in, rec)
+    }
+
+    fn scatter_in_scene(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        scene: &Scene,
+    ) -> Option<ScatterRecord> {
+        self.pick(rec).scatter_in_scene(r_in, rec, scene)
+    }
+
+    fn scatter_spectral(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        scene: &Scene,
+        lambdas: &SampledWavelengths,
+    ) -> Option<(ScatterRecord, SampledSpectrum)> {
+        self.pick(rec).scatter_spectral(r_in, rec, scene, lambdas)
+    }
+
+    fn brdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        let (first, second, t) = self.neighbours(rec);
+        (1.0 - t) * self.materials[first].brdf(r_in, rec, direction)
+            + t * self.materials[second].brdf(r_in, rec, direction)
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        let (first, second, t) = self.neighbours(rec);
+        (1.0 - t) * self.materials[first].emitted(rec) + t * self.materials[second].emitted(rec)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.materials
+            .iter()
+            .any(|material| material.is_dispersive())
+    }
+
+    /// Holes in the materials let through the share of rays their weight gives them
+    fn opaque(&self, rec: &HitRecord) -> bool {
+        self.pick(rec).opaque(rec)
+    }
 }
@@ -0,0 +1,106 @@
+use std::f64::consts::PI;
+
+use crate::vector::{cross, dot, unit_vector, Vec3};
+
+/// Trowbridge-Reitz (GGX) microfacet distribution with Smith shadowing-masking.
+///
+/// All directions are in a local shading frame where z is the surface normal (see Onb).
+#[derive(Clone, Copy)]
+pub struct TrowbridgeReitz {
+    alpha_x: f64,
+    alpha_y: f64,
+}
+
+impl TrowbridgeReitz {
+    /// Creates a distribution from perceptual roughness along the two tangents, in [0, 1]
+    pub fn new(roughness_x: f64, roughness_y: f64) -> TrowbridgeReitz {
+        TrowbridgeReitz {
+            alpha_x: TrowbridgeReitz::roughness_to_alpha(roughness_x),
+            alpha_y: TrowbridgeReitz::roughness_to_alpha(roughness_y),
+        }
+    }
+
+    /// Maps perceptual roughness to alpha. Alpha is kept above a small minimum so near-smooth surfaces stay
+    /// numerically stable.
+    pub fn roughness_to_alpha(roughness: f64) -> f64 {
+        f64::max(roughness * roughness, 1e-4)
+    }
+
+    /// Returns the density of microfacet normals around wm
+    pub fn d(&self, wm: &Vec3) -> f64 {
+        let cos2 = wm.z() * wm.z();
+        if cos2 <= 0.0 {
+            return 0.0;
+        }
+
+        let e = (wm.x() * wm.x() / (self.alpha_x * self.alpha_x)
+            + wm.y() * wm.y() / (self.alpha_y * self.alpha_y))
+            / cos2;
+        1.0 / (PI * self.alpha_x * self.alpha_y * cos2 * cos2 * (1.0 + e) * (1.0 + e))
+    }
+
+    /// Smith's auxiliary function for a direction
+    pub fn lambda(&self, w: &Vec3) -> f64 {
+        let cos2 = w.z() * w.z();
+        if cos2 <= 0.0 {
+            return 0.0;
+        }
+
+        let alpha2_tan2 = (self.alpha_x * self.alpha_x * w.x() * w.x()
+            + self.alpha_y * self.alpha_y * w.y() * w.y())
+            / cos2;
+        (f64::sqrt(1.0 + alpha2_tan2) - 1.0) / 2.0
+    }
+
+    /// Fraction of microfacets visible from w
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction of microfacets visible from both wo and wi
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Returns the density of normals visible from w, which is the pdf of sample_visible
+    pub fn visible_d(&self, w: &Vec3, wm: &Vec3) -> f64 {
+        if w.z() == 0.0 {
+            return 0.0;
+        }
+        self.g1(w) / f64::abs(w.z()) * self.d(wm) * f64::abs(dot(w, wm))
+    }
+
+    /// Samples a microfacet normal visible from w, given two uniform random numbers (Heitz 2018)
+    pub fn sample_visible(&self, w: &Vec3, u1: f64, u2: f64) -> Vec3 {
+        // Stretch the view direction so the distribution becomes the hemisphere configuration
+        let sign = if w.z() < 0.0 { -1.0 } else { 1.0 };
+        let vh = unit_vector(Vec3::new(
+            self.alpha_x * w.x() * sign,
+            self.alpha_y * w.y() * sign,
+            w.z() * sign,
+        ));
+
+        let lensq = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1 = if lensq > 0.0 {
+            Vec3::new(-vh.y(), vh.x(), 0.0) / f64::sqrt(lensq)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = cross(&vh, &t1);
+
+        // Sample a disk and warp it onto the visible half of the projected hemisphere
+        let r = f64::sqrt(u1);
+        let phi = 2.0 * PI * u2;
+        let p1 = r * f64::cos(phi);
+        let s = 0.5 * (1.0 + vh.z());
+        let p2 = (1.0 - s) * f64::sqrt(1.0 - p1 * p1) + s * r * f64::sin(phi);
+        let nh = p1 * t1 + p2 * t2 + f64::sqrt(f64::max(0.0, 1.0 - p1 * p1 - p2 * p2)) * vh;
+
+        // Unstretch back to the ellipsoid configuration
+        unit_vector(Vec3::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            f64::max(1e-6, nh.z()),
+        ))
+    }
+}
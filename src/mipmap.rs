@@ -0,0 +1,320 @@
+use std::sync::Arc;
+
+use crate::assets::Pyramid;
+use crate::texture::TextureCoord;
+use crate::vector::{zero_vec, Color};
+
+/// Longest allowed ratio between the axes of an EWA filter ellipse. Longer ellipses are widened, trading a little
+/// blur for bounded lookup cost.
+const MAX_ANISOTROPY: f64 = 8.0;
+
+/// How texel coordinates outside of the image are mapped back into it
+#[derive(Clone, Copy)]
+pub enum WrapMode {
+    /// Tile the image
+    Repeat,
+    /// Extend the edge texels
+    Clamp,
+    /// Tile the image, flipping every other tile
+    Mirror,
+}
+
+/// How an image is sampled between and across texels
+#[derive(Clone, Copy)]
+pub enum FilterMode {
+    /// The closest texel
+    Nearest,
+    /// Linear interpolation between the four closest texels
+    Bilinear,
+    /// Catmull-Rom interpolation over the sixteen closest texels. Sharper than bilinear when magnified.
+    Bicubic,
+    /// Bilinear lookups in the two mip levels closest to the pixel footprint, blended
+    Trilinear,
+    /// Elliptically weighted average over the pixel footprint. Keeps detail on surfaces seen at grazing angles
+    /// that trilinear filtering blurs.
+    Ewa,
+}
+
+/// An image pyramid built by repeatedly halving the resolution of an image, for filtered lookups
+pub struct MipMap {
+    pyramid: Arc<Pyramid>,
+    wrap: WrapMode,
+    filter: FilterMode,
+}
+
+impl MipMap {
+    pub fn new(pyramid: Arc<Pyramid>, wrap: WrapMode, filter: FilterMode) -> MipMap {
+        MipMap {
+            pyramid,
+            wrap,
+            filter,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.pyramid.size(0).0
+    }
+
+    pub fn height(&self) -> usize {
+        self.pyramid.size(0).1
+    }
+
+    /// Returns the filtered color of the image over the footprint of a texture coordinate
+    pub fn lookup(&self, uv: &TextureCoord) -> Color {
+        match self.filter {
+            FilterMode::Nearest => {
+                let x = f64::floor(uv.u * self.width() as f64) as i64;
+                let y = f64::floor(uv.v * self.height() as f64) as i64;
+                self.texel(0, x, y)
+            }
+            FilterMode::Bilinear => self.bilinear(0, uv.u, uv.v),
+            FilterMode::Bicubic => self.bicubic(0, uv.u, uv.v),
+            FilterMode::Trilinear => self.trilinear(uv),
+            FilterMode::Ewa => self.ewa(uv),
+        }
+    }
+
+    /// Returns the texel at integer coordinates of a level, wrapping coordinates outside of it
+    fn texel(&self, level: usize, x: i64, y: i64) -> Color {
+        let (width, height) = self.pyramid.size(level);
+        let x = wrap(x, width as i64, self.wrap);
+        let y = wrap(y, height as i64, self.wrap);
+        self.pyramid.texel(level, x, y)
+    }
+
+    fn bilinear(&self, level: usize, u: f64, v: f64) -> Color {
+        let (width, height) = self.pyramid.size(level);
+        let x = u * width as f64 - 0.5;
+        let y = v * height as f64 - 0.5;
+        let x0 = f64::floor(x);
+        let y0 = f64::floor(y);
+        let fx = x - x0;
+        let fy = y - y0;
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        (1.0 - fx) * (1.0 - fy) * self.texel(level, x0, y0)
+            + fx * (1.0 - fy) * self.texel(level, x0 + 1, y0)
+            + (1.0 - fx) * fy * self.texel(level, x0, y0 + 1)
+            + fx * fy * self.texel(level, x0 + 1, y0 + 1)
+    }
+
+    fn bicubic(&self, level: usize, u: f64, v: f64) -> Color {
+        let (width, height) = self.pyramid.size(level);
+        let x = u * width as f64 - 0.5;
+        let y = v * height as f64 - 0.5;
+        let x0 = f64::floor(x);
+        let y0 = f64::floor(y);
+        let wx = catmull_rom_weights(x - x0);
+        let wy = catmull_rom_weights(y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let mut color = zero_vec();
+        for (j, wy) in wy.iter().enumerate() {
+            for (i, wx) in wx.iter().enumerate() {
+                color += wx * wy * self.texel(level, x0 + i as i64 - 1, y0 + j as i64 - 1);
+            }
+        }
+
+        // Catmull-Rom overshoots around sharp edges
+        Color::new(
+            f64::max(color.x(), 0.0),
+            f64::max(color.y(), 0.0),
+            f64::max(color.z(), 0.0),
+        )
+    }
+
+    /// Returns the continuous mip level whose texels are the given width in texture coordinates
+    fn level_for_width(&self, width: f64) -> f64 {
+        let size = usize::max(self.width(), self.height()) as f64;
+        f64::log2(f64::max(width * size, 1e-8))
+    }
+
+    fn trilinear(&self, uv: &TextureCoord) -> Color {
+        let width = 2.0
+            * [uv.dudx, uv.dudy, uv.dvdx, uv.dvdy]
+                .iter()
+                .fold(0.0, |max, d| f64::max(max, d.abs()));
+        let level = self.level_for_width(width);
+        let last = self.pyramid.level_count() - 1;
+
+        if level <= 0.0 {
+            return self.bilinear(0, uv.u, uv.v);
+        }
+        if level >= last as f64 {
+            return self.texel(last, 0, 0);
+        }
+
+        let lower = level as usize;
+        let t = level - lower as f64;
+        (1.0 - t) * self.bilinear(lower, uv.u, uv.v) + t * self.bilinear(lower + 1, uv.u, uv.v)
+    }
+
+    fn ewa(&self, uv: &TextureCoord) -> Color {
+        // The axes of the ellipse the pixel covers in texture space, major axis first
+        let mut major = (uv.dudx, uv.dvdx);
+        let mut minor = (uv.dudy, uv.dvdy);
+        let length = |axis: (f64, f64)| f64::sqrt(axis.0 * axis.0 + axis.1 * axis.1);
+        if length(major) < length(minor) {
+            std::mem::swap(&mut major, &mut minor);
+        }
+        let major_length = length(major);
+        let mut minor_length = length(minor);
+
+        if minor_length == 0.0 {
+            return self.bilinear(0, uv.u, uv.v);
+        }
+
+        if minor_length * MAX_ANISOTROPY < major_length {
+            let scale = major_length / (minor_length * MAX_ANISOTROPY);
+            minor = (minor.0 * scale, minor.1 * scale);
+            minor_length *= scale;
+        }
+
+        // The filter runs on the level where the minor axis is about a texel wide
+        let level = f64::max(self.level_for_width(minor_length), 0.0);
+        let lower = level as usize;
+        let t = level - lower as f64;
+        if t == 0.0 {
+            return self.ewa_level(lower, uv.u, uv.v, major, minor);
+        }
+        (1.0 - t) * self.ewa_level(lower, uv.u, uv.v, major, minor)
+            + t * self.ewa_level(lower + 1, uv.u, uv.v, major, minor)
+    }
+
+    /// Returns the gaussian weighted average of the texels of a level inside an ellipse
+    fn ewa_level(
+        &self,
+        level: usize,
+        u: f64,
+        v: f64,
+        axis0: (f64, f64),
+        axis1: (f64, f64),
+    ) -> Color {
+        let last = self.pyramid.level_count() - 1;
+        if level >= last {
+            return self.texel(last, 0, 0);
+        }
+
+        let (width, height) = self.pyramid.size(level);
+        let (width, height) = (width as f64, height as f64);
+        let s = u * width - 0.5;
+        let t = v * height - 0.5;
+        let axis0 = (axis0.0 * width, axis0.1 * height);
+        let axis1 = (axis1.0 * width, axis1.1 * height);
+
+        // Implicit ellipse a * s^2 + b * s * t + c * t^2 = 1, widened by a texel so it never falls between texels
+        let mut a = axis0.1 * axis0.1 + axis1.1 * axis1.1 + 1.0;
+        let mut b = -2.0 * (axis0.0 * axis0.1 + axis1.0 * axis1.1);
+        let mut c = axis0.0 * axis0.0 + axis1.0 * axis1.0 + 1.0;
+        let inverse_f = 1.0 / (a * c - b * b * 0.25);
+        a *= inverse_f;
+        b *= inverse_f;
+        c *= inverse_f;
+
+        // Bounding box of the ellipse
+        let det = -b * b + 4.0 * a * c;
+        let inverse_det = 1.0 / det;
+        let u_sqrt = f64::sqrt(det * c);
+        let v_sqrt = f64::sqrt(a * det);
+        let s0 = f64::ceil(s - 2.0 * inverse_det * u_sqrt) as i64;
+        let s1 = f64::floor(s + 2.0 * inverse_det * u_sqrt) as i64;
+        let t0 = f64::ceil(t - 2.0 * inverse_det * v_sqrt) as i64;
+        let t1 = f64::floor(t + 2.0 * inverse_det * v_sqrt) as i64;
+
+        let mut sum = zero_vec();
+        let mut weights = 0.0;
+        for it in t0..=t1 {
+            let tt = it as f64 - t;
+            for is in s0..=s1 {
+                let ss = is as f64 - s;
+                let r2 = a * ss * ss + b * ss * tt + c * tt * tt;
+                if r2 < 1.0 {
+                    let weight = f64::exp(-2.0 * r2) - f64::exp(-2.0);
+                    sum += weight * self.texel(level, is, it);
+                    weights += weight;
+                }
+            }
+        }
+
+        if weights <= 0.0 {
+            return self.bilinear(level, u, v);
+        }
+        sum / weights
+    }
+}
+
+/// Returns the next level of a pyramid from the texels at each index of a level. Even sizes average pairs of texels
+/// and odd sizes use a 3 tap weighted box, so every texel of the level is covered and none are dropped.
+pub fn downsample(
+    width: usize,
+    height: usize,
+    texel: impl Fn(usize) -> Color,
+) -> (usize, usize, Vec<Color>) {
+    let next_width = usize::max(width / 2, 1);
+    let next_height = usize::max(height / 2, 1);
+    let mut next = Vec::with_capacity(next_width * next_height);
+    for y in 0..next_height {
+        let rows = box_taps(height, next_height, y);
+        for x in 0..next_width {
+            let columns = box_taps(width, next_width, x);
+            let mut sum = zero_vec();
+            for &(py, wy) in &rows {
+                for &(px, wx) in &columns {
+                    sum += wx * wy * texel(py * width + px);
+                }
+            }
+            next.push(sum);
+        }
+    }
+    (next_width, next_height, next)
+}
+
+/// Returns the texels and weights that texel i of the next level averages along one axis. Each texel of the next
+/// level covers size / next texels of this one, so with an odd size the texels at the ends of its span are shared
+/// with its neighbours.
+fn box_taps(size: usize, next: usize, i: usize) -> [(usize, f64); 3] {
+    if size == 1 {
+        return [(0, 1.0), (0, 0.0), (0, 0.0)];
+    }
+    if size.is_multiple_of(2) {
+        return [(2 * i, 0.5), (2 * i + 1, 0.5), (0, 0.0)];
+    }
+
+    let n = next as f64;
+    let position = i as f64;
+    let total = size as f64;
+    [
+        (2 * i, (n - position) / total),
+        (2 * i + 1, n / total),
+        (2 * i + 2, (position + 1.0) / total),
+    ]
+}
+
+/// Maps a texel coordinate into [0, size)
+fn wrap(x: i64, size: i64, mode: WrapMode) -> usize {
+    let x = match mode {
+        WrapMode::Repeat => x.rem_euclid(size),
+        WrapMode::Clamp => x.clamp(0, size - 1),
+        WrapMode::Mirror => {
+            let x = x.rem_euclid(2 * size);
+            if x >= size {
+                2 * size - 1 - x
+            } else {
+                x
+            }
+        }
+    };
+    x as usize
+}
+
+/// Weights of the four texels around a point a fraction t of the way from the second to the third
+fn catmull_rom_weights(t: f64) -> [f64; 4] {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    [
+        0.5 * (-t3 + 2.0 * t2 - t),
+        0.5 * (3.0 * t3 - 5.0 * t2 + 2.0),
+        0.5 * (-3.0 * t3 + 4.0 * t2 + t),
+        0.5 * (t3 - t2),
+    ]
+}
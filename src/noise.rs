@@ -0,0 +1,481 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use crate::vector::{Point3, Vec3};
+
+/// Gradients of 3D noise, the midpoints of the edges of a cube
+const GRAD3: [[f64; 3]; 12] = [
+    [1.0, 1.0, 0.0],
+    [-1.0, 1.0, 0.0],
+    [1.0, -1.0, 0.0],
+    [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0],
+    [-1.0, 0.0, 1.0],
+    [1.0, 0.0, -1.0],
+    [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0],
+    [0.0, -1.0, 1.0],
+    [0.0, 1.0, -1.0],
+    [0.0, -1.0, -1.0],
+];
+
+/// Gradients of 4D noise, the midpoints of the edges of a tesseract
+const GRAD4: [[f64; 4]; 32] = [
+    [0.0, 1.0, 1.0, 1.0],
+    [0.0, 1.0, 1.0, -1.0],
+    [0.0, 1.0, -1.0, 1.0],
+    [0.0, 1.0, -1.0, -1.0],
+    [0.0, -1.0, 1.0, 1.0],
+    [0.0, -1.0, 1.0, -1.0],
+    [0.0, -1.0, -1.0, 1.0],
+    [0.0, -1.0, -1.0, -1.0],
+    [1.0, 0.0, 1.0, 1.0],
+    [1.0, 0.0, 1.0, -1.0],
+    [1.0, 0.0, -1.0, 1.0],
+    [1.0, 0.0, -1.0, -1.0],
+    [-1.0, 0.0, 1.0, 1.0],
+    [-1.0, 0.0, 1.0, -1.0],
+    [-1.0, 0.0, -1.0, 1.0],
+    [-1.0, 0.0, -1.0, -1.0],
+    [1.0, 1.0, 0.0, 1.0],
+    [1.0, 1.0, 0.0, -1.0],
+    [1.0, -1.0, 0.0, 1.0],
+    [1.0, -1.0, 0.0, -1.0],
+    [-1.0, 1.0, 0.0, 1.0],
+    [-1.0, 1.0, 0.0, -1.0],
+    [-1.0, -1.0, 0.0, 1.0],
+    [-1.0, -1.0, 0.0, -1.0],
+    [1.0, 1.0, 1.0, 0.0],
+    [1.0, 1.0, -1.0, 0.0],
+    [1.0, -1.0, 1.0, 0.0],
+    [1.0, -1.0, -1.0, 0.0],
+    [-1.0, 1.0, 1.0, 0.0],
+    [-1.0, 1.0, -1.0, 0.0],
+    [-1.0, -1.0, 1.0, 0.0],
+    [-1.0, -1.0, -1.0, 0.0],
+];
+
+/// Distance a Worley noise returns
+#[derive(Clone, Copy)]
+pub enum WorleyDistance {
+    /// Distance to the closest feature point. Gives round cells.
+    F1,
+    /// Distance to the second closest feature point
+    F2,
+    /// Difference of the two. Zero on the borders between cells, like cracks or veins.
+    F2MinusF1,
+}
+
+/// Basic noise functions everything else is built from
+#[derive(Clone, Copy)]
+pub enum Basis {
+    /// Improved gradient noise (Perlin 2002), in about [-1, 1]
+    Perlin,
+    /// Simplex noise, in about [-1, 1]. Has fewer axis aligned artifacts than Perlin noise and is cheaper in 4D.
+    Simplex,
+    /// Cellular noise from one random feature point per unit cell, in about [0, 1]
+    Worley(WorleyDistance),
+}
+
+/// A noise function, in 3D or in 4D where the fourth coordinate is usually time. The same seed always gives the
+/// same noise.
+pub struct Noise {
+    basis: Basis,
+    seed: u64,
+    /// Permutation of 0..256, repeated so lookups of a sum of two entries don't need wrapping
+    perm: [u8; 512],
+}
+
+impl Noise {
+    pub fn new(basis: Basis, seed: u64) -> Noise {
+        let mut table: Vec<u8> = (0..=255).collect();
+        table.shuffle(&mut StdRng::seed_from_u64(seed));
+
+        let mut perm = [0; 512];
+        for (i, value) in perm.iter_mut().enumerate() {
+            *value = table[i & 255];
+        }
+
+        Noise { basis, seed, perm }
+    }
+
+    /// Returns true if the noise is centered on zero
+    pub fn signed(&self) -> bool {
+        !matches!(self.basis, Basis::Worley(_))
+    }
+
+    pub fn sample(&self, p: &Point3) -> f64 {
+        let p = [p.x(), p.y(), p.z()];
+        match self.basis {
+            Basis::Perlin => self.perlin3(p),
+            Basis::Simplex => self.simplex3(p),
+            Basis::Worley(distance) => self.worley(&p, distance),
+        }
+    }
+
+    pub fn sample_4d(&self, p: &Point3, w: f64) -> f64 {
+        let p = [p.x(), p.y(), p.z(), w];
+        match self.basis {
+            Basis::Perlin => self.perlin4(p),
+            Basis::Simplex => self.simplex4(p),
+            Basis::Worley(distance) => self.worley(&p, distance),
+        }
+    }
+
+    fn hash3(&self, x: i32, y: i32, z: i32) -> usize {
+        let perm = |i: usize| self.perm[i] as usize;
+        perm(perm(perm((x & 255) as usize) + (y & 255) as usize) + (z & 255) as usize)
+    }
+
+    fn hash4(&self, x: i32, y: i32, z: i32, w: i32) -> usize {
+        let perm = |i: usize| self.perm[i] as usize;
+        perm(
+            perm(perm(perm((x & 255) as usize) + (y & 255) as usize) + (z & 255) as usize)
+                + (w & 255) as usize,
+        )
+    }
+
+    fn perlin3(&self, p: [f64; 3]) -> f64 {
+        let cell = p.map(f64::floor);
+        let f = [p[0] - cell[0], p[1] - cell[1], p[2] - cell[2]];
+        let fade = f.map(quintic);
+        let c = cell.map(|c| c as i32);
+
+        let mut result = 0.0;
+        for corner in 0..8 {
+            let o = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let g = GRAD3[self.hash3(c[0] + o[0], c[1] + o[1], c[2] + o[2]) % 12];
+            let mut weight = 1.0;
+            let mut gradient = 0.0;
+            for axis in 0..3 {
+                let offset = f[axis] - o[axis] as f64;
+                gradient += g[axis] * offset;
+                weight *= if o[axis] == 1 {
+                    fade[axis]
+                } else {
+                    1.0 - fade[axis]
+                };
+            }
+            result += weight * gradient;
+        }
+        result
+    }
+
+    fn perlin4(&self, p: [f64; 4]) -> f64 {
+        let cell = p.map(f64::floor);
+        let f = [
+            p[0] - cell[0],
+            p[1] - cell[1],
+            p[2] - cell[2],
+            p[3] - cell[3],
+        ];
+        let fade = f.map(quintic);
+        let c = cell.map(|c| c as i32);
+
+        let mut result = 0.0;
+        for corner in 0..16 {
+            let o = [
+                corner & 1,
+                (corner >> 1) & 1,
+                (corner >> 2) & 1,
+                (corner >> 3) & 1,
+            ];
+            let g = GRAD4[self.hash4(c[0] + o[0], c[1] + o[1], c[2] + o[2], c[3] + o[3]) % 32];
+            let mut weight = 1.0;
+            let mut gradient = 0.0;
+            for axis in 0..4 {
+                let offset = f[axis] - o[axis] as f64;
+                gradient += g[axis] * offset;
+                weight *= if o[axis] == 1 {
+                    fade[axis]
+                } else {
+                    1.0 - fade[axis]
+                };
+            }
+            result += weight * gradient;
+        }
+        result
+    }
+
+    /// 3D simplex noise, following Gustavson's "Simplex noise demystified"
+    fn simplex3(&self, p: [f64; 3]) -> f64 {
+        const F3: f64 = 1.0 / 3.0;
+        const G3: f64 = 1.0 / 6.0;
+
+        // Skew into the grid of cubes to find the simplex cell
+        let s = (p[0] + p[1] + p[2]) * F3;
+        let cell = [
+            f64::floor(p[0] + s),
+            f64::floor(p[1] + s),
+            f64::floor(p[2] + s),
+        ];
+        let t = (cell[0] + cell[1] + cell[2]) * G3;
+        let x0 = [
+            p[0] - (cell[0] - t),
+            p[1] - (cell[1] - t),
+            p[2] - (cell[2] - t),
+        ];
+        let c = cell.map(|c| c as i32);
+
+        // The cube is split into six tetrahedra, picked by the order of the coordinates
+        let (first, second) = if x0[0] >= x0[1] {
+            if x0[1] >= x0[2] {
+                ([1, 0, 0], [1, 1, 0])
+            } else if x0[0] >= x0[2] {
+                ([1, 0, 0], [1, 0, 1])
+            } else {
+                ([0, 0, 1], [1, 0, 1])
+            }
+        } else if x0[1] < x0[2] {
+            ([0, 0, 1], [0, 1, 1])
+        } else if x0[0] < x0[2] {
+            ([0, 1, 0], [0, 1, 1])
+        } else {
+            ([0, 1, 0], [1, 1, 0])
+        };
+
+        let corners = [[0, 0, 0], first, second, [1, 1, 1]];
+        let mut result = 0.0;
+        for (n, corner) in corners.iter().enumerate() {
+            let offset = [
+                x0[0] - corner[0] as f64 + n as f64 * G3,
+                x0[1] - corner[1] as f64 + n as f64 * G3,
+                x0[2] - corner[2] as f64 + n as f64 * G3,
+            ];
+            let t = 0.6 - offset[0] * offset[0] - offset[1] * offset[1] - offset[2] * offset[2];
+            if t > 0.0 {
+                let g =
+                    GRAD3[self.hash3(c[0] + corner[0], c[1] + corner[1], c[2] + corner[2]) % 12];
+                let gradient = g[0] * offset[0] + g[1] * offset[1] + g[2] * offset[2];
+                result += t * t * t * t * gradient;
+            }
+        }
+
+        32.0 * result
+    }
+
+    /// 4D simplex noise, following Gustavson's "Simplex noise demystified"
+    fn simplex4(&self, p: [f64; 4]) -> f64 {
+        let f4 = (f64::sqrt(5.0) - 1.0) / 4.0;
+        let g4 = (5.0 - f64::sqrt(5.0)) / 20.0;
+
+        let s = (p[0] + p[1] + p[2] + p[3]) * f4;
+        let cell = p.map(|x| f64::floor(x + s));
+        let t = (cell[0] + cell[1] + cell[2] + cell[3]) * g4;
+        let x0 = [
+            p[0] - (cell[0] - t),
+            p[1] - (cell[1] - t),
+            p[2] - (cell[2] - t),
+            p[3] - (cell[3] - t),
+        ];
+        let c = cell.map(|c| c as i32);
+
+        // Rank the coordinates. The simplex steps along the largest first.
+        let mut rank = [0; 4];
+        for i in 0..4 {
+            for j in (i + 1)..4 {
+                if x0[i] > x0[j] {
+                    rank[i] += 1;
+                } else {
+                    rank[j] += 1;
+                }
+            }
+        }
+
+        let mut result = 0.0;
+        for n in 0..5 {
+            // Corner n has the n largest coordinates stepped
+            let corner = rank.map(|r| if r >= 4 - n { 1 } else { 0 });
+            let mut offset = [0.0; 4];
+            for axis in 0..4 {
+                offset[axis] = x0[axis] - corner[axis] as f64 + n as f64 * g4;
+            }
+            let t = 0.6 - offset.iter().map(|o| o * o).sum::<f64>();
+            if t > 0.0 {
+                let g = GRAD4[self.hash4(
+                    c[0] + corner[0],
+                    c[1] + corner[1],
+                    c[2] + corner[2],
+                    c[3] + corner[3],
+                ) % 32];
+                let gradient: f64 = (0..4).map(|axis| g[axis] * offset[axis]).sum();
+                result += t * t * t * t * gradient;
+            }
+        }
+
+        27.0 * result
+    }
+
+    /// Worley noise in 3 or 4 dimensions, with one feature point at a random position in each unit cell
+    fn worley<const N: usize>(&self, p: &[f64; N], distance: WorleyDistance) -> f64 {
+        let cell = p.map(|x| f64::floor(x) as i64);
+        let mut f1 = f64::INFINITY;
+        let mut f2 = f64::INFINITY;
+
+        // Visit the 3^N cells around the point
+        for neighbour in 0..3_usize.pow(N as u32) {
+            let mut offset = [0_i64; N];
+            let mut rest = neighbour;
+            for axis in offset.iter_mut() {
+                *axis = (rest % 3) as i64 - 1;
+                rest /= 3;
+            }
+
+            let mut neighbour_cell = cell;
+            for (axis, offset) in neighbour_cell.iter_mut().zip(offset) {
+                *axis += offset;
+            }
+            let mut hash = cell_hash(self.seed, &neighbour_cell);
+
+            let mut distance_squared = 0.0;
+            for axis in 0..N {
+                hash = splitmix(hash);
+                let feature = neighbour_cell[axis] as f64 + unit_float(hash);
+                distance_squared += (feature - p[axis]) * (feature - p[axis]);
+            }
+
+            if distance_squared < f1 {
+                f2 = f1;
+                f1 = distance_squared;
+            } else if distance_squared < f2 {
+                f2 = distance_squared;
+            }
+        }
+
+        let (f1, f2) = (f64::sqrt(f1), f64::sqrt(f2));
+        match distance {
+            WorleyDistance::F1 => f1,
+            WorleyDistance::F2 => f2,
+            WorleyDistance::F2MinusF1 => f2 - f1,
+        }
+    }
+}
+
+/// Way octaves of noise are summed
+#[derive(Clone, Copy)]
+pub enum Fractal {
+    /// A single octave
+    None,
+    /// Fractional Brownian motion, the plain sum of octaves
+    Fbm,
+    /// Sum of the absolute values of octaves. Gives billowy, cloud like noise.
+    Turbulence,
+    /// Sum of inverted absolute values, each octave weighted by the last. Gives sharp ridges, like mountains.
+    Ridged,
+}
+
+/// Noise summed over octaves of increasing frequency and decreasing amplitude
+pub struct FractalNoise {
+    pub noise: Noise,
+    pub fractal: Fractal,
+    pub octaves: u32,
+    /// Frequency multiplier from one octave to the next
+    pub lacunarity: f64,
+    /// Amplitude multiplier from one octave to the next
+    pub gain: f64,
+}
+
+impl FractalNoise {
+    pub fn new(
+        noise: Noise,
+        fractal: Fractal,
+        octaves: u32,
+        lacunarity: f64,
+        gain: f64,
+    ) -> FractalNoise {
+        FractalNoise {
+            noise,
+            fractal,
+            octaves: u32::max(octaves, 1),
+            lacunarity,
+            gain,
+        }
+    }
+
+    /// Returns the noise at a point and time w, mapped to about [0, 1]. The fourth dimension is skipped if w is
+    /// None.
+    pub fn value(&self, p: &Point3, w: Option<f64>) -> f64 {
+        let octaves = match self.fractal {
+            Fractal::None => 1,
+            _ => self.octaves,
+        };
+
+        let mut sum = 0.0;
+        let mut total = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = 1.0;
+        let mut previous = 1.0;
+        for octave in 0..octaves {
+            // Shift octaves apart so their lattices don't line up at the origin
+            let shift = octave as f64 * Vec3::new(19.1, 7.7, 13.3);
+            let p = frequency * *p + shift;
+            let n = match w {
+                Some(w) => self
+                    .noise
+                    .sample_4d(&p, frequency * w + octave as f64 * 3.1),
+                None => self.noise.sample(&p),
+            };
+
+            let n = match self.fractal {
+                Fractal::None | Fractal::Fbm => n,
+                Fractal::Turbulence => f64::abs(n),
+                Fractal::Ridged => {
+                    let ridge = 1.0 - f64::abs(n);
+                    let ridge = ridge * ridge * previous;
+                    previous = f64::clamp(ridge, 0.0, 1.0);
+                    ridge
+                }
+            };
+
+            sum += amplitude * n;
+            total += amplitude;
+            amplitude *= self.gain;
+            frequency *= self.lacunarity;
+        }
+        let value = sum / total;
+
+        match self.fractal {
+            Fractal::None | Fractal::Fbm if self.noise.signed() => 0.5 + 0.5 * value,
+            _ => value,
+        }
+    }
+
+    /// Returns three decorrelated noise values in about [-1, 1], for displacing points
+    pub fn vector(&self, p: &Point3, w: Option<f64>) -> Vec3 {
+        let sample = |offset: Vec3| 2.0 * self.value(&(*p + offset), w) - 1.0;
+        Vec3::new(
+            sample(Vec3::new(0.0, 0.0, 0.0)),
+            sample(Vec3::new(31.4, 47.2, 11.9)),
+            sample(Vec3::new(-23.7, 5.3, 61.1)),
+        )
+    }
+}
+
+/// Smooth step with zero first and second derivatives at 0 and 1
+fn quintic(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+/// Returns a random value in [0, 1) for a cell of an integer grid, the same every time for the same seed
+pub fn cell_random(seed: u64, cell: &[i64]) -> f64 {
+    unit_float(cell_hash(seed, cell))
+}
+
+fn cell_hash(seed: u64, cell: &[i64]) -> u64 {
+    cell.iter()
+        .fold(splitmix(seed), |hash, &axis| splitmix(hash ^ axis as u64))
+}
+
+/// Maps the top 53 bits of a hash to [0, 1)
+fn unit_float(hash: u64) -> f64 {
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Mixes the bits of a 64 bit value
+fn splitmix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
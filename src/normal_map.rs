@@ -0,0 +1,177 @@
+use std::sync::Arc;
+
+use crate::hit::HitRecord;
+use crate::materials::{Material, ScatterRecord};
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::spectrum::{SampledSpectrum, SampledWavelengths};
+use crate::texture::Texture;
+use crate::vector::{cross, dot, unit_vector, Color, Vec3};
+
+/// Step in texture coordinates used to differentiate bump maps
+const BUMP_DELTA: f64 = 0.0005;
+
+/// A map that perturbs the shading normal of a surface
+pub enum NormalMap {
+    /// Tangent space normal map. Colors in [0, 1] map to normals in [-1, 1], with red along u, green along v and
+    /// blue along the surface normal.
+    Tangent(Arc<dyn Texture>),
+    /// Height map read from the first channel. The surface moves along its normal by height * strength scene units.
+    Bump {
+        height: Arc<dyn Texture>,
+        strength: f64,
+    },
+}
+
+/// Wraps a material, shading it with a normal perturbed by a normal or bump map
+pub struct NormalMapped {
+    material: Arc<dyn Material>,
+    map: NormalMap,
+}
+
+impl NormalMapped {
+    pub fn new(material: Arc<dyn Material>, map: NormalMap) -> NormalMapped {
+        NormalMapped { material, map }
+    }
+
+    /// Returns a copy of the hit with the perturbed shading normal, still facing the incoming ray
+    fn perturb(&self, rec: &HitRecord) -> HitRecord {
+        let outward = if rec.front_face {
+            rec.normal
+        } else {
+            -rec.normal
+        };
+
+        let normal = match &self.map {
+            NormalMap::Tangent(texture) => {
+                let (tangent, bitangent) = tangent_frame(&outward, rec);
+                let color = texture.value(&rec.uv, &rec.p);
+                let local = 2.0 * color - Color::new(1.0, 1.0, 1.0);
+                local.x() * tangent + local.y() * bitangent + local.z() * outward
+            }
+            NormalMap::Bump { height, strength } => {
+                let (tangent, bitangent) = tangent_frame(&outward, rec);
+                let dpdu = if rec.dpdu.near_zero() {
+                    tangent
+                } else {
+                    rec.dpdu
+                };
+                let dpdv = if rec.dpdv.near_zero() {
+                    bitangent
+                } else {
+                    rec.dpdv
+                };
+
+                // Step backwards near the upper edge so image lookups stay inside [0, 1]
+                let du = if rec.uv.u + BUMP_DELTA > 1.0 {
+                    -BUMP_DELTA
+                } else {
+                    BUMP_DELTA
+                };
+                let dv = if rec.uv.v + BUMP_DELTA > 1.0 {
+                    -BUMP_DELTA
+                } else {
+                    BUMP_DELTA
+                };
+
+                // The neighbours keep the rest of the lookup, so projected, object space and animated heights
+                // are read the same way as at the hit
+                let neighbour = |du: f64, dv: f64, offset: Vec3| {
+                    let mut uv = rec.uv.clone();
+                    uv.u += du;
+                    uv.v += dv;
+                    uv.object_p += offset;
+                    strength * height.value(&uv, &(rec.p + offset)).x()
+                };
+                let h = strength * height.value(&rec.uv, &rec.p).x();
+                let h_u = neighbour(du, 0.0, du * dpdu);
+                let h_v = neighbour(0.0, dv, dv * dpdv);
+
+                // Differentiate the displaced surface p + h * n, ignoring the change in the normal itself
+                let displaced_dpdu = dpdu + ((h_u - h) / du) * outward;
+                let displaced_dpdv = dpdv + ((h_v - h) / dv) * outward;
+                let normal = cross(&displaced_dpdu, &displaced_dpdv);
+
+                // The uv parameterization may be left handed
+                if dot(&normal, &outward) < 0.0 {
+                    -normal
+                } else {
+                    normal
+                }
+            }
+        };
+
+        let mut perturbed = rec.clone();
+        if normal.near_zero() {
+            return perturbed;
+        }
+        let normal = unit_vector(normal);
+        perturbed.normal = if rec.front_face { normal } else { -normal };
+        perturbed
+    }
+}
+
+/// Returns unit tangent and bitangent vectors following u and v at a hit, perpendicular to the normal. Falls back
+/// to an arbitrary frame where the texture coordinates are degenerate.
+fn tangent_frame(normal: &Vec3, rec: &HitRecord) -> (Vec3, Vec3) {
+    let tangent = rec.dpdu - dot(&rec.dpdu, normal) * *normal;
+    if tangent.near_zero() {
+        let onb = Onb::build_from_w(normal);
+        return (onb.u, onb.v);
+    }
+    let tangent = unit_vector(tangent);
+
+    let bitangent = cross(normal, &tangent);
+    if dot(&bitangent, &rec.dpdv) < 0.0 {
+        return (tangent, -bitangent);
+    }
+    (tangent, bitangent)
+}
+
+impl Material for NormalMapped {
+    fn apply(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene, depth: i32) -> Color {
+        self.material.apply(r_in, &self.perturb(rec), scene, depth)
+    }
+
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.material.scatter(r_in, &self.perturb(rec))
+    }
+
+    fn scatter_in_scene(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        scene: &Scene,
+    ) -> Option<ScatterRecord> {
+        self.material
+            .scatter_in_scene(r_in, &self.perturb(rec), scene)
+    }
+
+    fn scatter_spectral(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        scene: &Scene,
+        lambdas: &SampledWavelengths,
+    ) -> Option<(ScatterRecord, SampledSpectrum)> {
+        self.material
+            .scatter_spectral(r_in, &self.perturb(rec), scene, lambdas)
+    }
+
+    fn brdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        self.material.brdf(r_in, &self.perturb(rec), direction)
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.material.emitted(rec)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.material.is_dispersive()
+    }
+
+    fn opaque(&self, rec: &HitRecord) -> bool {
+        self.material.opaque(rec)
+    }
+}
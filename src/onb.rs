@@ -0,0 +1,49 @@
+use crate::vector::{cross, dot, unit_vector, Vec3};
+
+/// Orthonormal basis, used to move directions between world space and a local shading frame where w is the normal
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    /// Builds a basis around n. The tangents are derived from the world y axis (or x axis when n is close to y), so
+    /// they don't follow the surface. Use build_from_w_and_tangent where the orientation around n matters.
+    pub fn build_from_w(n: &Vec3) -> Onb {
+        let w = unit_vector(*n);
+        let a = if f64::abs(w.y()) > 0.9 {
+            Vec3::new(1.0, 0.0, 0.0)
+        } else {
+            Vec3::new(0.0, 1.0, 0.0)
+        };
+        let v = unit_vector(cross(&w, &a));
+        let u = cross(&w, &v);
+
+        Onb { u, v, w }
+    }
+
+    /// Builds a basis around n with u following a surface tangent such as dpdu, made perpendicular to n. Falls
+    /// back to build_from_w where the tangent is degenerate or parallel to n.
+    pub fn build_from_w_and_tangent(n: &Vec3, tangent: &Vec3) -> Onb {
+        let w = unit_vector(*n);
+        let u = *tangent - dot(tangent, &w) * w;
+        if tangent.near_zero() || u.length_squared() < 1e-12 * tangent.length_squared() {
+            return Onb::build_from_w(n);
+        }
+        let u = unit_vector(u);
+        let v = cross(&w, &u);
+
+        Onb { u, v, w }
+    }
+
+    /// Converts a direction from local coordinates to world space
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        a.x() * self.u + a.y() * self.v + a.z() * self.w
+    }
+
+    /// Converts a direction from world space to local coordinates
+    pub fn to_local(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(dot(a, &self.u), dot(a, &self.v), dot(a, &self.w))
+    }
+}
@@ -0,0 +1,360 @@
+use std::sync::Arc;
+
+use crate::noise::{cell_random, FractalNoise};
+use crate::projection::Space;
+use crate::texture::{texture_point, Texture, TextureCoord};
+use crate::vector::{Color, Point3};
+
+fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    let t = f64::clamp((x - edge0) / (edge1 - edge0), 0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Scales a color by a random factor in [1 - variation, 1 + variation] picked for a cell
+fn vary(color: Color, variation: f64, seed: u64, cell: &[i64]) -> Color {
+    let factor = 1.0 + variation * (2.0 * cell_random(seed, cell) - 1.0);
+    f64::max(factor, 0.0) * color
+}
+
+/// Growth rings around the y axis, distorted by noise
+pub struct Wood {
+    light: Arc<dyn Texture>,
+    dark: Arc<dyn Texture>,
+    /// Rings per unit of distance from the axis
+    rings: f64,
+    noise: FractalNoise,
+    noise_scale: f64,
+    /// How far the noise pushes the rings, in rings
+    distortion: f64,
+    space: Space,
+}
+
+impl Wood {
+    pub fn new(
+        light: Arc<dyn Texture>,
+        dark: Arc<dyn Texture>,
+        rings: f64,
+        noise: FractalNoise,
+        noise_scale: f64,
+        distortion: f64,
+        space: Space,
+    ) -> Wood {
+        Wood {
+            light,
+            dark,
+            rings,
+            noise,
+            noise_scale,
+            distortion,
+            space,
+        }
+    }
+}
+
+impl Texture for Wood {
+    fn value(&self, uv: &TextureCoord, p: &Point3) -> Color {
+        let point = texture_point(uv, p, self.space);
+        let radius = f64::sqrt(point.x() * point.x() + point.z() * point.z());
+        let grain = self.noise.value(&(self.noise_scale * point), None) - 0.5;
+        let ring = (radius * self.rings + self.distortion * grain).rem_euclid(1.0);
+
+        // Wood darkens slowly through the growing season, then starts the next ring light
+        let dark = smoothstep(0.0, 0.85, ring) * (1.0 - smoothstep(0.85, 1.0, ring));
+        (1.0 - dark) * self.light.value(uv, p) + dark * self.dark.value(uv, p)
+    }
+}
+
+/// Veins across the x axis, bent by turbulent noise
+pub struct Marble {
+    base: Arc<dyn Texture>,
+    vein: Arc<dyn Texture>,
+    /// Angular frequency of the veins along x, giving frequency / pi veins per unit
+    frequency: f64,
+    noise: FractalNoise,
+    noise_scale: f64,
+    distortion: f64,
+    /// Higher values give thinner veins
+    sharpness: f64,
+    space: Space,
+}
+
+impl Marble {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        base: Arc<dyn Texture>,
+        vein: Arc<dyn Texture>,
+        frequency: f64,
+        noise: FractalNoise,
+        noise_scale: f64,
+        distortion: f64,
+        sharpness: f64,
+        space: Space,
+    ) -> Marble {
+        Marble {
+            base,
+            vein,
+            frequency,
+            noise,
+            noise_scale,
+            distortion,
+            sharpness,
+            space,
+        }
+    }
+}
+
+impl Texture for Marble {
+    fn value(&self, uv: &TextureCoord, p: &Point3) -> Color {
+        let point = texture_point(uv, p, self.space);
+        let turbulence = self.noise.value(&(self.noise_scale * point), None);
+        let wave = f64::sin(self.frequency * point.x() + self.distortion * turbulence);
+        let vein = f64::powf(1.0 - f64::abs(wave), self.sharpness);
+        (1.0 - vein) * self.base.value(uv, p) + vein * self.vein.value(uv, p)
+    }
+}
+
+/// Rows of bricks in texture space, each row shifted by half a brick
+pub struct Bricks {
+    brick: Arc<dyn Texture>,
+    mortar: Arc<dyn Texture>,
+    /// Size of a brick and its share of the mortar around it, in u and v
+    size: (f64, f64),
+    mortar_width: f64,
+    /// Largest random change in the brightness of a brick, as a fraction
+    variation: f64,
+    seed: u64,
+}
+
+impl Bricks {
+    pub fn new(
+        brick: Arc<dyn Texture>,
+        mortar: Arc<dyn Texture>,
+        size: (f64, f64),
+        mortar_width: f64,
+        variation: f64,
+        seed: u64,
+    ) -> Bricks {
+        Bricks {
+            brick,
+            mortar,
+            size,
+            mortar_width,
+            variation,
+            seed,
+        }
+    }
+}
+
+impl Texture for Bricks {
+    fn value(&self, uv: &TextureCoord, p: &Point3) -> Color {
+        let (width, height) = self.size;
+        let y = uv.v / height;
+        let row = f64::floor(y);
+        let x = uv.u / width + 0.5 * row;
+        let column = f64::floor(x);
+
+        // Distance to the nearest edge of the brick, in texture space
+        let edge_x = width * f64::min(x - column, column + 1.0 - x);
+        let edge_y = height * f64::min(y - row, row + 1.0 - y);
+        if f64::min(edge_x, edge_y) < 0.5 * self.mortar_width {
+            return self.mortar.value(uv, p);
+        }
+
+        let color = self.brick.value(uv, p);
+        vary(
+            color,
+            self.variation,
+            self.seed,
+            &[column as i64, row as i64],
+        )
+    }
+}
+
+/// Shape of the tiles of a Tiles texture
+#[derive(Clone, Copy)]
+pub enum TileShape {
+    Square,
+    /// Hexagons with pointed tops, with the size measured across the flat sides
+    Hexagon,
+}
+
+/// A grid of tiles separated by grout, in texture space
+pub struct Tiles {
+    tile: Arc<dyn Texture>,
+    grout: Arc<dyn Texture>,
+    shape: TileShape,
+    size: f64,
+    grout_width: f64,
+    /// Largest random change in the brightness of a tile, as a fraction
+    variation: f64,
+    seed: u64,
+}
+
+impl Tiles {
+    pub fn new(
+        tile: Arc<dyn Texture>,
+        grout: Arc<dyn Texture>,
+        shape: TileShape,
+        size: f64,
+        grout_width: f64,
+        variation: f64,
+        seed: u64,
+    ) -> Tiles {
+        Tiles {
+            tile,
+            grout,
+            shape,
+            size,
+            grout_width,
+            variation,
+            seed,
+        }
+    }
+
+    /// Returns the cell of the tile at a point in units of tiles, and the distance to its edge in the same units
+    fn locate(&self, u: f64, v: f64) -> ([i64; 2], f64) {
+        match self.shape {
+            TileShape::Square => {
+                let (column, row) = (f64::floor(u), f64::floor(v));
+                let edge = [u - column, column + 1.0 - u, v - row, row + 1.0 - v];
+                let edge = edge.iter().cloned().fold(f64::INFINITY, f64::min);
+                ([column as i64, row as i64], edge)
+            }
+            TileShape::Hexagon => {
+                // Hexagon centers lie on two rectangular grids, offset from each other by half a cell
+                let height = f64::sqrt(3.0);
+                let local = |offset_u: f64, offset_v: f64| {
+                    let x = (u - offset_u).rem_euclid(1.0) - 0.5;
+                    let y = (v - offset_v).rem_euclid(height) - 0.5 * height;
+                    (x, y)
+                };
+                let a = local(0.0, 0.0);
+                let b = local(0.5, 0.5 * height);
+                let (x, y) = if a.0 * a.0 + a.1 * a.1 < b.0 * b.0 + b.1 * b.1 {
+                    a
+                } else {
+                    b
+                };
+
+                // Centers are on a half cell grid, so doubling them gives whole numbers
+                let cell = [
+                    f64::round(2.0 * (u - x)) as i64,
+                    f64::round(2.0 * (v - y) / height) as i64,
+                ];
+                // Distance from the center to the edge, across the sides
+                let (x, y) = (f64::abs(x), f64::abs(y));
+                let distance = f64::max(x, 0.5 * x + 0.5 * height * y);
+                (cell, 0.5 - distance)
+            }
+        }
+    }
+}
+
+impl Texture for Tiles {
+    fn value(&self, uv: &TextureCoord, p: &Point3) -> Color {
+        let (cell, edge) = self.locate(uv.u / self.size, uv.v / self.size);
+        if edge * self.size < 0.5 * self.grout_width {
+            return self.grout.value(uv, p);
+        }
+        vary(self.tile.value(uv, p), self.variation, self.seed, &cell)
+    }
+}
+
+/// Stripes along u
+pub struct Stripes {
+    stripe: Arc<dyn Texture>,
+    gap: Arc<dyn Texture>,
+    /// Stripes per unit of u
+    frequency: f64,
+    /// Share of each period covered by the stripe
+    ratio: f64,
+}
+
+impl Stripes {
+    pub fn new(
+        stripe: Arc<dyn Texture>,
+        gap: Arc<dyn Texture>,
+        frequency: f64,
+        ratio: f64,
+    ) -> Stripes {
+        Stripes {
+            stripe,
+            gap,
+            frequency,
+            ratio,
+        }
+    }
+}
+
+impl Texture for Stripes {
+    fn value(&self, uv: &TextureCoord, p: &Point3) -> Color {
+        if (uv.u * self.frequency).rem_euclid(1.0) < self.ratio {
+            self.stripe.value(uv, p)
+        } else {
+            self.gap.value(uv, p)
+        }
+    }
+}
+
+/// Shape of a Gradient texture
+#[derive(Clone, Copy)]
+pub enum GradientShape {
+    /// From u = 0 to u = 1
+    Linear,
+    /// From the center of texture space, (0.5, 0.5), out to a distance of 0.5
+    Radial,
+}
+
+/// Blends from one texture to another across texture space
+pub struct Gradient {
+    start: Arc<dyn Texture>,
+    end: Arc<dyn Texture>,
+    shape: GradientShape,
+}
+
+impl Gradient {
+    pub fn new(start: Arc<dyn Texture>, end: Arc<dyn Texture>, shape: GradientShape) -> Gradient {
+        Gradient { start, end, shape }
+    }
+}
+
+impl Texture for Gradient {
+    fn value(&self, uv: &TextureCoord, p: &Point3) -> Color {
+        let t = match self.shape {
+            GradientShape::Linear => uv.u,
+            GradientShape::Radial => 2.0 * f64::hypot(uv.u - 0.5, uv.v - 0.5),
+        };
+        let t = f64::clamp(t, 0.0, 1.0);
+        (1.0 - t) * self.start.value(uv, p) + t * self.end.value(uv, p)
+    }
+}
+
+/// Checkers in texture space, unlike Checker which works on world space points
+pub struct UvChecker {
+    odd: Arc<dyn Texture>,
+    even: Arc<dyn Texture>,
+    /// Squares per unit of u and v
+    frequency: (f64, f64),
+}
+
+impl UvChecker {
+    pub fn new(odd: Arc<dyn Texture>, even: Arc<dyn Texture>, frequency: (f64, f64)) -> UvChecker {
+        UvChecker {
+            odd,
+            even,
+            frequency,
+        }
+    }
+}
+
+impl Texture for UvChecker {
+    fn value(&self, uv: &TextureCoord, p: &Point3) -> Color {
+        let column = f64::floor(uv.u * self.frequency.0) as i64;
+        let row = f64::floor(uv.v * self.frequency.1) as i64;
+        if (column + row).rem_euclid(2) == 1 {
+            self.odd.value(uv, p)
+        } else {
+            self.even.value(uv, p)
+        }
+    }
+}
@@ -1,7 +1,7 @@
-use crate::{
-    utility::random_int,
-    vector::{dot, random_vec, unit_vector, zero_vec, Point3, Vec3},
-};
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
+
+use crate::vector::{dot, unit_vector, zero_vec, Point3, Vec3};
 
 pub struct Perlin {
     point_count: usize,
@@ -14,16 +14,29 @@ pub struct Perlin {
 
 impl Perlin {
     pub fn new() -> Perlin {
+        Perlin::new_from_rng(&mut thread_rng())
+    }
+
+    /// Creates Perlin noise that is the same on every run for the same seed
+    pub fn with_seed(seed: u64) -> Perlin {
+        Perlin::new_from_rng(&mut StdRng::seed_from_u64(seed))
+    }
+
+    fn new_from_rng<R: Rng>(rng: &mut R) -> Perlin {
         let point_count = 256;
         let mut ranvec: Vec<Vec3> = Vec::new();
 
         for _ in 0..point_count {
-            ranvec.push(unit_vector(random_vec(-1.0, 1.0)));
+            ranvec.push(unit_vector(Vec3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            )));
         }
 
-        let perm_x = Perlin::perlin_generate_perm(point_count);
-        let perm_y = Perlin::perlin_generate_perm(point_count);
-        let perm_z = Perlin::perlin_generate_perm(point_count);
+        let perm_x = Perlin::perlin_generate_perm(point_count, rng);
+        let perm_y = Perlin::perlin_generate_perm(point_count, rng);
+        let perm_z = Perlin::perlin_generate_perm(point_count, rng);
 
         Perlin {
             point_count,
@@ -95,21 +108,21 @@ impl Perlin {
         return accum;
     }
 
-    fn perlin_generate_perm(point_count: usize) -> Vec<usize> {
+    fn perlin_generate_perm<R: Rng>(point_count: usize, rng: &mut R) -> Vec<usize> {
         let mut p: Vec<usize> = Vec::new();
 
         for i in 0..point_count {
             p.push(i);
         }
 
-        Perlin::permute(&mut p, point_count);
+        Perlin::permute(&mut p, point_count, rng);
 
         return p;
     }
 
-    fn permute(p: &mut Vec<usize>, n: usize) {
+    fn permute<R: Rng>(p: &mut Vec<usize>, n: usize, rng: &mut R) {
         for i in (0..n).rev() {
-            let target = random_int(0, i as i32) as usize;
+            let target = rng.gen_range(0..=i);
             let tmp = p[i];
             p[i] = p[target];
             p[target] = tmp;
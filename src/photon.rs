@@ -0,0 +1,463 @@
+use std::sync::Arc;
+
+use crate::buffer::Buffer;
+use crate::hit::{HitRecord, Hittable};
+use crate::ray::{Ray, RayDifferential};
+use crate::scene::Scene;
+use crate::utility::{random_float, random_float_1, INFINITY, PI};
+use crate::vector::{dot, random_unit_vector, unit_vector, zero_vec, Color, Point3, Vec3};
+
+/// Settings for stochastic progressive photon mapping
+#[derive(Clone)]
+pub struct SppmSettings {
+    /// Number of camera + photon passes
+    pub iterations: i32,
+    /// Photons emitted per pass
+    pub photons: usize,
+    /// Initial gather radius of every pixel
+    pub radius: f64,
+    /// Fraction of new photons kept every pass. Lower values shrink the radius faster.
+    pub alpha: f64,
+}
+
+/// A photon stored at a surface with a brdf
+#[derive(Clone, Copy)]
+struct Photon {
+    position: Point3,
+    /// Direction the photon was travelling in when it hit the surface
+    direction: Vec3,
+    power: Color,
+}
+
+/// Photons stored in a balanced kd-tree.
+///
+/// The tree is implicit: the median of every range is the node, and its split axis is stored alongside it.
+struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<usize>,
+}
+
+impl PhotonMap {
+    fn new(photons: Vec<Photon>) -> PhotonMap {
+        let mut map = PhotonMap {
+            axes: vec![0; photons.len()],
+            photons,
+        };
+        let length = map.photons.len();
+        map.build(0, length);
+
+        map
+    }
+
+    fn build(&mut self, lo: usize, hi: usize) {
+        if hi <= lo {
+            return;
+        }
+
+        // Split along the axis where the photons are spread out the most
+        let mut min = Vec3::new(INFINITY, INFINITY, INFINITY);
+        let mut max = Vec3::new(-INFINITY, -INFINITY, -INFINITY);
+        for photon in &self.photons[lo..hi] {
+            for a in 0..3 {
+                min[a] = f64::min(min[a], photon.position[a]);
+                max[a] = f64::max(max[a], photon.position[a]);
+            }
+        }
+        let extent = max - min;
+        let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+            0
+        } else if extent.y() > extent.z() {
+            1
+        } else {
+            2
+        };
+
+        let mid = (lo + hi) / 2;
+        self.photons[lo..hi].select_nth_unstable_by(mid - lo, |a, b| {
+            a.position[axis].total_cmp(&b.position[axis])
+        });
+        self.axes[mid] = axis;
+
+        self.build(lo, mid);
+        self.build(mid + 1, hi);
+    }
+
+    /// Calls f for every photon within radius of p
+    fn for_each_in_radius<F: FnMut(&Photon)>(&self, p: &Point3, radius: f64, f: &mut F) {
+        self.query(0, self.photons.len(), p, radius * radius, f);
+    }
+
+    fn query<F: FnMut(&Photon)>(&self, lo: usize, hi: usize, p: &Point3, radius2: f64, f: &mut F) {
+        if hi <= lo {
+            return;
+        }
+
+        let mid = (lo + hi) / 2;
+        let photon = &self.photons[mid];
+        if (photon.position - *p).length_squared() <= radius2 {
+            f(photon);
+        }
+
+        let axis = self.axes[mid];
+        let diff = p[axis] - photon.position[axis];
+        let (near, far) = match diff < 0.0 {
+            true => ((lo, mid), (mid + 1, hi)),
+            false => ((mid + 1, hi), (lo, mid)),
+        };
+
+        self.query(near.0, near.1, p, radius2, f);
+        if diff * diff <= radius2 {
+            self.query(far.0, far.1, p, radius2, f);
+        }
+    }
+}
+
+/// The first surface that photons are stored on seen through a pixel, along with its SPPM statistics
+#[derive(Clone)]
+struct VisiblePoint {
+    /// Camera ray and its hit at the visible point, or None if the path didn't reach one this pass
+    hit: Option<(Ray, HitRecord)>,
+    /// Camera path throughput at the visible point
+    weight: Color,
+    /// Direct light (emission, background and point lights) summed over every pass
+    direct: Color,
+    radius: f64,
+    photon_count: f64,
+    flux: Color,
+}
+
+/// Something photons can be emitted from
+enum Emitter {
+    Point { position: Point3, power: Color },
+    Surface { object: Arc<dyn Hittable> },
+}
+
+impl Scene {
+    /// Renders the scene with stochastic progressive photon mapping (Hachisuka & Jensen 2009).
+    ///
+    /// Every pass traces one camera path per pixel to the first hit that photons are stored on, then scatters a fresh photon map
+    /// from the lights and shrinks each pixel's gather radius. Photons are thrown away after every pass, so memory
+    /// stays bounded while the bias goes to zero as the number of passes increases.
+    pub fn render_sppm(&self, settings: &SppmSettings) -> Buffer {
+        let width = self.width() as usize;
+        let height = self.height() as usize;
+        let num_threads = usize::max(num_cpus::get(), 1);
+
+        let emitters = self.emitters();
+        let mut pixels = vec![
+            VisiblePoint {
+                hit: None,
+                weight: zero_vec(),
+                direct: zero_vec(),
+                radius: settings.radius,
+                photon_count: 0.0,
+                flux: zero_vec(),
+            };
+            width * height
+        ];
+        let rows_per_thread = height.div_ceil(num_threads);
+
+        for iteration in 0..settings.iterations {
+            eprint!("\rSPPM pass {}/{}", iteration + 1, settings.iterations);
+
+            // Camera pass
+            crossbeam::scope(|scope| {
+                for (chunk_index, chunk) in pixels.chunks_mut(rows_per_thread * width).enumerate() {
+                    scope.spawn(move |_| {
+                        for (index, pixel) in chunk.iter_mut().enumerate() {
+                            let index = chunk_index * rows_per_thread * width + index;
+                            let i = index % width;
+                            let j = index / width;
+                            let u = (i as f64 + random_float_1()) / (width - 1) as f64;
+                            let v = (j as f64 + random_float_1()) / (height - 1) as f64;
+                            let (ray, differential) = self.get_ray_differential(
+                                u,
+                                v,
+                                1.0 / (width - 1) as f64,
+                                1.0 / (height - 1) as f64,
+                            );
+                            self.trace_camera_path(&ray, &differential, pixel);
+                        }
+                    });
+                }
+            })
+            .unwrap();
+
+            // Photon pass
+            let photons_per_thread = settings.photons / num_threads;
+            let photons: Vec<Photon> = crossbeam::scope(|scope| {
+                let threads: Vec<_> = (0..num_threads)
+                    .map(|_| {
+                        let emitters = &emitters;
+                        scope.spawn(move |_| {
+                            let mut photons = Vec::new();
+                            for _ in 0..photons_per_thread {
+                                self.trace_photon(emitters, &mut photons);
+                            }
+                            photons
+                        })
+                    })
+                    .collect();
+
+                threads
+                    .into_iter()
+                    .flat_map(|thread| thread.join().unwrap())
+                    .collect()
+            })
+            .unwrap();
+            let emitted = (photons_per_thread * num_threads) as f64;
+            let map = PhotonMap::new(photons);
+
+            // Density estimation and radius update
+            crossbeam::scope(|scope| {
+                for chunk in pixels.chunks_mut(rows_per_thread * width) {
+                    let map = &map;
+                    scope.spawn(move |_| {
+                        for pixel in chunk.iter_mut() {
+                            if pixel.hit.is_some() {
+                                Scene::gather(map, pixel, emitted, settings.alpha);
+                            }
+                        }
+                    });
+                }
+            })
+            .unwrap();
+        }
+        eprintln!();
+
+        let mut buffer = Buffer::new(width as u32, height as u32);
+        let iterations = settings.iterations as f64;
+        for (index, pixel) in pixels.iter().enumerate() {
+            let indirect = pixel.flux / (iterations * PI * pixel.radius * pixel.radius);
+            let color = pixel.direct / iterations + indirect;
+            buffer.write(color, (index % width) as u32, (index / width) as u32);
+        }
+
+        buffer
+    }
+
+    /// Follows a camera ray through specular bounces and records the first hit that photons are stored on in the pixel
+    fn trace_camera_path(&self, r: &Ray, differential: &RayDifferential, pixel: &mut VisiblePoint) {
+        let mut ray = Ray::new(r.origin, r.direction, r.time);
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        pixel.hit = None;
+
+        for bounce in 0..self.max_depth() {
+            let hit = if bounce == 0 {
+                self.hit_from_camera(&ray, differential)
+            } else {
+                self.hit(&ray, 0.001, INFINITY)
+            };
+            let rec = match hit {
+                Some(rec) => rec,
+                None => {
+                    pixel.direct += throughput * self.background_color(ray.direction);
+                    return;
+                }
+            };
+
+            pixel.direct += throughput * rec.material.emitted(&rec);
+
+            // Point lights are handled directly at every bounce, with the brdf evaluated towards each of them. The
+            // sum isn't clamped so that it agrees with the photons.
+            pixel.direct += throughput * self.lights.reflected(&ray, &rec, self);
+
+            let scattered = rec.material.scatter_in_scene(&ray, &rec, self);
+
+            // The visible point is the first hit that photons are stored on. Its brdf is left to gather, so it's
+            // always recorded with the plain throughput. Specular bounces off of it are still followed for
+            // emission and point lights, but photons seen through them are lost.
+            if stores_photons(&ray, &rec) {
+                if pixel.hit.is_some() {
+                    return;
+                }
+                pixel.weight = throughput;
+                pixel.hit = Some((ray, rec));
+            }
+
+            let scattered = match scattered {
+                Some(scattered) if scattered.specular => scattered,
+                _ => return,
+            };
+
+            throughput = throughput * scattered.attenuation;
+            ray = scattered.ray;
+        }
+    }
+
+    /// Emits a single photon and stores it at every surface with a brdf it bounces off of
+    fn trace_photon(&self, emitters: &Vec<(Emitter, f64)>, photons: &mut Vec<Photon>) {
+        if emitters.is_empty() {
+            return;
+        }
+
+        // Pick an emitter proportionally to its power
+        let total: f64 = emitters.iter().map(|(_, weight)| weight).sum();
+        let mut target = random_float_1() * total;
+        let mut chosen = &emitters[emitters.len() - 1];
+        for emitter in emitters {
+            if target < emitter.1 {
+                chosen = emitter;
+                break;
+            }
+            target -= emitter.1;
+        }
+        let pick_pdf = chosen.1 / total;
+        let (time0, time1) = self.shutter();
+        let time = match time1 > time0 {
+            true => random_float(time0, time1),
+            false => time0,
+        };
+
+        let (mut ray, mut power, mut light_position) = match &chosen.0 {
+            Emitter::Point { position, power } => {
+                let ray = Ray::new(*position, random_unit_vector(), time);
+                (ray, *power / pick_pdf, Some(*position))
+            }
+            Emitter::Surface { object } => {
+                let rec = match object.random_surface_point(time) {
+                    Some(rec) => rec,
+                    None => return,
+                };
+                // Emitters are two sided, so pick a side and emit with a cosine distribution around it
+                let normal = match random_float_1() < 0.5 {
+                    true => rec.normal,
+                    false => -rec.normal,
+                };
+                let mut direction = normal + random_unit_vector();
+                if direction.near_zero() {
+                    direction = normal;
+                }
+                let power = rec.material.emitted(&rec) * 2.0 * PI * object.area();
+                (Ray::new(rec.p, direction, time), power / pick_pdf, None)
+            }
+        };
+        let from_point_light = light_position.is_some();
+
+        for bounce in 0..self.max_depth() {
+            let rec = match self.hit(&ray, 0.001, INFINITY) {
+                Some(rec) => rec,
+                None => return,
+            };
+
+            if stores_photons(&ray, &rec) {
+                // Photons spread out with the square of the distance, but point lights don't fall off. Scaling once
+                // by the squared distance from the light to the first surface the photon lands on gives the
+                // irradiance PointLight shades with, whether or not it passed through glass on the way.
+                if let Some(position) = light_position.take() {
+                    power = power * (rec.p - position).length_squared();
+                }
+
+                // Direct light from point lights is already computed on the camera side
+                if bounce > 0 || !from_point_light {
+                    photons.push(Photon {
+                        position: rec.p,
+                        direction: unit_vector(ray.direction),
+                        power,
+                    });
+                }
+            }
+
+            let scattered = match rec.material.scatter_in_scene(&ray, &rec, self) {
+                Some(scattered) => scattered,
+                None => return,
+            };
+
+            // Russian roulette keeps photon powers roughly constant
+            let new_power = power * scattered.attenuation;
+            let survival = match max_component(power) > 0.0 {
+                true => f64::min(1.0, max_component(new_power) / max_component(power)),
+                false => 0.0,
+            };
+            if random_float_1() >= survival {
+                return;
+            }
+
+            power = new_power / survival;
+            ray = scattered.ray;
+        }
+    }
+
+    /// Adds the photons around a visible point to its statistics and shrinks its radius
+    fn gather(map: &PhotonMap, pixel: &mut VisiblePoint, emitted: f64, alpha: f64) {
+        let (ray, rec) = match &pixel.hit {
+            Some(hit) => hit,
+            None => return,
+        };
+
+        let mut count = 0.0;
+        let mut flux = zero_vec();
+        map.for_each_in_radius(&rec.p, pixel.radius, &mut |photon| {
+            // Ignore photons arriving from behind the surface
+            if dot(&photon.direction, &rec.normal) < 0.0 {
+                count += 1.0;
+                flux += rec.material.brdf(ray, rec, &-photon.direction) * photon.power;
+            }
+        });
+
+        if count == 0.0 {
+            return;
+        }
+
+        // The brdf is premultiplied by pi, and photon powers are per emitted photon
+        let flux = pixel.weight * flux / (PI * emitted);
+        let new_count = pixel.photon_count + alpha * count;
+        let new_radius = pixel.radius * f64::sqrt(new_count / (pixel.photon_count + count));
+        let shrink = (new_radius * new_radius) / (pixel.radius * pixel.radius);
+
+        pixel.flux = (pixel.flux + flux) * shrink;
+        pixel.radius = new_radius;
+        pixel.photon_count = new_count;
+    }
+
+    /// Collects point lights and emissive shapes, weighted by their power
+    fn emitters(&self) -> Vec<(Emitter, f64)> {
+        let mut emitters = Vec::new();
+
+        for light in &self.lights.lights {
+            if let Some((position, color)) = light.point_emitter() {
+                // An isotropic light of intensity pi * color gives an irradiance of pi * color * cos(theta) at
+                // unit distance, as PointLight does everywhere
+                let power = 4.0 * PI * PI * color;
+                emitters.push((Emitter::Point { position, power }, average(power)));
+            }
+        }
+
+        for object in &self.objects.objects {
+            // Estimate emitted power from a handful of samples of the surface when the shutter opens
+            let samples = 16;
+            let mut radiance = zero_vec();
+            for _ in 0..samples {
+                if let Some(rec) = object.random_surface_point(self.shutter().0) {
+                    radiance += rec.material.emitted(&rec);
+                }
+            }
+            let power = average(radiance) / samples as f64 * 2.0 * PI * object.area();
+
+            if power > 0.0 {
+                emitters.push((
+                    Emitter::Surface {
+                        object: Arc::clone(object),
+                    },
+                    power,
+                ));
+            }
+        }
+
+        emitters
+    }
+}
+
+/// Returns true if photons landing on a hit can be reflected back out. Photons are stored wherever the material
+/// has a brdf, whichever way its scatter sends them on.
+fn stores_photons(ray: &Ray, rec: &HitRecord) -> bool {
+    !rec.material.brdf(ray, rec, &rec.normal).near_zero()
+}
+
+fn average(color: Color) -> f64 {
+    (color.x() + color.y() + color.z()) / 3.0
+}
+
+fn max_component(color: Color) -> f64 {
+    f64::max(color.x(), f64::max(color.y(), color.z()))
+}
@@ -0,0 +1,278 @@
+use std::sync::Arc;
+
+use crate::color::WorkingSpace;
+use crate::hit::HitRecord;
+use crate::materials::{fresnel_dielectric, Material, ScatterRecord};
+use crate::microfacet::TrowbridgeReitz;
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::texture::Texture;
+use crate::utility::random_float_1;
+use crate::vector::{dot, random_cosine_direction, reflect, unit_vector, zero_vec, Color, Vec3};
+
+/// Principled "uber" material following the Disney BRDF (Burley 2012), with rough glass transmission on top.
+///
+/// Every parameter is a texture. Scalar parameters are read from the first channel.
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    /// Specular reflectance of non-metals. 0.5 maps to a reflectance of 4% at normal incidence.
+    pub specular: Arc<dyn Texture>,
+    /// Tints the non-metal specular reflection towards the base color
+    pub specular_tint: Arc<dyn Texture>,
+    /// Soft reflection at grazing angles, for cloth
+    pub sheen: Arc<dyn Texture>,
+    /// Strength of a second, colorless specular layer
+    pub clearcoat: Arc<dyn Texture>,
+    /// Glossiness of the clearcoat layer, from satin (0) to gloss (1)
+    pub clearcoat_gloss: Arc<dyn Texture>,
+    /// Blends the non-metal base into rough glass
+    pub transmission: Arc<dyn Texture>,
+    /// Index of refraction of the transmissive part
+    pub ior: Arc<dyn Texture>,
+    /// Space the colors are in, which sets the luminance weights used for tints and lobe selection
+    pub working_space: WorkingSpace,
+}
+
+/// Parameters of a Principled material evaluated at a hit
+struct PrincipledSample {
+    base_color: Color,
+    metallic: f64,
+    roughness: f64,
+    specular: f64,
+    specular_tint: f64,
+    sheen: f64,
+    clearcoat: f64,
+    clearcoat_gloss: f64,
+    transmission: f64,
+    ior: f64,
+    luminance: f64,
+    /// Hue of the base color with its luminance normalized away
+    tint: Color,
+}
+
+/// Lobes of the Principled material
+#[derive(Clone, Copy)]
+enum Lobe {
+    Diffuse,
+    Specular,
+    Clearcoat,
+    Transmission,
+}
+
+impl Principled {
+    fn sample_parameters(&self, rec: &HitRecord) -> PrincipledSample {
+        let scalar = |texture: &Arc<dyn Texture>| texture.value(&rec.uv, &rec.p).x();
+
+        let base_color = self.base_color.value(&rec.uv, &rec.p);
+        let luminance = self.working_space.luminance(base_color);
+        let tint = if luminance > 0.0 {
+            base_color / luminance
+        } else {
+            Color::new(1.0, 1.0, 1.0)
+        };
+
+        PrincipledSample {
+            base_color,
+            metallic: f64::clamp(scalar(&self.metallic), 0.0, 1.0),
+            roughness: f64::clamp(scalar(&self.roughness), 0.0, 1.0),
+            specular: f64::max(scalar(&self.specular), 0.0),
+            specular_tint: f64::clamp(scalar(&self.specular_tint), 0.0, 1.0),
+            sheen: f64::max(scalar(&self.sheen), 0.0),
+            clearcoat: f64::max(scalar(&self.clearcoat), 0.0),
+            clearcoat_gloss: f64::clamp(scalar(&self.clearcoat_gloss), 0.0, 1.0),
+            transmission: f64::clamp(scalar(&self.transmission), 0.0, 1.0),
+            ior: scalar(&self.ior),
+            luminance,
+            tint,
+        }
+    }
+}
+
+impl PrincipledSample {
+    /// Scale of the diffuse lobe. The transmissive part of the base replaces both its diffuse and specular lobes.
+    fn diffuse_weight(&self) -> f64 {
+        (1.0 - self.metallic) * (1.0 - self.transmission)
+    }
+
+    /// Returns the diffuse and sheen lobes reflecting light arriving from wi towards wo, times pi, with both
+    /// directions in the local frame
+    fn diffuse(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        let wh = unit_vector(*wi + *wo);
+        let cos_d = dot(wi, &wh);
+
+        // Burley's diffuse with retro-reflection at grazing angles, plus sheen
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let fl = 1.0 + (fd90 - 1.0) * f64::powi(1.0 - wi.z(), 5);
+        let fv = 1.0 + (fd90 - 1.0) * f64::powi(1.0 - wo.z(), 5);
+        let sheen_color = lerp(&Color::new(1.0, 1.0, 1.0), &self.tint, 0.5);
+        let sheen = self.sheen * f64::powi(1.0 - cos_d, 5) * std::f64::consts::PI;
+
+        self.diffuse_weight() * (self.base_color * (fl * fv) + sheen * sheen_color)
+    }
+}
+
+impl Material for Principled {
+    fn apply(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene, depth: i32) -> Color {
+        // Point lights can only be reached from the diffuse lobe, which is evaluated towards each of them
+        let direct = scene.lights.reflected(r_in, rec, scene);
+
+        let bounced = match self.scatter(r_in, rec) {
+            Some(scattered) => scattered.attenuation * scene.ray_color(&scattered.ray, depth),
+            None => zero_vec(),
+        };
+
+        direct + bounced
+    }
+
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let params = self.sample_parameters(rec);
+
+        let onb = Onb::build_from_w(&rec.normal);
+        let wo = onb.to_local(&-unit_vector(r_in.direction));
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let luminance = |color: &Color| self.working_space.luminance(*color);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let dielectric_f0 =
+            0.08 * params.specular * lerp(&white, &params.tint, params.specular_tint);
+        let f0 = lerp(&dielectric_f0, &params.base_color, params.metallic);
+
+        // Scale of each lobe
+        let diffuse_weight = params.diffuse_weight();
+        let specular_weight = 1.0 - (1.0 - params.metallic) * params.transmission;
+        let transmission_weight = (1.0 - params.metallic) * params.transmission;
+        let clearcoat_weight = 0.25 * params.clearcoat;
+
+        // Pick one lobe, with probabilities based on its rough share of the reflected energy
+        let lobes = [
+            (
+                Lobe::Diffuse,
+                diffuse_weight * f64::max(params.luminance, 0.05),
+            ),
+            (
+                Lobe::Specular,
+                specular_weight * luminance(&schlick(&f0, wo.z())),
+            ),
+            (
+                Lobe::Clearcoat,
+                clearcoat_weight * luminance(&schlick(&Color::new(0.04, 0.04, 0.04), wo.z())),
+            ),
+            (Lobe::Transmission, transmission_weight),
+        ];
+        let total: f64 = lobes.iter().map(|lobe| lobe.1).sum();
+        if total <= 0.0 {
+            return None;
+        }
+
+        let mut pick = random_float_1() * total;
+        let mut lobe = lobes[0];
+        for candidate in lobes.iter().filter(|lobe| lobe.1 > 0.0) {
+            lobe = *candidate;
+            if pick < candidate.1 {
+                break;
+            }
+            pick -= candidate.1;
+        }
+        let probability = lobe.1 / total;
+
+        let (wi, weight, specular) = match lobe.0 {
+            Lobe::Diffuse => {
+                let wi = random_cosine_direction();
+                (wi, params.diffuse(&wo, &wi), false)
+            }
+            Lobe::Specular => {
+                let distribution = TrowbridgeReitz::new(params.roughness, params.roughness);
+                let wm = distribution.sample_visible(&wo, random_float_1(), random_float_1());
+                let wi = reflect(&-wo, &wm);
+                if wi.z() <= 0.0 {
+                    return None;
+                }
+
+                let fresnel = schlick(&f0, dot(&wo, &wm));
+                let weight =
+                    specular_weight * fresnel * (distribution.g(&wo, &wi) / distribution.g1(&wo));
+                (wi, weight, true)
+            }
+            Lobe::Clearcoat => {
+                let alpha = 0.1 + (0.001 - 0.1) * params.clearcoat_gloss;
+                let distribution = TrowbridgeReitz::new(f64::sqrt(alpha), f64::sqrt(alpha));
+                let wm = distribution.sample_visible(&wo, random_float_1(), random_float_1());
+                let wi = reflect(&-wo, &wm);
+                if wi.z() <= 0.0 {
+                    return None;
+                }
+
+                let fresnel = schlick(&Color::new(0.04, 0.04, 0.04), dot(&wo, &wm));
+                let weight =
+                    clearcoat_weight * fresnel * (distribution.g(&wo, &wi) / distribution.g1(&wo));
+                (wi, weight, true)
+            }
+            Lobe::Transmission => {
+                // Rough glass, reflecting or refracting with probability equal to the Fresnel reflectance.
+                // Refraction is tinted by the base color.
+                let eta = if rec.front_face {
+                    params.ior
+                } else {
+                    1.0 / params.ior
+                };
+                let distribution = TrowbridgeReitz::new(params.roughness, params.roughness);
+                let wm = distribution.sample_visible(&wo, random_float_1(), random_float_1());
+                let cos_i = dot(&wo, &wm);
+
+                let reflected = fresnel_dielectric(cos_i, eta) > random_float_1();
+                let (wi, tint) = if reflected {
+                    (reflect(&-wo, &wm), white)
+                } else {
+                    let cos_t = f64::sqrt(1.0 - (1.0 - cos_i * cos_i) / (eta * eta));
+                    (-wo / eta + (cos_i / eta - cos_t) * wm, params.base_color)
+                };
+                // Reflections have to stay above the surface and refractions have to cross it
+                if reflected != (wi.z() > 0.0) {
+                    return None;
+                }
+
+                let weight =
+                    transmission_weight * tint * (distribution.g(&wo, &wi) / distribution.g1(&wo));
+                (wi, weight, true)
+            }
+        };
+
+        Some(ScatterRecord {
+            ray: Ray {
+                origin: rec.p,
+                direction: onb.local(&wi),
+                time: r_in.time,
+                wavelength: r_in.wavelength,
+            },
+            attenuation: weight / probability,
+            specular,
+        })
+    }
+
+    /// Only the diffuse and sheen lobes can be reached by point lights and photons
+    fn brdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        let onb = Onb::build_from_w(&rec.normal);
+        let wo = onb.to_local(&-unit_vector(r_in.direction));
+        let wi = onb.to_local(&unit_vector(*direction));
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return zero_vec();
+        }
+
+        self.sample_parameters(rec).diffuse(&wo, &wi)
+    }
+}
+
+/// Schlick's approximation of the Fresnel reflectance, per channel
+fn schlick(f0: &Color, cos_theta: f64) -> Color {
+    let white = Color::new(1.0, 1.0, 1.0);
+    *f0 + (white - *f0) * f64::powi(1.0 - f64::clamp(cos_theta, 0.0, 1.0), 5)
+}
+
+fn lerp(a: &Vec3, b: &Vec3, t: f64) -> Vec3 {
+    (1.0 - t) * *a + t * *b
+}
@@ -0,0 +1,243 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use crate::texture::{Texture, TextureCoord};
+use crate::vector::{cross, dot, unit_vector, zero_vec, Color, Point3, Vec3};
+
+/// Space a projection reads hit points and normals in
+#[derive(Clone, Copy)]
+pub enum Space {
+    /// The shape's own space, so the texture moves with the shape's transforms
+    Object,
+    /// World space, so shapes move through the texture
+    World,
+}
+
+/// How texture coordinates are made from a hit
+pub enum Projection {
+    /// The texture coordinates of the shape
+    Uv,
+    /// Projects the hit point along an axis onto the plane perpendicular to it
+    Planar,
+    /// Latitude and longitude of the direction from the center, with the axis as the pole
+    Spherical,
+    /// Angle around the axis and height along it, from the center
+    Cylindrical,
+    /// Planar projections along x, y and z, weighted by how much the normal faces each of them. blend in (0, 1]
+    /// widens the transitions between them, 0 switches sharply.
+    Triplanar { blend: f64 },
+}
+
+/// Scale, rotation, offset and flip applied to texture coordinates after projecting them
+pub struct UvTransform {
+    pub scale: (f64, f64),
+    pub offset: (f64, f64),
+    /// Counterclockwise, in degrees, around (0.5, 0.5)
+    pub rotation: f64,
+    pub flip_u: bool,
+    pub flip_v: bool,
+    /// Offset added per second, sliding the texture across the surface over time
+    pub scroll: (f64, f64),
+}
+
+impl UvTransform {
+    /// Transforms a point in texture space at a given time
+    fn point(&self, u: f64, v: f64, time: f64) -> (f64, f64) {
+        let (du, dv) = self.vector(u - 0.5, v - 0.5);
+        (
+            du + 0.5 * self.scale.0 + self.offset.0 + self.scroll.0 * time,
+            dv + 0.5 * self.scale.1 + self.offset.1 + self.scroll.1 * time,
+        )
+    }
+
+    /// Transforms a difference between texture coordinates, leaving out the offset
+    fn vector(&self, du: f64, dv: f64) -> (f64, f64) {
+        let du = if self.flip_u { -du } else { du };
+        let dv = if self.flip_v { -dv } else { dv };
+        let (sin, cos) = f64::sin_cos(self.rotation.to_radians());
+        (
+            self.scale.0 * (cos * du - sin * dv),
+            self.scale.1 * (sin * du + cos * dv),
+        )
+    }
+}
+
+/// Samples a texture with texture coordinates from a projection instead of the shape's own
+pub struct Projected {
+    texture: Arc<dyn Texture>,
+    projection: Projection,
+    space: Space,
+    /// Projection axis. Its frame gives the directions of u and v.
+    axis: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+    center: Point3,
+    transform: UvTransform,
+}
+
+impl Projected {
+    pub fn new(
+        texture: Arc<dyn Texture>,
+        projection: Projection,
+        space: Space,
+        axis: Vec3,
+        center: Point3,
+        transform: UvTransform,
+    ) -> Projected {
+        let (axis, tangent, bitangent) = axis_frame(&axis);
+        Projected {
+            texture,
+            projection,
+            space,
+            axis,
+            tangent,
+            bitangent,
+            center,
+            transform,
+        }
+    }
+
+    /// Returns the texture coordinates of a point relative to the center
+    fn project(&self, d: &Vec3) -> (f64, f64) {
+        let x = dot(d, &self.tangent);
+        let y = dot(d, &self.bitangent);
+        let z = dot(d, &self.axis);
+        match self.projection {
+            // Shape coordinates and triplanar projections are handled in value
+            Projection::Planar | Projection::Uv | Projection::Triplanar { .. } => (x, y),
+            Projection::Spherical => {
+                let length = d.length();
+                if length == 0.0 {
+                    return (0.0, 0.0);
+                }
+                let theta = f64::acos(f64::clamp(-z / length, -1.0, 1.0));
+                let phi = f64::atan2(-y, x) + PI;
+                (phi / (2.0 * PI), theta / PI)
+            }
+            Projection::Cylindrical => {
+                let phi = f64::atan2(-y, x) + PI;
+                (phi / (2.0 * PI), z)
+            }
+        }
+    }
+
+    /// Returns the texture coordinates with a pixel footprint the projection can use. Footprints are in world
+    /// space, so they only carry over to world space projections.
+    fn footprint(&self, uv: &TextureCoord) -> TextureCoord {
+        let mut footprint = uv.clone();
+        if let Space::Object = self.space {
+            footprint.dpdx = zero_vec();
+            footprint.dpdy = zero_vec();
+        }
+        footprint
+    }
+
+    /// Looks up the texture with coordinates projected from a point, carrying the pixel footprint over by
+    /// projecting the neighbouring pixels' points too
+    fn sample(
+        &self,
+        uv: &TextureCoord,
+        p: &Point3,
+        project: &dyn Fn(&Vec3) -> (f64, f64),
+    ) -> Color {
+        let (u, v) = project(&(*p - self.center));
+        let mut projected = uv.clone();
+        (projected.u, projected.v) = self.transform.point(u, v, uv.time);
+
+        let periodic = matches!(
+            self.projection,
+            Projection::Spherical | Projection::Cylindrical
+        );
+        let derivative = |dp: &Vec3| {
+            if dp.near_zero() {
+                return (0.0, 0.0);
+            }
+            let (u1, v1) = project(&(*p + *dp - self.center));
+            let mut du = u1 - u;
+            // Neighbours across the seam of a periodic u are close, not a whole texture apart
+            if periodic {
+                du -= du.round();
+            }
+            self.transform.vector(du, v1 - v)
+        };
+        (projected.dudx, projected.dvdx) = derivative(&uv.dpdx);
+        (projected.dudy, projected.dvdy) = derivative(&uv.dpdy);
+
+        self.texture.value(&projected, p)
+    }
+}
+
+impl Texture for Projected {
+    fn value(&self, uv: &TextureCoord, p: &Point3) -> Color {
+        let (point, normal) = match self.space {
+            Space::Object => (uv.object_p, uv.object_normal),
+            Space::World => (*p, uv.normal),
+        };
+
+        match self.projection {
+            Projection::Uv => {
+                let mut transformed = uv.clone();
+                (transformed.u, transformed.v) = self.transform.point(uv.u, uv.v, uv.time);
+                (transformed.dudx, transformed.dvdx) = self.transform.vector(uv.dudx, uv.dvdx);
+                (transformed.dudy, transformed.dvdy) = self.transform.vector(uv.dudy, uv.dvdy);
+                self.texture.value(&transformed, p)
+            }
+            Projection::Triplanar { blend } => {
+                let footprint = self.footprint(uv);
+                let weights = triplanar_weights(&normal, blend);
+                let axes = [
+                    (Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 0.0)),
+                    (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0)),
+                    (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+                ];
+                let mut color = Color::new(0.0, 0.0, 0.0);
+                for (weight, (tangent, bitangent)) in weights.iter().zip(axes) {
+                    if *weight > 0.0 {
+                        let project = |d: &Vec3| (dot(d, &tangent), dot(d, &bitangent));
+                        color += *weight * self.sample(&footprint, &point, &project);
+                    }
+                }
+                color
+            }
+            _ => self.sample(&self.footprint(uv), &point, &|d: &Vec3| self.project(d)),
+        }
+    }
+}
+
+/// Returns a unit axis along with unit u and v directions perpendicular to it. Axes along x, y and z give the
+/// u and v directions of looking at the matching side of a box.
+fn axis_frame(axis: &Vec3) -> (Vec3, Vec3, Vec3) {
+    let axis = unit_vector(*axis);
+    let tangent = if f64::abs(axis.y()) > 0.999 {
+        Vec3::new(1.0, 0.0, 0.0)
+    } else {
+        unit_vector(cross(&Vec3::new(0.0, 1.0, 0.0), &axis))
+    };
+    let bitangent = cross(&axis, &tangent);
+    (axis, tangent, bitangent)
+}
+
+/// Returns the weights of the x, y and z planar projections for a normal, summing to 1
+fn triplanar_weights(normal: &Vec3, blend: f64) -> [f64; 3] {
+    let n = [normal.x().abs(), normal.y().abs(), normal.z().abs()];
+    if blend <= 0.0 {
+        let mut weights = [0.0; 3];
+        let largest = if n[0] >= n[1] && n[0] >= n[2] {
+            0
+        } else if n[1] >= n[2] {
+            1
+        } else {
+            2
+        };
+        weights[largest] = 1.0;
+        return weights;
+    }
+
+    let sharpness = 1.0 / blend;
+    let weights = n.map(|n| n.powf(sharpness));
+    let total: f64 = weights.iter().sum();
+    if total == 0.0 {
+        return [1.0 / 3.0; 3];
+    }
+    weights.map(|w| w / total)
+}
@@ -1,16 +1,35 @@
 use crate::vector::{Point3, Vec3};
 
+#[derive(Clone)]
 pub struct Ray {
     pub origin: Point3,
     pub direction: Vec3,
+    /// Time the ray was cast at, within the camera's shutter interval
+    pub time: f64,
+    /// Hero wavelength in nm carried by spectral paths, None when rendering in rgb
+    pub wavelength: Option<f64>,
 }
 
 impl Ray {
-    pub fn new(origin: Point3, direction: Vec3) -> Ray {
-        Ray { origin, direction }
+    pub fn new(origin: Point3, direction: Vec3, time: f64) -> Ray {
+        Ray {
+            origin,
+            direction,
+            time,
+            wavelength: None,
+        }
     }
     /// Return position of ray at time t
     pub fn at(&self, t: f64) -> Point3 {
         self.origin + t * self.direction
     }
 }
+
+/// Rays offset by one pixel in x and y from a camera ray, used to estimate the footprint of the pixel on the
+/// surfaces the camera ray hits
+pub struct RayDifferential {
+    pub rx_origin: Point3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Point3,
+    pub ry_direction: Vec3,
+}
@@ -7,22 +7,58 @@ use std::sync::Arc;
 
 use json::{self, JsonValue};
 
+use crate::animation;
+use crate::assets::{self, load_image, ImageChannel, ImageOptions, TexelFormat};
 use crate::background::{Background, BackgroundColor, GradientY};
+use crate::bake::{BakeMode, BakeSettings};
 use crate::buffer::Buffer;
 use crate::bvh::BVHNode;
 use crate::camera::Camera;
 use crate::camera::PerspectiveCamera;
-use crate::hit::{Hittable, HittableList};
-use crate::instance::{RotateY, Translate};
+use crate::camera::Shutter;
+use crate::color::{ColorSpace, WorkingSpace};
+use crate::cutout::{AlphaMode, Cutout, Masked, OpacityMask, CUTOUT_EPSILON};
+use crate::hit::{HitRecord, Hittable, HittableList};
+use crate::instance::{Keyframe, KeyframedTransform, RotateY, Translate};
 use crate::lights::{LightList, PointLight};
-use crate::materials::{BlinnPhong, Dielectric, Diffuse, Emissive, Lambertian, Material, Metal};
-use crate::ray::Ray;
-use crate::shapes::{self, Sphere, Triangle, XYRect, XZRect, YZRect};
-use crate::texture::{Checker, ImageTexture, NoiseTexture, SolidColor, Texture};
+use crate::materials::{
+    BlinnPhong, Coated, Conductor, Dielectric, Diffuse, Emissive, Ior, Lambertian, Material, Metal,
+    Mix, MixMode, OrenNayar, RoughDielectric,
+};
+use crate::mipmap::{FilterMode, WrapMode};
+use crate::noise::{Basis, Fractal, FractalNoise, Noise, WorleyDistance};
+use crate::normal_map::{NormalMap, NormalMapped};
+use crate::patterns::{
+    Bricks, Gradient, GradientShape, Marble, Stripes, TileShape, Tiles, UvChecker, Wood,
+};
+use crate::photon::SppmSettings;
+use crate::principled::Principled;
+use crate::projection::{Projected, Projection, Space, UvTransform};
+use crate::ray::{Ray, RayDifferential};
+use crate::shapes::{self, MovingSphere, Sphere, Triangle, XYRect, XZRect, YZRect};
+use crate::subsurface::Subsurface;
+use crate::texture::{
+    Arithmetic, Channel, ChannelSelect, Checker, ColorRamp, DomainWarp, FractalTexture, HsvAdjust,
+    ImageSequence, ImageTexture, Invert, MixTexture, NoiseTexture, Operation, RampInterpolation,
+    Remap, SolidColor, Texture,
+};
 use crate::utility::{random_float_1, INFINITY};
 use crate::vector::{quick_vec, zero_vec, Color, Vec3};
 use crate::volumes::ConstantMedium;
 
+/// Path renders are written to. Frames of an animation are numbered from it.
+pub const OUTPUT_PATH: &str = "image.png";
+
+/// Algorithm used to render the scene
+pub enum Integrator {
+    /// Recursive path tracing through Material::apply
+    PathTracer,
+    /// Stochastic progressive photon mapping
+    Sppm(SppmSettings),
+    /// Path tracing with sampled wavelengths instead of rgb
+    Spectral,
+}
+
 pub struct Scene {
     camera: Arc<dyn Camera>,
     pub objects: Arc<HittableList>,
@@ -33,6 +69,13 @@ pub struct Scene {
     max_depth: i32,
     background: Arc<dyn Background>,
     bvh_root: BVHNode,
+    integrator: Integrator,
+    /// Linear rgb space colors are rendered in
+    pub working_space: WorkingSpace,
+    /// Objects with a name in the scene file, with their modifiers applied
+    pub named_objects: HashMap<String, Arc<dyn Hittable>>,
+    /// What to bake when the scene is baked rather than rendered
+    pub bake: Option<BakeSettings>,
 }
 
 impl Scene {
@@ -46,7 +89,8 @@ impl Scene {
         max_depth: i32,
         background: Arc<dyn Background>,
     ) -> Scene {
-        let bvh_root = BVHNode::new(&objects.objects, 0);
+        let (time0, time1) = camera.shutter();
+        let bvh_root = BVHNode::new(&objects.objects, 0, time0, time1);
         Scene {
             camera,
             objects,
@@ -57,6 +101,29 @@ impl Scene {
             max_depth,
             background,
             bvh_root,
+            integrator: Integrator::PathTracer,
+            working_space: WorkingSpace::Rec709,
+            named_objects: HashMap::new(),
+            bake: None,
+        }
+    }
+
+    /// Returns the color of a camera ray. Unlike ray_color the first hit knows its pixel footprint, so textures can
+    /// be filtered.
+    ///
+    /// differential: Rays offset from r by one pixel in x and y, as returned by get_ray_differential. They set the
+    /// texture footprint of the first hit only.
+    ///
+    /// depth: Current recursion depth, as in ray_color.
+    pub fn camera_ray_color(&self, r: &Ray, differential: &RayDifferential, depth: i32) -> Color {
+        let depth = depth - 1;
+        if depth <= 0 {
+            return zero_vec();
+        }
+
+        match self.hit_from_camera(r, differential) {
+            Some(rec) => rec.material.apply(r, &rec, self, depth),
+            None => self.background.apply(r.direction),
         }
     }
 
@@ -72,7 +139,7 @@ impl Scene {
             return zero_vec();
         }
 
-        match self.bvh_root.hit(r, 0.001, INFINITY) {
+        match self.hit(r, 0.001, INFINITY) {
             Some(rec) => {
                 return rec.material.apply(r, &rec, &self, depth);
             }
@@ -82,17 +149,78 @@ impl Scene {
         return self.background.apply(r.direction);
     }
 
-    pub fn any_hit(&self, r: &Ray, _t_min: f64, _t_max: f64) -> bool {
-        match self.bvh_root.hit(r, 0.001, INFINITY) {
-            Some(_) => return true,
-            None => return false,
+    /// Returns the closest intersection with the scene in range [t_min, t_max], skipping surfaces whose material
+    /// is cut out at the hit
+    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut t_min = t_min;
+        loop {
+            let mut rec = self.bvh_root.hit(r, t_min, t_max)?;
+            rec.uv.normal = if rec.front_face {
+                rec.normal
+            } else {
+                -rec.normal
+            };
+            if rec.material.opaque(&rec) {
+                return Some(rec);
+            }
+            t_min = rec.t + CUTOUT_EPSILON;
         }
     }
 
+    /// Returns the background color for a ray direction
+    pub fn background_color(&self, dir: Vec3) -> Color {
+        self.background.apply(dir)
+    }
+
+    /// Returns image width
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    /// Returns image height
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    /// Returns the number of samples per pixel
+    pub fn samples(&self) -> i32 {
+        self.samples
+    }
+
+    /// Returns the maximum recursion depth of a path
+    pub fn max_depth(&self) -> i32 {
+        self.max_depth
+    }
+
+    /// Returns true if anything blocks the ray in range [t_min, t_max]
+    pub fn any_hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.hit(r, t_min, t_max).is_some()
+    }
+
     pub fn get_ray(&self, s: f64, t: f64) -> Ray {
         self.camera.get_ray(s, t)
     }
 
+    /// Returns a camera ray along with its differentials for a pixel of size ds by dt. The differentials shrink
+    /// with the sample count, since the samples of a pixel already average over its area.
+    pub fn get_ray_differential(&self, s: f64, t: f64, ds: f64, dt: f64) -> (Ray, RayDifferential) {
+        let scale = f64::max(1.0 / f64::sqrt(self.samples as f64), 0.125);
+        self.camera
+            .get_ray_differential(s, t, ds * scale, dt * scale)
+    }
+
+    /// Returns the closest hit of a camera ray, with the texture footprint of its pixel
+    pub fn hit_from_camera(&self, r: &Ray, differential: &RayDifferential) -> Option<HitRecord> {
+        let mut rec = self.hit(r, 0.001, INFINITY)?;
+        rec.set_texture_footprint(differential);
+        Some(rec)
+    }
+
+    /// Returns the camera's shutter open and close times
+    pub fn shutter(&self) -> (f64, f64) {
+        self.camera.shutter()
+    }
+
     /// Converts a string to a vector.
     ///
     /// Syntax is "[x] [y] [z]" to vector of (x, y, z)
@@ -107,12 +235,337 @@ impl Scene {
         )
     }
 
+    /// Parses the index of refraction of a dielectric material entry. It is one of
+    /// - "ir": constant index
+    /// - "ior_data": list of [wavelength in nm, index] pairs
+    /// - "cauchy": { "a", "b", "c" } coefficients with wavelengths in µm
+    /// - "sellmeier": { "b": "[b1] [b2] ...", "c": "[c1] [c2] ..." } with wavelengths in µm
+    /// - "preset": name of a known material (bk7, fused_silica, sf11, diamond, water)
+    fn parse_ior(entry: &JsonValue) -> Ior {
+        if entry.has_key("ior_data") {
+            let table: Vec<(f64, f64)> = entry["ior_data"]
+                .members()
+                .map(|pair| (pair[0].as_f64().unwrap(), pair[1].as_f64().unwrap()))
+                .collect();
+            if table.is_empty() {
+                panic!("ior_data needs at least one (wavelength, index) pair");
+            }
+            if table.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+                panic!("ior_data wavelengths must be strictly increasing");
+            }
+            Ior::Tabulated(table)
+        } else if entry.has_key("cauchy") {
+            let cauchy = &entry["cauchy"];
+            Ior::Cauchy {
+                a: cauchy["a"].as_f64().unwrap(),
+                b: cauchy["b"].as_f64().unwrap_or(0.0),
+                c: cauchy["c"].as_f64().unwrap_or(0.0),
+            }
+        } else if entry.has_key("sellmeier") {
+            let parse = |key: &str| -> Vec<f64> {
+                entry["sellmeier"][key]
+                    .as_str()
+                    .unwrap()
+                    .split_whitespace()
+                    .map(|c| c.parse::<f64>().unwrap())
+                    .collect()
+            };
+            let b = parse("b");
+            let c = parse("c");
+            if b.len() != c.len() {
+                panic!("Sellmeier coefficients b and c must have the same length");
+            }
+            Ior::Sellmeier(b.into_iter().zip(c).collect())
+        } else if entry.has_key("preset") {
+            let preset = entry["preset"].as_str().unwrap();
+            Ior::preset(preset).unwrap_or_else(|| panic!("Unknown ior preset {}", preset))
+        } else {
+            Ior::Constant(entry["ir"].as_f64().unwrap())
+        }
+    }
+
+    /// Returns a material parameter that can be driven by a texture. The parameter is either a number, or the name
+    /// of a texture. Missing parameters use a solid default.
+    fn texture_parameter(
+        entry: &JsonValue,
+        key: &str,
+        textures: &HashMap<String, Arc<dyn Texture>>,
+        default: Color,
+    ) -> Arc<dyn Texture> {
+        if let Some(name) = entry[key].as_str() {
+            return match textures.get(name) {
+                Some(texture) => Arc::clone(texture),
+                None => panic!("Unknown texture {}", name),
+            };
+        }
+
+        match entry[key].as_f64() {
+            Some(value) => Arc::new(SolidColor::new(quick_vec(value, value, value))),
+            None => Arc::new(SolidColor::new(default)),
+        }
+    }
+
+    /// Wraps a material in the modifiers of its entry: a normal map ("normal_map") or bump map ("bump_map" and
+    /// "bump_strength"), and an opacity mask. Maps are texture names.
+    fn material_modifiers(
+        entry: &JsonValue,
+        material: Arc<dyn Material>,
+        textures: &HashMap<String, Arc<dyn Texture>>,
+    ) -> Arc<dyn Material> {
+        let texture = |key: &str| {
+            let name = entry[key].as_str().unwrap();
+            match textures.get(name) {
+                Some(texture) => Arc::clone(texture),
+                None => panic!("Unknown texture {}", name),
+            }
+        };
+
+        let mut material = material;
+        if entry.has_key("normal_map") {
+            material = Arc::new(NormalMapped::new(
+                material,
+                NormalMap::Tangent(texture("normal_map")),
+            ));
+        } else if entry.has_key("bump_map") {
+            material = Arc::new(NormalMapped::new(
+                material,
+                NormalMap::Bump {
+                    height: texture("bump_map"),
+                    strength: entry["bump_strength"].as_f64().unwrap_or(1.0),
+                },
+            ));
+        }
+
+        if let Some(mask) = Scene::opacity_mask(entry, textures) {
+            material = Arc::new(Masked::new(material, mask));
+        }
+        material
+    }
+
+    /// Parses the opacity mask of a shape or material, if it has one. "opacity" is a texture name, and
+    /// "alpha_mode" is either "threshold" (the default, cutting out wherever the opacity is below
+    /// "alpha_threshold") or "stochastic".
+    fn opacity_mask(
+        entry: &JsonValue,
+        textures: &HashMap<String, Arc<dyn Texture>>,
+    ) -> Option<OpacityMask> {
+        let name = entry["opacity"].as_str()?;
+        let opacity = match textures.get(name) {
+            Some(texture) => Arc::clone(texture),
+            None => panic!("Unknown texture {}", name),
+        };
+        let mode = match entry["alpha_mode"].as_str() {
+            Some("threshold") | None => {
+                AlphaMode::Threshold(entry["alpha_threshold"].as_f64().unwrap_or(0.5))
+            }
+            Some("stochastic") => AlphaMode::Stochastic,
+            Some(other) => panic!("Unknown alpha mode {}", other),
+        };
+        Some(OpacityMask::new(opacity, mode))
+    }
+
+    /// Parses a coated material wrapping a base material by name
+    fn coated_material(
+        entry: &JsonValue,
+        materials: &HashMap<String, Arc<dyn Material>>,
+        textures: &HashMap<String, Arc<dyn Texture>>,
+    ) -> Arc<dyn Material> {
+        let base = Arc::clone(&materials[entry["base"].as_str().unwrap()]);
+        let roughness = Scene::texture_parameter(entry, "roughness", textures, zero_vec());
+        let ior = entry["ior"].as_f64().unwrap_or(1.5);
+        let thickness = entry["thickness"].as_f64().unwrap_or(1.0);
+        let tint = Scene::texture_parameter(entry, "tint", textures, quick_vec(1.0, 1.0, 1.0));
+        Arc::new(Coated::new(base, roughness, ior, thickness, tint))
+    }
+
+    /// Parses a mix of materials referenced by name. The weight is a texture name or a number, and the mode is
+    /// either "stochastic" (the default) or "blend".
+    fn mix_material(
+        entry: &JsonValue,
+        materials: &HashMap<String, Arc<dyn Material>>,
+        textures: &HashMap<String, Arc<dyn Texture>>,
+    ) -> Arc<dyn Material> {
+        let mixed: Vec<Arc<dyn Material>> = entry["materials"]
+            .members()
+            .map(|name| Arc::clone(&materials[name.as_str().unwrap()]))
+            .collect();
+        if mixed.is_empty() {
+            panic!("Mix material {} needs at least one material", entry["name"]);
+        }
+        let weight = Scene::texture_parameter(entry, "weight", textures, quick_vec(0.5, 0.5, 0.5));
+        let mode = match entry["mode"].as_str() {
+            Some("stochastic") | None => MixMode::Stochastic,
+            Some("blend") => MixMode::Blend,
+            Some(other) => panic!("Unknown mix mode {}", other),
+        };
+        Arc::new(Mix::new(mixed, weight, mode))
+    }
+
+    /// Wraps a texture in the texture coordinate options of its entry. "projection" is one of "uv" (default),
+    /// "planar", "spherical", "cylindrical" or "triplanar", set up by "space" ("object" or "world"), "axis" ("x",
+    /// "y", "z" or a vector), "center" and "blend" (triplanar). The projected coordinates are then transformed by
+    /// "uv_scale" (a number or "[u] [v]"), "uv_rotation" (degrees), "uv_offset" ("[u] [v]"), "flip_u",
+    /// "flip_v" and "uv_scroll" ("[u] [v]" added to the offset per second).
+    fn texture_modifiers(entry: &JsonValue, texture: Arc<dyn Texture>) -> Arc<dyn Texture> {
+        let keys = [
+            "projection",
+            "uv_scale",
+            "uv_rotation",
+            "uv_offset",
+            "flip_u",
+            "flip_v",
+            "uv_scroll",
+        ];
+        if !keys.iter().any(|key| entry.has_key(key)) {
+            return texture;
+        }
+
+        let transform = UvTransform {
+            scale: Scene::parse_pair(entry, "uv_scale", (1.0, 1.0)),
+            offset: Scene::parse_pair(entry, "uv_offset", (0.0, 0.0)),
+            rotation: entry["uv_rotation"].as_f64().unwrap_or(0.0),
+            flip_u: entry["flip_u"].as_bool().unwrap_or(false),
+            flip_v: entry["flip_v"].as_bool().unwrap_or(false),
+            scroll: Scene::parse_pair(entry, "uv_scroll", (0.0, 0.0)),
+        };
+
+        let projection = match entry["projection"].as_str() {
+            Some("uv") | None => Projection::Uv,
+            Some("planar") => Projection::Planar,
+            Some("spherical") => Projection::Spherical,
+            Some("cylindrical") => Projection::Cylindrical,
+            Some("triplanar") => Projection::Triplanar {
+                blend: entry["blend"].as_f64().unwrap_or(0.2),
+            },
+            Some(other) => panic!("Unknown projection {}", other),
+        };
+        let space = match entry["space"].as_str() {
+            Some("object") | None => Space::Object,
+            Some("world") => Space::World,
+            Some(other) => panic!("Unknown texture space {}", other),
+        };
+        // Planar projections look along z by default, the others have their poles along y
+        let axis = match entry["axis"].as_str() {
+            Some("x") => Vec3::new(1.0, 0.0, 0.0),
+            Some("y") => Vec3::new(0.0, 1.0, 0.0),
+            Some("z") => Vec3::new(0.0, 0.0, 1.0),
+            Some(axis) => Scene::string_to_vec(axis),
+            None => match projection {
+                Projection::Planar => Vec3::new(0.0, 0.0, 1.0),
+                _ => Vec3::new(0.0, 1.0, 0.0),
+            },
+        };
+        let center = match entry["center"].as_str() {
+            Some(center) => Scene::string_to_vec(center),
+            None => zero_vec(),
+        };
+
+        Arc::new(Projected::new(
+            texture, projection, space, axis, center, transform,
+        ))
+    }
+
+    /// Parses a pair of values in u and v, given either as one number for both or as "[u] [v]"
+    fn parse_pair(entry: &JsonValue, key: &str, default: (f64, f64)) -> (f64, f64) {
+        if let Some(value) = entry[key].as_f64() {
+            return (value, value);
+        }
+        match entry[key].as_str() {
+            Some(string) => {
+                let values: Vec<f64> = string
+                    .split_whitespace()
+                    .map(|value| value.parse::<f64>().unwrap())
+                    .collect();
+                match values.as_slice() {
+                    [u, v] => (*u, *v),
+                    _ => panic!("{} must be a number or \"[u] [v]\"", key),
+                }
+            }
+            None => default,
+        }
+    }
+
+    /// Parses an input of a pattern texture, which is the name of a texture, a color string or a number. Colors
+    /// are converted to the working space like other scene colors.
+    fn pattern_input(
+        entry: &JsonValue,
+        key: &str,
+        textures: &HashMap<String, Arc<dyn Texture>>,
+        working_space: WorkingSpace,
+        default: &str,
+    ) -> Arc<dyn Texture> {
+        if let Some(value) = entry[key].as_f64() {
+            return Arc::new(SolidColor::new(quick_vec(value, value, value)));
+        }
+        let value = entry[key].as_str().unwrap_or(default);
+        if let Some(texture) = textures.get(value) {
+            return Arc::clone(texture);
+        }
+
+        // Anything that isn't three numbers is taken to be a misspelt or missing texture
+        let components: Vec<&str> = value.split(' ').collect();
+        if components.len() != 3 || components.iter().any(|c| c.parse::<f64>().is_err()) {
+            panic!(
+                "Unknown texture {} for {} of {}, which isn't a color \"[r] [g] [b]\" either",
+                value, key, entry["name"]
+            );
+        }
+        Arc::new(SolidColor::new(
+            working_space.to_working(Scene::string_to_vec(value)),
+        ))
+    }
+
+    /// Parses the noise settings of a fractal, warp or pattern texture
+    fn fractal_noise(entry: &JsonValue, default_fractal: Fractal) -> FractalNoise {
+        let basis = match entry["noise"].as_str() {
+            Some("perlin") | None => Basis::Perlin,
+            Some("simplex") => Basis::Simplex,
+            Some("worley") => Basis::Worley(match entry["distance"].as_str() {
+                Some("f1") | None => WorleyDistance::F1,
+                Some("f2") => WorleyDistance::F2,
+                Some("f2-f1") => WorleyDistance::F2MinusF1,
+                Some(other) => panic!("Unknown Worley distance {}", other),
+            }),
+            Some(other) => panic!("Unknown noise {}", other),
+        };
+        let fractal = match entry["fractal"].as_str() {
+            None => default_fractal,
+            Some("fbm") => Fractal::Fbm,
+            Some("none") => Fractal::None,
+            Some("turbulence") => Fractal::Turbulence,
+            Some("ridged") => Fractal::Ridged,
+            Some(other) => panic!("Unknown fractal {}", other),
+        };
+        let seed = entry["seed"].as_u64().unwrap_or(0);
+
+        FractalNoise::new(
+            Noise::new(basis, seed),
+            fractal,
+            entry["octaves"].as_u32().unwrap_or(5),
+            entry["lacunarity"].as_f64().unwrap_or(2.0),
+            entry["gain"].as_f64().unwrap_or(0.5),
+        )
+    }
+
+    /// Parses the space a procedural texture is evaluated in. World space by default, like the noise texture.
+    fn procedural_space(entry: &JsonValue) -> Space {
+        match entry["space"].as_str() {
+            Some("world") | None => Space::World,
+            Some("object") => Space::Object,
+            Some(other) => panic!("Unknown texture space {}", other),
+        }
+    }
+
     fn modifiers(
         entry: &JsonValue,
         shape: Arc<dyn Hittable>,
         textures: &HashMap<String, Arc<dyn Texture>>,
     ) -> Arc<dyn Hittable> {
         let mut transform_shape = shape;
+        // Masks are evaluated in object space, so they move with the shape
+        if let Some(mask) = Scene::opacity_mask(entry, textures) {
+            transform_shape = Arc::new(Cutout::new(transform_shape, mask));
+        }
         if entry.has_key("rotate_y") {
             let angle = entry["rotate_y"].as_f64().unwrap();
             transform_shape = Arc::new(RotateY::new(transform_shape, angle));
@@ -123,6 +576,27 @@ impl Scene {
             transform_shape = Arc::new(Translate::new(transform_shape, translate));
         }
 
+        if entry.has_key("keyframes") {
+            let mut keyframes = Vec::new();
+            for keyframe in entry["keyframes"].members() {
+                let time = keyframe["time"].as_f64().unwrap();
+                let translate = match keyframe["translate"].as_str() {
+                    Some(translate) => Scene::string_to_vec(translate),
+                    None => zero_vec(),
+                };
+                let rotate_y = keyframe["rotate_y"].as_f64().unwrap_or(0.0);
+                keyframes.push(Keyframe {
+                    time,
+                    translate,
+                    rotate_y,
+                });
+            }
+            if keyframes.is_empty() {
+                panic!("\"keyframes\" needs at least one keyframe");
+            }
+            transform_shape = Arc::new(KeyframedTransform::new(transform_shape, keyframes));
+        }
+
         if entry.has_key("volume") {
             let details = &entry["volume"];
             let texture_name = details["texture"].as_str().unwrap().to_string();
@@ -141,6 +615,14 @@ impl Scene {
 
     /// Parses in the json scenefile at path
     pub fn read_scene_file(path: &String) -> Scene {
+        Scene::read_scene_file_at_frame(path, 1.0)
+    }
+
+    /// Parses in the json scenefile at path, evaluating animated values at a given frame.
+    ///
+    /// The camera shutter is offset by the time of the frame, (frame - 1) / fps, so that keyframed transforms and
+    /// moving objects keep moving across frames.
+    pub fn read_scene_file_at_frame(path: &String, frame: f64) -> Scene {
         // Read in scene file
         let path = Path::new(path);
         let display = path.display();
@@ -159,10 +641,14 @@ impl Scene {
             Err(why) => panic!("couldn't convert {} to json: {}", display, why),
             Ok(parsed) => parsed,
         };
+        let parsed = animation::resolve(&parsed, frame);
+        let fps = parsed["animation"]["fps"].as_f64().unwrap_or(24.0);
+        let frame_time = (frame - 1.0) / fps;
 
         if parsed.has_key("scene") {
             match parsed["scene"].as_str().unwrap() {
                 "rt:tnw_final" => return Scene::gen_final_scene(),
+                "rt:tnw_bouncing_spheres" => return Scene::gen_bouncing_spheres(),
                 _ => return Scene::gen_final_scene(),
             }
         }
@@ -181,25 +667,41 @@ impl Scene {
                 let lookat = Scene::string_to_vec(parsed_camera["lookat"].as_str().unwrap());
                 let vup = Scene::string_to_vec(parsed_camera["vup"].as_str().unwrap());
                 let vfov = parsed_camera["vfov"].as_f64().unwrap();
+                let shutter_open = parsed_camera["shutter_open"].as_f64().unwrap_or(0.0);
+                let shutter_close = parsed_camera["shutter_close"]
+                    .as_f64()
+                    .unwrap_or(shutter_open);
+                let shutter = Shutter {
+                    open: shutter_open + frame_time,
+                    close: shutter_close + frame_time,
+                };
                 width = parsed_camera["width"].as_i32().unwrap();
                 height = parsed_camera["height"].as_i32().unwrap();
                 samples = parsed_camera["samples"].as_i32().unwrap();
                 max_depth = parsed_camera["max_depth"].as_i32().unwrap();
                 let aspect_ratio = width as f64 / height as f64;
 
-                camera = Arc::new(PerspectiveCamera::new(
-                    lookfrom,
-                    lookat,
-                    vup,
-                    vfov,
-                    aspect_ratio,
-                ));
+                camera = Arc::new(
+                    PerspectiveCamera::new(lookfrom, lookat, vup, vfov, aspect_ratio)
+                        .with_shutter(shutter),
+                );
             }
             _ => {
                 panic!("Camera not defined")
             }
         };
 
+        // COLOR PARSING
+        // Colors in the scene file are linear with Rec.709 primaries, and are converted to the working space
+        let working_space = match parsed["working_space"].as_str() {
+            Some("rec709") | None => WorkingSpace::Rec709,
+            Some("acescg") => WorkingSpace::AcesCg,
+            Some(other) => panic!("Unknown working space {}", other),
+        };
+        let parse_color = |value: &JsonValue| {
+            working_space.to_working(Scene::string_to_vec(value.as_str().unwrap()))
+        };
+
         let background_parsed = &parsed["background"];
         let background_type = match background_parsed["type"].as_str() {
             Some(string) => string,
@@ -207,60 +709,308 @@ impl Scene {
         };
         let background: Arc<dyn Background> = match background_type {
             "gradientY" => {
-                let color1 = Scene::string_to_vec(background_parsed["color1"].as_str().unwrap());
-                let color2 = Scene::string_to_vec(background_parsed["color2"].as_str().unwrap());
+                let color1 = parse_color(&background_parsed["color1"]);
+                let color2 = parse_color(&background_parsed["color2"]);
                 Arc::new(GradientY::new(color1, color2))
             }
             "backgroundColor" => {
-                let color = Scene::string_to_vec(background_parsed["color"].as_str().unwrap());
+                let color = parse_color(&background_parsed["color"]);
                 Arc::new(BackgroundColor::new(color))
             }
             "" => Arc::new(BackgroundColor::new(zero_vec())),
             _ => Arc::new(BackgroundColor::new(zero_vec())),
         };
 
+        // INTEGRATOR PARSING
+        let parsed_integrator = &parsed["integrator"];
+        let integrator = match parsed_integrator["type"].as_str() {
+            Some("sppm") => Integrator::Sppm(SppmSettings {
+                iterations: parsed_integrator["iterations"].as_i32().unwrap_or(16),
+                photons: parsed_integrator["photons"].as_usize().unwrap_or(100000),
+                radius: parsed_integrator["radius"].as_f64().unwrap_or(1.0),
+                alpha: parsed_integrator["alpha"].as_f64().unwrap_or(2.0 / 3.0),
+            }),
+            Some("spectral") => Integrator::Spectral,
+            Some("path") | None => Integrator::PathTracer,
+            Some(other) => panic!("Unknown integrator {}", other),
+        };
+
         // LIGHT PARSING
         let mut lights = LightList::new();
         let parsed_lights = &parsed["lights"];
         if parsed_lights.has_key("pointLight") {
             for entry in parsed_lights["pointLight"].members() {
                 let position = Scene::string_to_vec(entry["position"].as_str().unwrap());
-                let color = Scene::string_to_vec(entry["color"].as_str().unwrap());
+                let color = parse_color(&entry["color"]);
                 lights.add(Arc::new(PointLight { position, color }));
             }
         }
 
+        // "texture_cache": {"memory_mb": [size]} bounds the memory images take up. The least recently used tiles of
+        // tiled images, and images that aren't tiled, are dropped from the cache to stay under it.
+        if let Some(memory) = parsed["texture_cache"]["memory_mb"].as_f64() {
+            assets::set_memory_budget((memory * 1024.0 * 1024.0) as usize);
+        }
+
         let mut textures: HashMap<String, Arc<dyn Texture>> = HashMap::new();
         let parsed_textures = &parsed["textures"];
 
         if parsed.has_key("textures") {
             for entry in parsed["textures"].members() {
-                match entry["type"].as_str().unwrap() {
+                let name = entry["name"].as_str().unwrap().to_string();
+                // Inputs of pattern and node textures
+                let input = |key: &str, default: &str| {
+                    Scene::pattern_input(entry, key, &textures, working_space, default)
+                };
+                let required = |key: &str| {
+                    if entry[key].is_null() {
+                        panic!("Texture {} is missing its {} input", name, key);
+                    }
+                    input(key, "")
+                };
+                let texture: Arc<dyn Texture> = match entry["type"].as_str().unwrap() {
                     "color" => {
-                        let name = entry["name"].as_str().unwrap().to_string();
-                        let color = Scene::string_to_vec(entry["color"].as_str().unwrap());
-                        textures.insert(name, Arc::new(SolidColor::new(color)));
+                        let color = parse_color(&entry["color"]);
+                        Arc::new(SolidColor::new(color))
                     }
                     "checker" => {
-                        let name = entry["name"].as_str().unwrap().to_string();
                         let odd =
                             Arc::clone(&textures[&entry["odd"].as_str().unwrap().to_string()]);
                         let even =
                             Arc::clone(&textures[&entry["even"].as_str().unwrap().to_string()]);
-                        textures.insert(name, Arc::new(Checker::new_from_textures(&odd, &even)));
+                        Arc::new(Checker::new_from_textures(&odd, &even))
                     }
                     "noise" => {
-                        let name = entry["name"].as_str().unwrap().to_string();
                         let scale = entry["scale"].as_f64().unwrap();
-                        textures.insert(name, Arc::new(NoiseTexture::new(scale)));
+                        match entry["seed"].as_u64() {
+                            Some(seed) => Arc::new(NoiseTexture::with_seed(scale, seed)),
+                            None => Arc::new(NoiseTexture::new(scale)),
+                        }
+                    }
+                    "fractal" => Arc::new(FractalTexture::new(
+                        Scene::fractal_noise(entry, Fractal::Fbm),
+                        entry["scale"].as_f64().unwrap_or(1.0),
+                        Scene::procedural_space(entry),
+                        entry["w"].as_f64(),
+                        // "evolution" moves along w over time, e.g. for flickering fire
+                        entry["evolution"].as_f64().unwrap_or(0.0),
+                    )),
+                    "warp" => {
+                        let texture =
+                            Arc::clone(&textures[&entry["texture"].as_str().unwrap().to_string()]);
+                        Arc::new(DomainWarp::new(
+                            texture,
+                            Scene::fractal_noise(entry, Fractal::Fbm),
+                            entry["scale"].as_f64().unwrap_or(1.0),
+                            entry["strength"].as_f64().unwrap_or(1.0),
+                            Scene::procedural_space(entry),
+                        ))
+                    }
+                    "wood" => Arc::new(Wood::new(
+                        input("light", "0.75 0.5 0.3"),
+                        input("dark", "0.4 0.2 0.1"),
+                        entry["rings"].as_f64().unwrap_or(8.0),
+                        Scene::fractal_noise(entry, Fractal::Fbm),
+                        entry["noise_scale"].as_f64().unwrap_or(2.0),
+                        entry["distortion"].as_f64().unwrap_or(0.5),
+                        Scene::procedural_space(entry),
+                    )),
+                    "marble" => Arc::new(Marble::new(
+                        input("base", "0.9 0.9 0.88"),
+                        input("vein", "0.2 0.2 0.25"),
+                        entry["frequency"].as_f64().unwrap_or(5.0),
+                        Scene::fractal_noise(entry, Fractal::Turbulence),
+                        entry["noise_scale"].as_f64().unwrap_or(2.0),
+                        entry["distortion"].as_f64().unwrap_or(10.0),
+                        entry["sharpness"].as_f64().unwrap_or(3.0),
+                        Scene::procedural_space(entry),
+                    )),
+                    "bricks" => Arc::new(Bricks::new(
+                        input("brick", "0.6 0.25 0.15"),
+                        input("mortar", "0.8 0.8 0.75"),
+                        Scene::parse_pair(entry, "size", (0.25, 0.1)),
+                        entry["mortar_width"].as_f64().unwrap_or(0.01),
+                        entry["variation"].as_f64().unwrap_or(0.2),
+                        entry["seed"].as_u64().unwrap_or(0),
+                    )),
+                    "tiles" => {
+                        let shape = match entry["shape"].as_str() {
+                            Some("square") | None => TileShape::Square,
+                            Some("hexagon") => TileShape::Hexagon,
+                            Some(other) => panic!("Unknown tile shape {}", other),
+                        };
+                        Arc::new(Tiles::new(
+                            input("tile", "0.8 0.8 0.8"),
+                            input("grout", "0.3 0.3 0.3"),
+                            shape,
+                            entry["size"].as_f64().unwrap_or(0.1),
+                            entry["grout_width"].as_f64().unwrap_or(0.005),
+                            entry["variation"].as_f64().unwrap_or(0.1),
+                            entry["seed"].as_u64().unwrap_or(0),
+                        ))
+                    }
+                    "stripes" => Arc::new(Stripes::new(
+                        input("stripe", "1 1 1"),
+                        input("gap", "0 0 0"),
+                        entry["frequency"].as_f64().unwrap_or(10.0),
+                        entry["ratio"].as_f64().unwrap_or(0.5),
+                    )),
+                    "gradient" => {
+                        let shape = match entry["shape"].as_str() {
+                            Some("linear") | None => GradientShape::Linear,
+                            Some("radial") => GradientShape::Radial,
+                            Some(other) => panic!("Unknown gradient shape {}", other),
+                        };
+                        Arc::new(Gradient::new(
+                            input("start", "0 0 0"),
+                            input("end", "1 1 1"),
+                            shape,
+                        ))
+                    }
+                    "uv_checker" => Arc::new(UvChecker::new(
+                        input("odd", "0 0 0"),
+                        input("even", "1 1 1"),
+                        Scene::parse_pair(entry, "frequency", (10.0, 10.0)),
+                    )),
+                    "ramp" => {
+                        // Stops are [position, "r g b"] pairs
+                        let stops: Vec<(f64, Color)> = entry["stops"]
+                            .members()
+                            .map(|stop| (stop[0].as_f64().unwrap(), parse_color(&stop[1])))
+                            .collect();
+                        if stops.is_empty() {
+                            panic!("Ramp {} needs at least one stop", name);
+                        }
+                        let interpolation = match entry["interpolation"].as_str() {
+                            Some("linear") | None => RampInterpolation::Linear,
+                            Some("constant") => RampInterpolation::Constant,
+                            Some("smooth") => RampInterpolation::Smooth,
+                            Some(other) => panic!("Unknown ramp interpolation {}", other),
+                        };
+                        Arc::new(ColorRamp::new(required("input"), stops, interpolation))
+                    }
+                    "mix" => Arc::new(MixTexture::new(
+                        required("a"),
+                        required("b"),
+                        input("factor", "0.5 0.5 0.5"),
+                    )),
+                    "add" => Arc::new(Arithmetic::new(
+                        required("a"),
+                        required("b"),
+                        Operation::Add,
+                    )),
+                    "subtract" => Arc::new(Arithmetic::new(
+                        required("a"),
+                        required("b"),
+                        Operation::Subtract,
+                    )),
+                    "multiply" => Arc::new(Arithmetic::new(
+                        required("a"),
+                        required("b"),
+                        Operation::Multiply,
+                    )),
+                    "remap" => Arc::new(Remap::new(
+                        required("input"),
+                        Scene::parse_pair(entry, "from", (0.0, 1.0)),
+                        Scene::parse_pair(entry, "to", (0.0, 1.0)),
+                        entry["clamp"].as_bool().unwrap_or(false),
+                    )),
+                    "clamp" => {
+                        let range = (
+                            entry["min"].as_f64().unwrap_or(0.0),
+                            entry["max"].as_f64().unwrap_or(1.0),
+                        );
+                        Arc::new(Remap::new(required("input"), range, range, true))
+                    }
+                    "invert" => Arc::new(Invert::new(required("input"))),
+                    "hsv" => Arc::new(HsvAdjust::new(
+                        required("input"),
+                        entry["hue"].as_f64().unwrap_or(0.0),
+                        entry["saturation"].as_f64().unwrap_or(1.0),
+                        entry["value"].as_f64().unwrap_or(1.0),
+                    )),
+                    "channel" => {
+                        let channel = match entry["channel"].as_str() {
+                            Some("r") => Channel::Red,
+                            Some("g") => Channel::Green,
+                            Some("b") => Channel::Blue,
+                            Some("luminance") => Channel::Luminance(working_space),
+                            Some(other) => panic!("Unknown channel {}", other),
+                            None => panic!("Texture {} is missing its channel", name),
+                        };
+                        Arc::new(ChannelSelect::new(required("input"), channel))
                     }
                     "image" => {
-                        let name = entry["name"].as_str().unwrap().to_string();
                         let path = entry["path"].as_str().unwrap().to_string();
-                        textures.insert(name, Arc::new(ImageTexture::new(path)));
+                        let options = ImageOptions {
+                            // "channel": "alpha" reads the alpha channel, e.g. for opacity masks
+                            channel: match entry["channel"].as_str() {
+                                Some("color") | None => ImageChannel::Color,
+                                Some("alpha") => ImageChannel::Alpha,
+                                Some(other) => panic!("Unknown image channel {}", other),
+                            },
+                            // "colorspace" overrides the encoding the file declares. Use "raw" for data like
+                            // normal maps.
+                            colorspace: match entry["colorspace"].as_str() {
+                                Some("srgb") => Some(ColorSpace::Srgb),
+                                Some("linear") => Some(ColorSpace::Linear),
+                                Some("raw") => Some(ColorSpace::Raw),
+                                None => None,
+                                Some(other) => panic!("Unknown color space {}", other),
+                            },
+                            working_space,
+                            format: match entry["format"].as_str() {
+                                Some("u8") => TexelFormat::U8,
+                                Some("f16") | None => TexelFormat::F16,
+                                Some("f32") => TexelFormat::F32,
+                                Some(other) => panic!("Unknown texel format {}", other),
+                            },
+                            // Tiled images are read in tiles as lookups need them, within the memory budget
+                            tiled: entry["tiled"].as_bool().unwrap_or(false),
+                        };
+                        let wrap = match entry["wrap"].as_str() {
+                            Some("repeat") | None => WrapMode::Repeat,
+                            Some("clamp") => WrapMode::Clamp,
+                            Some("mirror") => WrapMode::Mirror,
+                            Some(other) => panic!("Unknown wrap mode {}", other),
+                        };
+                        let filter = match entry["filter"].as_str() {
+                            Some("nearest") => FilterMode::Nearest,
+                            Some("bilinear") => FilterMode::Bilinear,
+                            Some("bicubic") => FilterMode::Bicubic,
+                            Some("trilinear") | None => FilterMode::Trilinear,
+                            Some("ewa") => FilterMode::Ewa,
+                            Some(other) => panic!("Unknown filter mode {}", other),
+                        };
+                        // Paths like "frame_%04d.png" are image sequences, played back at "fps" from
+                        // "first_frame". "loop" starts them over after the last frame instead of holding it.
+                        if path.contains('%') {
+                            let first_frame = entry["first_frame"].as_i64().unwrap_or(1);
+                            let sequence_fps = entry["fps"].as_f64().unwrap_or(fps);
+                            let looping = entry["loop"].as_bool().unwrap_or(false);
+                            let sequence = ImageSequence::new(
+                                &path,
+                                first_frame,
+                                sequence_fps,
+                                looping,
+                                options,
+                                wrap,
+                                filter,
+                            )
+                            .unwrap_or_else(|error| {
+                                panic!("Failed to load image sequence {}: {}", path, error)
+                            });
+                            Arc::new(sequence)
+                        } else {
+                            let pyramid = load_image(&path, &options).unwrap_or_else(|error| {
+                                panic!("Failed to load image {}: {}", path, error)
+                            });
+                            Arc::new(ImageTexture::new_from_pyramid(pyramid, wrap, filter))
+                        }
                     }
-                    _ => {}
-                }
+                    other => panic!("Unknown type {} of texture {}", other, name),
+                };
+                textures.insert(name, Scene::texture_modifiers(entry, texture));
             }
         }
         // TODO: Possibly change textures so that they are initialized in the order they appear in the json file, and not by type.
@@ -268,8 +1018,9 @@ impl Scene {
         if parsed_textures.has_key("color") {
             for entry in parsed_textures["color"].members() {
                 let name = entry["name"].as_str().unwrap().to_string();
-                let color = Scene::string_to_vec(entry["color"].as_str().unwrap());
-                textures.insert(name, Arc::new(SolidColor::new(color)));
+                let color = parse_color(&entry["color"]);
+                let texture = Arc::new(SolidColor::new(color));
+                textures.insert(name, Scene::texture_modifiers(entry, texture));
             }
         }
 
@@ -279,7 +1030,8 @@ impl Scene {
                 let name = entry["name"].as_str().unwrap().to_string();
                 let odd = Arc::clone(&textures[&entry["odd"].as_str().unwrap().to_string()]);
                 let even = Arc::clone(&textures[&entry["even"].as_str().unwrap().to_string()]);
-                textures.insert(name, Arc::new(Checker::new_from_textures(&odd, &even)));
+                let texture = Arc::new(Checker::new_from_textures(&odd, &even));
+                textures.insert(name, Scene::texture_modifiers(entry, texture));
             }
         }
 
@@ -292,7 +1044,7 @@ impl Scene {
         if parsed_materials.has_key("lambertian") {
             for entry in parsed_materials["lambertian"].members() {
                 let name = entry["name"].as_str().unwrap().to_string();
-                let albedo = Scene::string_to_vec(entry["albedo"].as_str().unwrap());
+                let albedo = parse_color(&entry["albedo"]);
                 materials.insert(name, Arc::new(Lambertian::new(albedo)));
             }
         }
@@ -301,13 +1053,16 @@ impl Scene {
         if parsed_materials.has_key("blinnPhong") {
             for entry in parsed_materials["blinnPhong"].members() {
                 let name = entry["name"].as_str().unwrap().to_string();
-                let diffuse = Scene::string_to_vec(entry["diffuse"].as_str().unwrap());
-                let specular = Scene::string_to_vec(entry["specular"].as_str().unwrap());
+                let diffuse = parse_color(&entry["diffuse"]);
+                let specular = parse_color(&entry["specular"]);
                 let phong_exp = entry["phongExp"].as_f64().unwrap();
-                materials.insert(
-                    name,
-                    Arc::new(BlinnPhong::new(diffuse, specular, phong_exp)),
-                );
+                let legacy = entry["legacy"].as_bool().unwrap_or(false);
+                let material = if legacy {
+                    BlinnPhong::new_legacy(diffuse, specular, phong_exp)
+                } else {
+                    BlinnPhong::new(diffuse, specular, phong_exp)
+                };
+                materials.insert(name, Arc::new(material));
             }
         }
 
@@ -315,8 +1070,63 @@ impl Scene {
         if parsed_materials.has_key("dielectric") {
             for entry in parsed_materials["dielectric"].members() {
                 let name = entry["name"].as_str().unwrap().to_string();
-                let ir = entry["ir"].as_f64().unwrap();
-                materials.insert(name, Arc::new(Dielectric { ir }));
+                let ior = Scene::parse_ior(entry);
+                materials.insert(name, Arc::new(Dielectric::new_from_ior(ior)));
+            }
+        }
+
+        // Parse rough dielectric materials
+        if parsed_materials.has_key("roughDielectric") {
+            for entry in parsed_materials["roughDielectric"].members() {
+                let name = entry["name"].as_str().unwrap().to_string();
+                let ior = Scene::parse_ior(entry);
+                let roughness = Scene::texture_parameter(entry, "roughness", &textures, zero_vec());
+                materials.insert(name, Arc::new(RoughDielectric::new(ior, roughness)));
+            }
+        }
+
+        // Parse principled materials
+        if parsed_materials.has_key("principled") {
+            for entry in parsed_materials["principled"].members() {
+                let name = entry["name"].as_str().unwrap().to_string();
+                let parameter = |key: &str, default: f64| {
+                    Scene::texture_parameter(
+                        entry,
+                        key,
+                        &textures,
+                        quick_vec(default, default, default),
+                    )
+                };
+                let principled = Principled {
+                    base_color: parameter("base_color", 0.8),
+                    metallic: parameter("metallic", 0.0),
+                    roughness: parameter("roughness", 0.5),
+                    specular: parameter("specular", 0.5),
+                    specular_tint: parameter("specular_tint", 0.0),
+                    sheen: parameter("sheen", 0.0),
+                    clearcoat: parameter("clearcoat", 0.0),
+                    clearcoat_gloss: parameter("clearcoat_gloss", 1.0),
+                    transmission: parameter("transmission", 0.0),
+                    ior: parameter("ior", 1.45),
+                    working_space,
+                };
+                materials.insert(name, Arc::new(principled));
+            }
+        }
+
+        // Parse subsurface materials
+        if parsed_materials.has_key("subsurface") {
+            for entry in parsed_materials["subsurface"].members() {
+                let name = entry["name"].as_str().unwrap().to_string();
+                let albedo =
+                    Scene::texture_parameter(entry, "albedo", &textures, quick_vec(0.8, 0.8, 0.8));
+                // The mean free path is either one distance or one per channel
+                let mfp = match entry["mfp"].as_f64() {
+                    Some(mfp) => quick_vec(mfp, mfp, mfp),
+                    None => Scene::string_to_vec(entry["mfp"].as_str().unwrap()),
+                };
+                let ior = entry["ior"].as_f64().unwrap_or(1.4);
+                materials.insert(name, Arc::new(Subsurface::new(albedo, mfp, ior)));
             }
         }
 
@@ -337,6 +1147,32 @@ impl Scene {
             }
         }
 
+        // Parse conductor materials
+        if parsed_materials.has_key("conductor") {
+            for entry in parsed_materials["conductor"].members() {
+                let name = entry["name"].as_str().unwrap().to_string();
+                // Either a named "preset" or "eta" and "k" per channel
+                let (eta, k) = if entry.has_key("preset") {
+                    let preset = entry["preset"].as_str().unwrap();
+                    Conductor::preset(preset)
+                        .unwrap_or_else(|| panic!("Unknown conductor preset {}", preset))
+                } else {
+                    (
+                        Scene::string_to_vec(entry["eta"].as_str().unwrap()),
+                        Scene::string_to_vec(entry["k"].as_str().unwrap()),
+                    )
+                };
+                // "roughness" sets both tangents, "roughness_u" and "roughness_v" override it for anisotropy
+                let roughness = entry["roughness"].as_f64().unwrap_or(0.0);
+                let roughness_u = entry["roughness_u"].as_f64().unwrap_or(roughness);
+                let roughness_v = entry["roughness_v"].as_f64().unwrap_or(roughness);
+                materials.insert(
+                    name,
+                    Arc::new(Conductor::new(eta, k, roughness_u, roughness_v)),
+                );
+            }
+        }
+
         // Parse diffuse materials
         if parsed_materials.has_key("diffuse") {
             for entry in parsed_materials["diffuse"].members() {
@@ -351,6 +1187,19 @@ impl Scene {
             }
         }
 
+        // Parse Oren-Nayar materials
+        if parsed_materials.has_key("orenNayar") {
+            for entry in parsed_materials["orenNayar"].members() {
+                let name = entry["name"].as_str().unwrap().to_string();
+                let texture = entry["texture"].as_str().unwrap();
+                let sigma = entry["sigma"].as_f64().unwrap();
+                materials.insert(
+                    name,
+                    Arc::new(OrenNayar::new(Arc::clone(&textures[texture]), sigma)),
+                );
+            }
+        }
+
         // Prase emissive materials
         if parsed_materials.has_key("emissive") {
             for entry in parsed_materials["emissive"].members() {
@@ -360,10 +1209,72 @@ impl Scene {
             }
         }
 
+        // Attach normal maps and opacity masks. Wrapping materials get theirs as they are parsed below, so they wrap
+        // the modified versions of the materials they reference.
+        for (kind, entries) in parsed_materials.entries() {
+            if kind == "coated" || kind == "mix" {
+                continue;
+            }
+            for entry in entries.members() {
+                let name = entry["name"].as_str().unwrap();
+                let material =
+                    Scene::material_modifiers(entry, Arc::clone(&materials[name]), &textures);
+                materials.insert(name.to_string(), material);
+            }
+        }
+
+        // Parse materials that wrap other materials by name. These can wrap each other in any order, so they are
+        // parsed in passes until every material they reference exists.
+        let mut wrappers: Vec<(&str, &JsonValue)> = Vec::new();
+        for kind in ["coated", "mix"] {
+            for entry in parsed_materials[kind].members() {
+                wrappers.push((kind, entry));
+            }
+        }
+        while !wrappers.is_empty() {
+            let remaining = wrappers.len();
+            wrappers.retain(|(kind, entry)| {
+                let references: Vec<&str> = match *kind {
+                    "coated" => vec![entry["base"].as_str().unwrap()],
+                    _ => entry["materials"]
+                        .members()
+                        .map(|name| name.as_str().unwrap())
+                        .collect(),
+                };
+                if !references.iter().all(|name| materials.contains_key(*name)) {
+                    return true;
+                }
+
+                let material = match *kind {
+                    "coated" => Scene::coated_material(entry, &materials, &textures),
+                    _ => Scene::mix_material(entry, &materials, &textures),
+                };
+                let material = Scene::material_modifiers(entry, material, &textures);
+                materials.insert(entry["name"].as_str().unwrap().to_string(), material);
+                false
+            });
+
+            if wrappers.len() == remaining {
+                panic!(
+                    "Unknown or circular material reference in {}",
+                    wrappers[0].1["name"]
+                );
+            }
+        }
+
         // SHAPE PARSING
         let mut objects = HittableList {
             objects: Vec::new(),
         };
+        let mut named_objects: HashMap<String, Arc<dyn Hittable>> = HashMap::new();
+        // Objects can be given a "name" to refer to them by, such as the target of a bake
+        let mut add_object = |entry: &JsonValue, shape: Arc<dyn Hittable>| {
+            let object = Scene::modifiers(entry, shape, &textures);
+            if let Some(name) = entry["name"].as_str() {
+                named_objects.insert(name.to_string(), Arc::clone(&object));
+            }
+            objects.add(object);
+        };
         let parsed_objects = &parsed["objects"];
         // triangles
         if parsed_objects.has_key("triangle") {
@@ -379,7 +1290,7 @@ impl Scene {
                     material: Arc::clone(&materials[&material]),
                 };
 
-                objects.add(Scene::modifiers(entry, Arc::new(triangle), &textures));
+                add_object(entry, Arc::new(triangle));
             }
         }
 
@@ -395,7 +1306,23 @@ impl Scene {
                     material: Arc::clone(&materials[&material]),
                 };
 
-                objects.add(Scene::modifiers(entry, Arc::new(sphere), &textures));
+                add_object(entry, Arc::new(sphere));
+            }
+        }
+
+        // moving sphere
+        if parsed_objects.has_key("movingSphere") {
+            for entry in parsed_objects["movingSphere"].members() {
+                let center0 = Scene::string_to_vec(entry["center0"].as_str().unwrap());
+                let center1 = Scene::string_to_vec(entry["center1"].as_str().unwrap());
+                let time0 = entry["time0"].as_f64().unwrap();
+                let time1 = entry["time1"].as_f64().unwrap();
+                let radius = entry["radius"].as_f64().unwrap();
+                let material = entry["material"].as_str().unwrap().to_string();
+                let material = &Arc::clone(&materials[&material]);
+                let sphere = MovingSphere::new(center0, center1, time0, time1, radius, material);
+
+                add_object(entry, Arc::new(sphere));
             }
         }
 
@@ -411,7 +1338,7 @@ impl Scene {
                 let material = &Arc::clone(&materials[&material]);
                 let rect = XYRect::new(x0, x1, y0, y1, k, material);
 
-                objects.add(Scene::modifiers(entry, Arc::new(rect), &textures));
+                add_object(entry, Arc::new(rect));
             }
         }
         // XYRect
@@ -426,7 +1353,7 @@ impl Scene {
                 let material = &Arc::clone(&materials[&material]);
                 let rect = XZRect::new(x0, x1, z0, z1, k, material);
 
-                objects.add(Scene::modifiers(entry, Arc::new(rect), &textures));
+                add_object(entry, Arc::new(rect));
             }
         }
         // XYRect
@@ -441,7 +1368,7 @@ impl Scene {
                 let material = &Arc::clone(&materials[&material]);
                 let rect = YZRect::new(y0, y1, z0, z1, k, material);
 
-                objects.add(Scene::modifiers(entry, Arc::new(rect), &textures));
+                add_object(entry, Arc::new(rect));
             }
         }
 
@@ -453,15 +1380,47 @@ impl Scene {
                 let material = Arc::clone(&materials[&material]);
                 let s_box = shapes::Box::new(&min, &max, material);
 
-                objects.add(Scene::modifiers(entry, Arc::new(s_box), &textures));
+                add_object(entry, Arc::new(s_box));
             }
         }
 
+        // "bake": {"object": [name], "mode": [mode], ...} sets what --bake writes out
+        let parsed_bake = &parsed["bake"];
+        let bake = if parsed.has_key("bake") {
+            let mode = match parsed_bake["mode"].as_str() {
+                Some("texture") => {
+                    let texture = parsed_bake["texture"].as_str().unwrap();
+                    BakeMode::Texture(Arc::clone(&textures[texture]))
+                }
+                Some("ao") => BakeMode::AmbientOcclusion {
+                    distance: parsed_bake["distance"].as_f64().unwrap_or(INFINITY),
+                },
+                Some("irradiance") => BakeMode::Irradiance,
+                Some("lighting") | None => BakeMode::Lighting,
+                Some(other) => panic!("Unknown bake mode {}", other),
+            };
+            Some(BakeSettings {
+                object: parsed_bake["object"].as_str().unwrap().to_string(),
+                mode,
+                width: parsed_bake["width"].as_u32().unwrap_or(1024),
+                height: parsed_bake["height"].as_u32().unwrap_or(1024),
+                samples: parsed_bake["samples"].as_i32().unwrap_or(16),
+                padding: parsed_bake["padding"].as_u32().unwrap_or(4),
+                output: parsed_bake["output"]
+                    .as_str()
+                    .unwrap_or("bake.png")
+                    .to_string(),
+            })
+        } else {
+            None
+        };
+
         eprintln!("{} lights", lights.len());
         eprintln!("{} textures", textures.len());
         eprintln!("{} materials", materials.len());
         eprintln!("{} objects", objects.len());
-        let bvh_root = BVHNode::new(&objects.objects, 0);
+        let (time0, time1) = camera.shutter();
+        let bvh_root = BVHNode::new(&objects.objects, 0, time0, time1);
         // eprintln!("{}")
         Scene {
             camera,
@@ -473,10 +1432,36 @@ impl Scene {
             max_depth,
             background,
             bvh_root,
+            integrator,
+            working_space,
+            named_objects,
+            bake,
         }
     }
 
     pub fn render(&self) {
+        self.render_to_file(String::from(OUTPUT_PATH));
+    }
+
+    /// Renders the scene and writes the result to a png at path
+    pub fn render_to_file(&self, path: String) {
+        let mut buffer = match &self.integrator {
+            Integrator::PathTracer => self.render_path_traced(),
+            Integrator::Sppm(settings) => self.render_sppm(settings),
+            Integrator::Spectral => self.render_spectral(),
+        };
+
+        for y in 0..buffer.height() {
+            for x in 0..buffer.width() {
+                let color = self.working_space.to_rec709(buffer.at(x, y));
+                buffer.write(color, x, y);
+            }
+        }
+        buffer.buffer_to_png(path);
+    }
+
+    /// Renders the scene by tracing paths through Material::apply
+    fn render_path_traced(&self) -> Buffer {
         let mut buffer = Buffer::new(self.width as u32, self.height as u32);
         let num_threads = num_cpus::get() - 1;
         //let channels: Vec<(Sender<_>, Receiver<Vec<Color>>)> = vec![mpsc::channel(); num_threads]
@@ -495,8 +1480,14 @@ impl Scene {
                             for _ in 0..self.samples {
                                 let u = (i as f64 + random_float_1()) / (self.width + 1) as f64;
                                 let v = (j as f64 + random_float_1()) / (self.height - 1) as f64;
-                                let r = self.get_ray(u, v);
-                                pixel_color += self.ray_color(&r, self.max_depth);
+                                let (r, differential) = self.get_ray_differential(
+                                    u,
+                                    v,
+                                    1.0 / (self.width + 1) as f64,
+                                    1.0 / (self.height - 1) as f64,
+                                );
+                                pixel_color +=
+                                    self.camera_ray_color(&r, &differential, self.max_depth);
                             }
 
                             thread_buffer.push(pixel_color / (self.samples as f64));
@@ -524,6 +1515,6 @@ impl Scene {
         })
         .unwrap();
 
-        buffer.buffer_to_png(String::from("image.png"));
+        buffer
     }
 }
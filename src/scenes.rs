@@ -1,9 +1,9 @@
 use std::sync::Arc;
 
-use crate::background::BackgroundColor;
+use crate::background::{BackgroundColor, GradientY};
 
 use crate::bvh::BVHNode;
-use crate::camera::PerspectiveCamera;
+use crate::camera::{PerspectiveCamera, RTOWCamera, Shutter};
 
 use crate::hit::*;
 use crate::instance::RotateY;
@@ -13,6 +13,7 @@ use crate::materials::*;
 
 use crate::scene::Scene;
 use crate::shapes::Box;
+use crate::shapes::MovingSphere;
 use crate::shapes::Sphere;
 
 use crate::shapes::XZRect;
@@ -21,6 +22,8 @@ use crate::texture::NoiseTexture;
 use crate::texture::SolidColor;
 use crate::texture::Texture;
 use crate::utility::*;
+use crate::vector::random_vec;
+use crate::vector::random_vec_1;
 use crate::vector::Color;
 use crate::vector::Point3;
 use crate::vector::Vec3;
@@ -205,16 +208,20 @@ impl Scene {
             123.0, 423.0, 147.0, 412.0, 554.0, &light,
         )));
 
-        let center = Point3::new(400.0, 400.0, 400.0);
-        let not_moving_sphere_material: Arc<dyn Material> = Arc::new(Diffuse::new_from_color(
+        let center1 = Point3::new(400.0, 400.0, 400.0);
+        let center2 = center1 + Vec3::new(30.0, 0.0, 0.0);
+        let moving_sphere_material: Arc<dyn Material> = Arc::new(Diffuse::new_from_color(
             Color::new(0.7, 0.3, 0.1),
             absorbance,
         ));
-        objects.add(Arc::new(Sphere {
-            center,
-            radius: 50.0,
-            material: Arc::clone(&not_moving_sphere_material),
-        }));
+        objects.add(Arc::new(MovingSphere::new(
+            center1,
+            center2,
+            0.0,
+            1.0,
+            50.0,
+            &moving_sphere_material,
+        )));
 
         let dielectric: Arc<dyn Material> = Arc::new(Dielectric::new(1.5));
 
@@ -250,7 +257,7 @@ impl Scene {
             material: permat,
         }));
         let emat = Arc::new(Diffuse::new(
-            Arc::new(ImageTexture::new("assets/Blue_Marble_2002.png".to_string())),
+            Arc::new(ImageTexture::new("assets/Blue_Marble_2002.png".to_string()).unwrap()),
             absorbance,
         ));
         objects.add(Arc::new(Sphere {
@@ -274,20 +281,26 @@ impl Scene {
             )))
         }
 
-        let boxes2 = BVHNode::new(&boxes2.objects, 0);
+        let boxes2 = BVHNode::new(&boxes2.objects, 0, 0.0, 1.0);
 
         objects.add(Arc::new(Translate::new(
             Arc::new(RotateY::new(Arc::new(boxes2), 15.0)),
             Vec3::new(-100.0, 270.0, 395.0),
         )));
 
-        let camera = Arc::new(PerspectiveCamera::new(
-            Vec3::new(478.0, 278.0, -600.0),
-            Vec3::new(278.0, 278.0, 0.0),
-            Vec3::new(0.0, 1.0, 0.0),
-            40.0,
-            1.0,
-        ));
+        let camera = Arc::new(
+            PerspectiveCamera::new(
+                Vec3::new(478.0, 278.0, -600.0),
+                Vec3::new(278.0, 278.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                40.0,
+                1.0,
+            )
+            .with_shutter(Shutter {
+                open: 0.0,
+                close: 1.0,
+            }),
+        );
 
         // this was tedious
         Scene::new(
@@ -301,4 +314,117 @@ impl Scene {
             Arc::new(BackgroundColor::new(Color::new(0.0, 0.0, 0.0))),
         )
     }
+
+    /// The bouncing spheres scene from the start of Ray Tracing: The Next Week
+    pub fn gen_bouncing_spheres() -> Scene {
+        let mut objects = HittableList::new();
+        let absorbance = 0.0;
+
+        let ground_material: Arc<dyn Material> = Arc::new(Diffuse::new_from_color(
+            Color::new(0.5, 0.5, 0.5),
+            absorbance,
+        ));
+        objects.add(Arc::new(Sphere::new(
+            Point3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            &ground_material,
+        )));
+
+        for a in -11..11 {
+            for b in -11..11 {
+                let choose_mat = random_float_1();
+                let center = Point3::new(
+                    a as f64 + 0.9 * random_float_1(),
+                    0.2,
+                    b as f64 + 0.9 * random_float_1(),
+                );
+
+                if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
+                    if choose_mat < 0.8 {
+                        // diffuse spheres bounce up during the shutter interval
+                        let albedo = random_vec_1() * random_vec_1();
+                        let sphere_material: Arc<dyn Material> =
+                            Arc::new(Diffuse::new_from_color(albedo, absorbance));
+                        let center2 = center + Vec3::new(0.0, random_float(0.0, 0.5), 0.0);
+                        objects.add(Arc::new(MovingSphere::new(
+                            center,
+                            center2,
+                            0.0,
+                            1.0,
+                            0.2,
+                            &sphere_material,
+                        )));
+                    } else if choose_mat < 0.95 {
+                        // metal
+                        let albedo = random_vec(0.5, 1.0);
+                        let fuzz = random_float(0.0, 0.5);
+                        let sphere_material: Arc<dyn Material> =
+                            Arc::new(Metal::new_from_color(albedo, fuzz));
+                        objects.add(Arc::new(Sphere::new(center, 0.2, &sphere_material)));
+                    } else {
+                        // glass
+                        let sphere_material: Arc<dyn Material> = Arc::new(Dielectric::new(1.5));
+                        objects.add(Arc::new(Sphere::new(center, 0.2, &sphere_material)));
+                    }
+                }
+            }
+        }
+
+        let material1: Arc<dyn Material> = Arc::new(Dielectric::new(1.5));
+        objects.add(Arc::new(Sphere::new(
+            Point3::new(0.0, 1.0, 0.0),
+            1.0,
+            &material1,
+        )));
+
+        let material2: Arc<dyn Material> = Arc::new(Diffuse::new_from_color(
+            Color::new(0.4, 0.2, 0.1),
+            absorbance,
+        ));
+        objects.add(Arc::new(Sphere::new(
+            Point3::new(-4.0, 1.0, 0.0),
+            1.0,
+            &material2,
+        )));
+
+        let material3: Arc<dyn Material> =
+            Arc::new(Metal::new_from_color(Color::new(0.7, 0.6, 0.5), 0.0));
+        objects.add(Arc::new(Sphere::new(
+            Point3::new(4.0, 1.0, 0.0),
+            1.0,
+            &material3,
+        )));
+
+        let width = 400;
+        let height = 225;
+        let camera = Arc::new(
+            RTOWCamera::new(
+                Point3::new(13.0, 2.0, 3.0),
+                Point3::new(0.0, 0.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                20.0,
+                width as f64 / height as f64,
+                0.1,
+                10.0,
+            )
+            .with_shutter(Shutter {
+                open: 0.0,
+                close: 1.0,
+            }),
+        );
+
+        Scene::new(
+            camera,
+            Arc::new(objects),
+            Arc::new(LightList::new()),
+            width,
+            height,
+            100,
+            10,
+            Arc::new(GradientY::new(
+                Color::new(1.0, 1.0, 1.0),
+                Color::new(0.5, 0.7, 1.0),
+            )),
+        )
+    }
 }
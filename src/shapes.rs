@@ -5,6 +5,7 @@ use crate::aabb::AABB;
 use crate::hit::*;
 use crate::materials::Material;
 use crate::texture::TextureCoord;
+use crate::utility::{random_float, random_float_1};
 use crate::vector::*;
 use crate::Ray;
 
@@ -27,21 +28,49 @@ impl Sphere {
         let theta = f64::acos(-p.y());
         let phi = f64::atan2(-p.z(), p.x()) + PI;
 
-        TextureCoord {
-            u: phi / (2.0 * PI),
-            v: theta / PI,
-        }
+        TextureCoord::new(phi / (2.0 * PI), theta / PI)
     }
-}
 
-impl Hittable for Sphere {
-    /// Ray intersect function for spheres
-    /// Returns true if there is an intersection in range [t_min, t_max]
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = r.origin - self.center;
+    /// Returns the normalized point on the unit sphere with the given texture coordinates. Inverse of get_sphere_uv.
+    fn get_sphere_point(u: f64, v: f64) -> Point3 {
+        let phi = 2.0 * PI * u;
+        let theta = PI * v;
+        Vec3::new(
+            -f64::sin(theta) * f64::cos(phi),
+            -f64::cos(theta),
+            f64::sin(theta) * f64::sin(phi),
+        )
+    }
+
+    /// Returns the derivatives of the point with respect to the texture coordinates for a normalized point on the
+    /// unit sphere
+    fn get_sphere_tangents(p: &Point3, radius: f64) -> (Vec3, Vec3) {
+        let sin_theta = f64::max(f64::sqrt(1.0 - p.y() * p.y()), 1e-8);
+        let dpdu = 2.0 * PI * radius * Vec3::new(p.z(), 0.0, -p.x());
+        let dpdv = PI
+            * radius
+            * Vec3::new(
+                -p.x() * p.y() / sin_theta,
+                sin_theta,
+                -p.y() * p.z() / sin_theta,
+            );
+        (dpdu, dpdv)
+    }
+
+    /// Ray intersect function for a sphere with the given center. Shared with moving spheres, which know their
+    /// center only once they know the time of the ray.
+    fn hit_centered(
+        center: &Point3,
+        radius: f64,
+        material: &Arc<dyn Material>,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<HitRecord> {
+        let oc = r.origin - *center;
         let a = r.direction.length_squared();
         let half_b = dot(&oc, &r.direction);
-        let c = oc.length_squared() - (self.radius * self.radius);
+        let c = oc.length_squared() - (radius * radius);
         let discriminant = half_b * half_b - a * c;
 
         if discriminant < 0.0 {
@@ -61,8 +90,9 @@ impl Hittable for Sphere {
 
         let t = root;
         let p = r.at(t);
-        let normal = (p - self.center) / self.radius;
-        let material = Arc::clone(&self.material);
+        let normal = (p - *center) / radius;
+        let material = Arc::clone(material);
+        let (dpdu, dpdv) = Sphere::get_sphere_tangents(&normal, radius);
 
         let mut rec = HitRecord {
             t,
@@ -71,6 +101,8 @@ impl Hittable for Sphere {
             material,
             front_face: true,
             uv: Sphere::get_sphere_uv(&normal),
+            dpdu,
+            dpdv,
         };
 
         rec.set_face_normal(r, &normal);
@@ -78,12 +110,165 @@ impl Hittable for Sphere {
         return Some(rec);
     }
 
-    fn bounding_box(&self) -> AABB {
+    /// Returns the point of a sphere with the given center along a unit normal
+    fn surface_point_centered(
+        center: &Point3,
+        radius: f64,
+        material: &Arc<dyn Material>,
+        normal: &Vec3,
+        uv: TextureCoord,
+    ) -> HitRecord {
+        let (dpdu, dpdv) = Sphere::get_sphere_tangents(normal, radius);
+        HitRecord::at_surface_point(
+            *center + radius * *normal,
+            *normal,
+            material,
+            uv,
+            dpdu,
+            dpdv,
+        )
+    }
+}
+
+impl Hittable for Sphere {
+    /// Ray intersect function for spheres
+    /// Returns true if there is an intersection in range [t_min, t_max]
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        Sphere::hit_centered(&self.center, self.radius, &self.material, r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> AABB {
         let center = self.center;
         let rad_vec = Vec3::new(self.radius, self.radius, self.radius);
 
         AABB::new(center - rad_vec, center + rad_vec)
     }
+
+    fn random_surface_point(&self, _time: f64) -> Option<HitRecord> {
+        let normal = random_unit_vector();
+        Some(Sphere::surface_point_centered(
+            &self.center,
+            self.radius,
+            &self.material,
+            &normal,
+            Sphere::get_sphere_uv(&normal),
+        ))
+    }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn bakeable(&self) -> bool {
+        true
+    }
+
+    fn point_at_uv(&self, u: f64, v: f64, _time: f64) -> Option<HitRecord> {
+        Some(Sphere::surface_point_centered(
+            &self.center,
+            self.radius,
+            &self.material,
+            &Sphere::get_sphere_point(u, v),
+            TextureCoord::new(u, v),
+        ))
+    }
+}
+
+/// A sphere whose center moves linearly from center0 at time0 to center1 at time1
+pub struct MovingSphere {
+    pub center0: Point3,
+    pub center1: Point3,
+    pub time0: f64,
+    pub time1: f64,
+    pub radius: f64,
+    pub material: Arc<dyn Material>,
+}
+
+impl MovingSphere {
+    pub fn new(
+        center0: Point3,
+        center1: Point3,
+        time0: f64,
+        time1: f64,
+        radius: f64,
+        material: &Arc<dyn Material>,
+    ) -> MovingSphere {
+        MovingSphere {
+            center0,
+            center1,
+            time0,
+            time1,
+            radius,
+            material: Arc::clone(material),
+        }
+    }
+
+    /// Returns the center of the sphere at a given time
+    pub fn center(&self, time: f64) -> Point3 {
+        if self.time1 == self.time0 {
+            return self.center0;
+        }
+
+        self.center0
+            + ((time - self.time0) / (self.time1 - self.time0)) * (self.center1 - self.center0)
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        Sphere::hit_centered(
+            &self.center(r.time),
+            self.radius,
+            &self.material,
+            r,
+            t_min,
+            t_max,
+        )
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> AABB {
+        // The center moves linearly, so the boxes at the ends of the interval cover everything in between
+        let rad_vec = Vec3::new(self.radius, self.radius, self.radius);
+        let mut min = zero_vec();
+        let mut max = zero_vec();
+        let center0 = self.center(time0);
+        let center1 = self.center(time1);
+        for c in 0..3 {
+            min[c] = f64::min(center0[c], center1[c]);
+            max[c] = f64::max(center0[c], center1[c]);
+        }
+
+        AABB::new(min - rad_vec, max + rad_vec)
+    }
+
+    fn random_surface_point(&self, time: f64) -> Option<HitRecord> {
+        let normal = random_unit_vector();
+        Some(Sphere::surface_point_centered(
+            &self.center(time),
+            self.radius,
+            &self.material,
+            &normal,
+            Sphere::get_sphere_uv(&normal),
+        ))
+    }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn bakeable(&self) -> bool {
+        true
+    }
+
+    fn point_at_uv(&self, u: f64, v: f64, time: f64) -> Option<HitRecord> {
+        Some(Sphere::surface_point_centered(
+            &self.center(time),
+            self.radius,
+            &self.material,
+            &Sphere::get_sphere_point(u, v),
+            TextureCoord::new(u, v),
+        ))
+    }
 }
 
 pub struct Triangle {
@@ -93,6 +278,14 @@ pub struct Triangle {
     pub material: Arc<dyn Material>,
 }
 
+impl Triangle {
+    /// Returns the derivatives of the point with respect to the texture coordinates. Hits store u and v as the
+    /// barycentric weights of point3 and point1, so the triangle covers the lower left half of texture space.
+    fn tangents(&self) -> (Vec3, Vec3) {
+        (self.point3 - self.point2, self.point1 - self.point2)
+    }
+}
+
 impl Hittable for Triangle {
     /// Ray triangle intersection
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
@@ -153,24 +346,85 @@ impl Hittable for Triangle {
             return None;
         }
 
-        // return hitstruct
+        // return hitstruct. Materials expect a unit normal. The edge tests are scaled by the squared length of n.
+        let normal = unit_vector(n);
+        let scale = n.length_squared();
+        let (dpdu, dpdv) = self.tangents();
         let mut rec = HitRecord {
             p,
-            normal: n,
+            normal,
             material: Arc::clone(&self.material),
             t,
             front_face: true,
-            uv: TextureCoord::new(gamma, beta),
+            uv: TextureCoord::new(gamma / scale, beta / scale),
+            dpdu,
+            dpdv,
         };
 
-        rec.set_face_normal(r, &n);
+        rec.set_face_normal(r, &normal);
 
         return Some(rec);
     }
 
-    fn bounding_box(&self) -> AABB {
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> AABB {
         AABB::new_from_points(&vec![self.point1, self.point2, self.point3])
     }
+
+    fn random_surface_point(&self, _time: f64) -> Option<HitRecord> {
+        // Uniformly sample barycentric coordinates by folding the unit square onto the triangle
+        let mut a = random_float_1();
+        let mut b = random_float_1();
+        if a + b > 1.0 {
+            a = 1.0 - a;
+            b = 1.0 - b;
+        }
+
+        let p = self.point1 + a * (self.point2 - self.point1) + b * (self.point3 - self.point1);
+        let normal = unit_vector(cross(
+            &(self.point2 - self.point1),
+            &(self.point3 - self.point1),
+        ));
+
+        let (dpdu, dpdv) = self.tangents();
+
+        Some(HitRecord::at_surface_point(
+            p,
+            normal,
+            &self.material,
+            TextureCoord::new(b, 1.0 - a - b),
+            dpdu,
+            dpdv,
+        ))
+    }
+
+    fn area(&self) -> f64 {
+        0.5 * cross(&(self.point2 - self.point1), &(self.point3 - self.point1)).length()
+    }
+
+    fn bakeable(&self) -> bool {
+        true
+    }
+
+    fn point_at_uv(&self, u: f64, v: f64, _time: f64) -> Option<HitRecord> {
+        let n = cross(&(self.point2 - self.point1), &(self.point3 - self.point1));
+        let weight3 = u;
+        let weight1 = v;
+        let weight2 = 1.0 - weight1 - weight3;
+        if weight1 < 0.0 || weight3 < 0.0 || weight2 < 0.0 {
+            return None;
+        }
+
+        let p = weight1 * self.point1 + weight2 * self.point2 + weight3 * self.point3;
+        let (dpdu, dpdv) = self.tangents();
+        Some(HitRecord::at_surface_point(
+            p,
+            unit_vector(n),
+            &self.material,
+            TextureCoord::new(u, v),
+            dpdu,
+            dpdv,
+        ))
+    }
 }
 
 pub struct XYRect {
@@ -196,7 +450,7 @@ impl XYRect {
 }
 
 impl Hittable for XYRect {
-    fn bounding_box(&self) -> AABB {
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> AABB {
         AABB::new(
             Vec3::new(self.x0, self.y0, self.k - 0.0001),
             Vec3::new(self.x1, self.y1, self.k + 0.0001),
@@ -224,12 +478,56 @@ impl Hittable for XYRect {
                 (x - self.x0) / (self.x1 - self.x0),
                 (y - self.y0) / (self.y1 - self.y0),
             ),
+            dpdu: Vec3::new(self.x1 - self.x0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, self.y1 - self.y0, 0.0),
         };
         let normal = rec.normal;
         rec.set_face_normal(r, &normal);
 
         return Some(rec);
     }
+
+    fn random_surface_point(&self, _time: f64) -> Option<HitRecord> {
+        let x = random_float(self.x0, self.x1);
+        let y = random_float(self.y0, self.y1);
+
+        Some(HitRecord::at_surface_point(
+            Vec3::new(x, y, self.k),
+            Vec3::new(0.0, 0.0, 1.0),
+            &self.material,
+            TextureCoord::new(
+                (x - self.x0) / (self.x1 - self.x0),
+                (y - self.y0) / (self.y1 - self.y0),
+            ),
+            Vec3::new(self.x1 - self.x0, 0.0, 0.0),
+            Vec3::new(0.0, self.y1 - self.y0, 0.0),
+        ))
+    }
+
+    fn area(&self) -> f64 {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+
+    fn bakeable(&self) -> bool {
+        true
+    }
+
+    fn point_at_uv(&self, u: f64, v: f64, _time: f64) -> Option<HitRecord> {
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+
+        let x = self.x0 + u * (self.x1 - self.x0);
+        let y = self.y0 + v * (self.y1 - self.y0);
+        Some(HitRecord::at_surface_point(
+            Vec3::new(x, y, self.k),
+            Vec3::new(0.0, 0.0, 1.0),
+            &self.material,
+            TextureCoord::new(u, v),
+            Vec3::new(self.x1 - self.x0, 0.0, 0.0),
+            Vec3::new(0.0, self.y1 - self.y0, 0.0),
+        ))
+    }
 }
 
 pub struct XZRect {
@@ -255,7 +553,7 @@ impl XZRect {
 }
 
 impl Hittable for XZRect {
-    fn bounding_box(&self) -> AABB {
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> AABB {
         AABB::new(
             Vec3::new(self.x0, self.k - 0.0001, self.z0),
             Vec3::new(self.x1, self.k + 0.0001, self.z1),
@@ -283,12 +581,56 @@ impl Hittable for XZRect {
                 (x - self.x0) / (self.x1 - self.x0),
                 (z - self.z0) / (self.z1 - self.z0),
             ),
+            dpdu: Vec3::new(self.x1 - self.x0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, self.z1 - self.z0),
         };
         let normal = rec.normal;
         rec.set_face_normal(r, &normal);
 
         return Some(rec);
     }
+
+    fn random_surface_point(&self, _time: f64) -> Option<HitRecord> {
+        let x = random_float(self.x0, self.x1);
+        let z = random_float(self.z0, self.z1);
+
+        Some(HitRecord::at_surface_point(
+            Vec3::new(x, self.k, z),
+            Vec3::new(0.0, 1.0, 0.0),
+            &self.material,
+            TextureCoord::new(
+                (x - self.x0) / (self.x1 - self.x0),
+                (z - self.z0) / (self.z1 - self.z0),
+            ),
+            Vec3::new(self.x1 - self.x0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, self.z1 - self.z0),
+        ))
+    }
+
+    fn area(&self) -> f64 {
+        (self.x1 - self.x0) * (self.z1 - self.z0)
+    }
+
+    fn bakeable(&self) -> bool {
+        true
+    }
+
+    fn point_at_uv(&self, u: f64, v: f64, _time: f64) -> Option<HitRecord> {
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+
+        let x = self.x0 + u * (self.x1 - self.x0);
+        let z = self.z0 + v * (self.z1 - self.z0);
+        Some(HitRecord::at_surface_point(
+            Vec3::new(x, self.k, z),
+            Vec3::new(0.0, 1.0, 0.0),
+            &self.material,
+            TextureCoord::new(u, v),
+            Vec3::new(self.x1 - self.x0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, self.z1 - self.z0),
+        ))
+    }
 }
 
 pub struct YZRect {
@@ -314,7 +656,7 @@ impl YZRect {
 }
 
 impl Hittable for YZRect {
-    fn bounding_box(&self) -> AABB {
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> AABB {
         AABB::new(
             Vec3::new(self.k - 0.0001, self.y0, self.z0),
             Vec3::new(self.k + 0.0001, self.y1, self.z1),
@@ -342,14 +684,59 @@ impl Hittable for YZRect {
                 (y - self.y0) / (self.y1 - self.y0),
                 (z - self.z0) / (self.z1 - self.z0),
             ),
+            dpdu: Vec3::new(0.0, self.y1 - self.y0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, self.z1 - self.z0),
         };
         let normal = rec.normal;
         rec.set_face_normal(r, &normal);
 
         return Some(rec);
     }
+
+    fn random_surface_point(&self, _time: f64) -> Option<HitRecord> {
+        let y = random_float(self.y0, self.y1);
+        let z = random_float(self.z0, self.z1);
+
+        Some(HitRecord::at_surface_point(
+            Vec3::new(self.k, y, z),
+            Vec3::new(1.0, 0.0, 0.0),
+            &self.material,
+            TextureCoord::new(
+                (y - self.y0) / (self.y1 - self.y0),
+                (z - self.z0) / (self.z1 - self.z0),
+            ),
+            Vec3::new(0.0, self.y1 - self.y0, 0.0),
+            Vec3::new(0.0, 0.0, self.z1 - self.z0),
+        ))
+    }
+
+    fn area(&self) -> f64 {
+        (self.y1 - self.y0) * (self.z1 - self.z0)
+    }
+
+    fn bakeable(&self) -> bool {
+        true
+    }
+
+    fn point_at_uv(&self, u: f64, v: f64, _time: f64) -> Option<HitRecord> {
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+
+        let y = self.y0 + u * (self.y1 - self.y0);
+        let z = self.z0 + v * (self.z1 - self.z0);
+        Some(HitRecord::at_surface_point(
+            Vec3::new(self.k, y, z),
+            Vec3::new(1.0, 0.0, 0.0),
+            &self.material,
+            TextureCoord::new(u, v),
+            Vec3::new(0.0, self.y1 - self.y0, 0.0),
+            Vec3::new(0.0, 0.0, self.z1 - self.z0),
+        ))
+    }
 }
 
+/// An axis aligned box made of six rectangles. Every side covers all of texture space, so boxes can't be baked.
 pub struct Box {
     min: Point3,
     max: Point3,
@@ -417,11 +804,19 @@ impl Box {
 }
 
 impl Hittable for Box {
-    fn bounding_box(&self) -> AABB {
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> AABB {
         return AABB::new(self.min, self.max);
     }
 
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         return self.sides.hit(r, t_min, t_max);
     }
+
+    fn random_surface_point(&self, time: f64) -> Option<HitRecord> {
+        self.sides.random_surface_point(time)
+    }
+
+    fn area(&self) -> f64 {
+        self.sides.area()
+    }
 }
@@ -0,0 +1,424 @@
+use std::ops::{Add, AddAssign, Mul};
+use std::sync::OnceLock;
+
+use crate::buffer::Buffer;
+use crate::ray::{Ray, RayDifferential};
+use crate::scene::Scene;
+use crate::utility::{random_float_1, INFINITY};
+use crate::vector::{Color, Vec3};
+
+/// Shortest wavelength sampled, in nm
+pub const LAMBDA_MIN: f64 = 360.0;
+/// Longest wavelength sampled, in nm
+pub const LAMBDA_MAX: f64 = 830.0;
+/// Wavelength used for wavelength dependent values (like dispersive iors) when rendering in rgb
+pub const DEFAULT_WAVELENGTH: f64 = 550.0;
+/// Wavelengths standing in for the red, green and blue channels when rendering dispersion in rgb
+pub const RGB_WAVELENGTHS: [f64; 3] = [610.0, 550.0, 465.0];
+/// Number of wavelengths carried by each path
+pub const N_WAVELENGTHS: usize = 4;
+
+/// Spectral values at the wavelengths of a SampledWavelengths
+#[derive(Copy, Clone)]
+pub struct SampledSpectrum {
+    pub e: [f64; N_WAVELENGTHS],
+}
+
+impl SampledSpectrum {
+    /// Creates a spectrum with the same value at every wavelength
+    pub fn new(value: f64) -> SampledSpectrum {
+        SampledSpectrum {
+            e: [value; N_WAVELENGTHS],
+        }
+    }
+}
+
+impl Add for SampledSpectrum {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        let mut e = self.e;
+        for (value, other) in e.iter_mut().zip(other.e) {
+            *value += other;
+        }
+        SampledSpectrum { e }
+    }
+}
+
+impl AddAssign for SampledSpectrum {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        let mut e = self.e;
+        for (value, other) in e.iter_mut().zip(other.e) {
+            *value *= other;
+        }
+        SampledSpectrum { e }
+    }
+}
+
+impl Mul<f64> for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(self, other: f64) -> Self {
+        let mut e = self.e;
+        for value in e.iter_mut() {
+            *value *= other;
+        }
+        SampledSpectrum { e }
+    }
+}
+
+/// Wavelengths carried by a path, using hero wavelength sampling (Wilkie et al. 2014).
+///
+/// The hero wavelength is sampled uniformly and the others are spaced evenly after it, wrapping around the visible
+/// range. The hero decides the direction of wavelength dependent bounces.
+#[derive(Copy, Clone)]
+pub struct SampledWavelengths {
+    pub lambda: [f64; N_WAVELENGTHS],
+    pub pdf: [f64; N_WAVELENGTHS],
+}
+
+impl SampledWavelengths {
+    /// Samples a hero wavelength from u in [0, 1) and the wavelengths rotated from it
+    pub fn sample_uniform(u: f64) -> SampledWavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let mut lambda = [0.0; N_WAVELENGTHS];
+        lambda[0] = LAMBDA_MIN + u * range;
+
+        let delta = range / N_WAVELENGTHS as f64;
+        for i in 1..N_WAVELENGTHS {
+            lambda[i] = lambda[i - 1] + delta;
+            if lambda[i] > LAMBDA_MAX {
+                lambda[i] -= range;
+            }
+        }
+
+        SampledWavelengths {
+            lambda,
+            pdf: [1.0 / range; N_WAVELENGTHS],
+        }
+    }
+
+    /// Returns the hero wavelength
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    /// Drops every wavelength except the hero. Used after a wavelength dependent bounce, where the path is only
+    /// valid for the wavelength that picked its direction.
+    pub fn terminate_secondary(&mut self) {
+        if self.secondary_terminated() {
+            return;
+        }
+
+        for pdf in self.pdf[1..].iter_mut() {
+            *pdf = 0.0;
+        }
+        self.pdf[0] /= N_WAVELENGTHS as f64;
+    }
+
+    pub fn secondary_terminated(&self) -> bool {
+        self.pdf[1..].iter().all(|pdf| *pdf == 0.0)
+    }
+}
+
+/// Bin centers and spectra of Smits' rgb to spectrum conversion ("An RGB-to-Spectrum Conversion for Reflectances",
+/// 1999), in 10 bins from 380 to 720 nm
+const SMITS_LAMBDA_MIN: f64 = 380.0;
+const SMITS_LAMBDA_MAX: f64 = 720.0;
+const SMITS_WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/// Linearly interpolates a Smits spectrum at a wavelength, holding the end bins outside of their range
+fn smits_at(spectrum: &[f64; 10], lambda: f64) -> f64 {
+    let bin_width = (SMITS_LAMBDA_MAX - SMITS_LAMBDA_MIN) / 10.0;
+    let x = (lambda - SMITS_LAMBDA_MIN) / bin_width - 0.5;
+    if x <= 0.0 {
+        return spectrum[0];
+    }
+    if x >= 9.0 {
+        return spectrum[9];
+    }
+
+    let i = x as usize;
+    let f = x - i as f64;
+    (1.0 - f) * spectrum[i] + f * spectrum[i + 1]
+}
+
+/// Evaluates Smits' smooth spectrum for an rgb color at a single wavelength
+fn rgb_to_spectrum_at(rgb: &Color, lambda: f64) -> f64 {
+    let (r, g, b) = (rgb.x(), rgb.y(), rgb.z());
+    let at = |spectrum: &[f64; 10]| smits_at(spectrum, lambda);
+
+    // Start from the white spectrum scaled by the smallest component, then add the spectra of the two
+    // complementary/primary colors that make up the rest
+    if r <= g && r <= b {
+        let mut value = r * at(&SMITS_WHITE);
+        if g <= b {
+            value += (g - r) * at(&SMITS_CYAN) + (b - g) * at(&SMITS_BLUE);
+        } else {
+            value += (b - r) * at(&SMITS_CYAN) + (g - b) * at(&SMITS_GREEN);
+        }
+        value
+    } else if g <= r && g <= b {
+        let mut value = g * at(&SMITS_WHITE);
+        if r <= b {
+            value += (r - g) * at(&SMITS_MAGENTA) + (b - r) * at(&SMITS_BLUE);
+        } else {
+            value += (b - g) * at(&SMITS_MAGENTA) + (r - b) * at(&SMITS_RED);
+        }
+        value
+    } else {
+        let mut value = b * at(&SMITS_WHITE);
+        if r <= g {
+            value += (r - b) * at(&SMITS_YELLOW) + (g - r) * at(&SMITS_GREEN);
+        } else {
+            value += (g - b) * at(&SMITS_YELLOW) + (r - g) * at(&SMITS_RED);
+        }
+        value
+    }
+}
+
+/// Upsamples a linear rgb color to spectral values at the sampled wavelengths.
+///
+/// Used for both reflectances (albedos) and emitters, since Smits' spectra are linear in the rgb values.
+pub fn rgb_to_spectrum(rgb: &Color, lambdas: &SampledWavelengths) -> SampledSpectrum {
+    SampledSpectrum {
+        e: lambdas
+            .lambda
+            .map(|lambda| f64::max(0.0, rgb_to_spectrum_at(rgb, lambda))),
+    }
+}
+
+/// Interpolates a quantity given at the RGB_WAVELENGTHS linearly in wavelength, holding its end values beyond them.
+/// Used for physical quantities like a complex ior, which don't follow the shape of Smits' reflectance spectra.
+pub fn rgb_wavelengths_to_spectrum(
+    values: &Color,
+    lambdas: &SampledWavelengths,
+) -> SampledSpectrum {
+    // The rgb wavelengths run from long to short
+    let [red, green, blue] = RGB_WAVELENGTHS;
+    let at = |lambda: f64| {
+        if lambda >= red {
+            values.x()
+        } else if lambda >= green {
+            let t = (lambda - green) / (red - green);
+            (1.0 - t) * values.y() + t * values.x()
+        } else if lambda >= blue {
+            let t = (lambda - blue) / (green - blue);
+            (1.0 - t) * values.z() + t * values.y()
+        } else {
+            values.z()
+        }
+    };
+
+    SampledSpectrum {
+        e: lambdas.lambda.map(at),
+    }
+}
+
+/// Piecewise gaussian used by the CIE color matching function fit
+fn cie_gaussian(lambda: f64, mu: f64, sigma1: f64, sigma2: f64) -> f64 {
+    let sigma = if lambda < mu { sigma1 } else { sigma2 };
+    let t = (lambda - mu) / sigma;
+    f64::exp(-0.5 * t * t)
+}
+
+/// CIE 1931 color matching functions, using the multi-lobe fit from Wyman et al. 2013
+pub fn cie_xyz(lambda: f64) -> Vec3 {
+    Vec3::new(
+        1.056 * cie_gaussian(lambda, 599.8, 37.9, 31.0)
+            + 0.362 * cie_gaussian(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * cie_gaussian(lambda, 501.1, 20.4, 26.2),
+        0.821 * cie_gaussian(lambda, 568.8, 46.9, 40.5)
+            + 0.286 * cie_gaussian(lambda, 530.9, 16.3, 31.1),
+        1.217 * cie_gaussian(lambda, 437.0, 11.8, 36.0)
+            + 0.681 * cie_gaussian(lambda, 459.0, 26.0, 13.8),
+    )
+}
+
+/// Converts CIE XYZ to linear sRGB (D65)
+pub fn xyz_to_rgb(xyz: &Vec3) -> Color {
+    Color::new(
+        3.2404542 * xyz.x() - 1.5371385 * xyz.y() - 0.4985314 * xyz.z(),
+        -0.9692660 * xyz.x() + 1.8760108 * xyz.y() + 0.0415560 * xyz.z(),
+        0.0556434 * xyz.x() - 0.2040259 * xyz.y() + 1.0572252 * xyz.z(),
+    )
+}
+
+/// Returns the rgb color a constant spectrum of 1 integrates to.
+///
+/// Dividing by it white balances the film, so rgb colors that get upsampled and integrated back come out unchanged.
+fn film_white() -> Color {
+    static WHITE: OnceLock<Color> = OnceLock::new();
+    *WHITE.get_or_init(|| {
+        let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+        let mut lambda = LAMBDA_MIN;
+        while lambda <= LAMBDA_MAX {
+            xyz += cie_xyz(lambda);
+            lambda += 1.0;
+        }
+        xyz_to_rgb(&xyz)
+    })
+}
+
+/// Converts spectral radiance at the sampled wavelengths to a white balanced linear rgb color
+pub fn spectrum_to_rgb(spectrum: &SampledSpectrum, lambdas: &SampledWavelengths) -> Color {
+    let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+    for i in 0..N_WAVELENGTHS {
+        if lambdas.pdf[i] != 0.0 {
+            xyz += cie_xyz(lambdas.lambda[i]) * (spectrum.e[i] / lambdas.pdf[i]);
+        }
+    }
+    xyz /= N_WAVELENGTHS as f64;
+
+    let rgb = xyz_to_rgb(&xyz);
+    let white = film_white();
+    Color::new(
+        rgb.x() / white.x(),
+        rgb.y() / white.y(),
+        rgb.z() / white.z(),
+    )
+}
+
+impl Scene {
+    /// Renders the scene spectrally, tracing 4 wavelengths per path and converting to rgb at the film
+    pub fn render_spectral(&self) -> Buffer {
+        let width = self.width() as usize;
+        let height = self.height() as usize;
+        let samples = self.samples();
+        let num_threads = usize::max(num_cpus::get(), 1);
+        let rows_per_thread = height.div_ceil(num_threads);
+
+        let mut pixels = vec![Color::new(0.0, 0.0, 0.0); width * height];
+        crossbeam::scope(|scope| {
+            for (chunk_index, chunk) in pixels.chunks_mut(rows_per_thread * width).enumerate() {
+                scope.spawn(move |_| {
+                    for (index, pixel) in chunk.iter_mut().enumerate() {
+                        let index = chunk_index * rows_per_thread * width + index;
+                        let i = index % width;
+                        let j = index / width;
+                        if i == 0 {
+                            eprint!("\rLine {}", j);
+                        }
+
+                        for _ in 0..samples {
+                            let u = (i as f64 + random_float_1()) / (width - 1) as f64;
+                            let v = (j as f64 + random_float_1()) / (height - 1) as f64;
+                            let mut lambdas = SampledWavelengths::sample_uniform(random_float_1());
+                            let (ray, differential) = self.get_ray_differential(
+                                u,
+                                v,
+                                1.0 / (width - 1) as f64,
+                                1.0 / (height - 1) as f64,
+                            );
+                            let radiance = self.ray_spectrum(&ray, &differential, &mut lambdas);
+                            *pixel += self
+                                .working_space
+                                .to_working(spectrum_to_rgb(&radiance, &lambdas));
+                        }
+                        *pixel /= samples as f64;
+                    }
+                });
+            }
+        })
+        .unwrap();
+        eprintln!();
+
+        let mut buffer = Buffer::new(width as u32, height as u32);
+        for (index, pixel) in pixels.iter().enumerate() {
+            buffer.write(*pixel, (index % width) as u32, (index / width) as u32);
+        }
+
+        buffer
+    }
+
+    /// Upsamples a color in the working space. Smits' spectra are fit to Rec.709 primaries, so wider gamut colors
+    /// are converted first and lose what lies outside of it.
+    pub fn upsample(&self, color: Color, lambdas: &SampledWavelengths) -> SampledSpectrum {
+        rgb_to_spectrum(&self.working_space.to_rec709(color), lambdas)
+    }
+
+    /// Returns the spectral radiance along a camera ray, following one bounce at a time through Material::scatter
+    pub fn ray_spectrum(
+        &self,
+        r: &Ray,
+        differential: &RayDifferential,
+        lambdas: &mut SampledWavelengths,
+    ) -> SampledSpectrum {
+        let mut radiance = SampledSpectrum::new(0.0);
+        let mut throughput = SampledSpectrum::new(1.0);
+        let mut ray = Ray::new(r.origin, r.direction, r.time);
+        ray.wavelength = Some(lambdas.hero());
+
+        for bounce in 0..self.max_depth() {
+            let hit = if bounce == 0 {
+                self.hit_from_camera(&ray, differential)
+            } else {
+                self.hit(&ray, 0.001, INFINITY)
+            };
+            let rec = match hit {
+                Some(rec) => rec,
+                None => {
+                    let background = self.background_color(ray.direction);
+                    radiance += throughput * self.upsample(background, lambdas);
+                    break;
+                }
+            };
+
+            radiance += throughput * self.upsample(rec.material.emitted(&rec), lambdas);
+
+            // Point lights are added at every bounce, with the brdf evaluated towards each of them and upsampled
+            // separately from the light. Like SPPM, the sum isn't clamped.
+            for light in &self.lights.lights {
+                let details = light.apply(&ray, &rec, self);
+                let brdf = rec.material.brdf(&ray, &rec, &(details.position - rec.p));
+                radiance += throughput
+                    * self.upsample(brdf, lambdas)
+                    * self.upsample(details.contribution, lambdas);
+            }
+
+            let (scattered, attenuation) =
+                match rec.material.scatter_spectral(&ray, &rec, self, lambdas) {
+                    Some(scattered) => scattered,
+                    None => break,
+                };
+
+            if rec.material.is_dispersive() {
+                lambdas.terminate_secondary();
+            }
+
+            throughput = throughput * attenuation;
+            ray = scattered.ray;
+            ray.wavelength = Some(lambdas.hero());
+        }
+
+        radiance
+    }
+}
@@ -0,0 +1,239 @@
+use std::sync::Arc;
+
+use crate::hit::HitRecord;
+use crate::lights::Light;
+use crate::materials::{diffuse_scatter, fresnel_dielectric, Material, ScatterRecord};
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::texture::Texture;
+use crate::utility::random_float_1;
+use crate::vector::{
+    dot, random_unit_vector, reflect, refract, unit_vector, zero_vec, Color, Vec3,
+};
+
+/// Random walks longer than this are absorbed
+const MAX_STEPS: usize = 1024;
+
+/// Subsurface scattering material for skin, wax, marble, milk and the like.
+///
+/// Light refracts through a smooth dielectric boundary and then random walks through a homogeneous, isotropic
+/// medium filling the shape until it leaves again. The walk intersects the whole scene, so the material should be
+/// applied to closed shapes that don't overlap other objects.
+pub struct Subsurface {
+    /// Single scattering albedo of the medium per channel. The surface looks darker than this since light
+    /// scatters many times before leaving.
+    pub albedo: Arc<dyn Texture>,
+    /// Mean distance light travels between scattering events per channel, in scene units
+    pub mfp: Color,
+    /// Index of refraction of the boundary
+    pub ior: f64,
+}
+
+impl Subsurface {
+    pub fn new(albedo: Arc<dyn Texture>, mfp: Color, ior: f64) -> Subsurface {
+        Subsurface {
+            albedo: Arc::clone(&albedo),
+            mfp,
+            ior,
+        }
+    }
+
+    /// Traces a path through the boundary and the medium behind it.
+    ///
+    /// Returns the ray leaving the surface along with the hit it leaves from, with its normal facing outwards.
+    /// Returns None if the path is absorbed.
+    fn walk(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        scene: &Scene,
+    ) -> Option<(ScatterRecord, HitRecord)> {
+        let unit_direction = unit_vector(r_in.direction);
+        let cos_theta = f64::min(dot(&-unit_direction, &rec.normal), 1.0);
+        let eta = if rec.front_face {
+            self.ior
+        } else {
+            1.0 / self.ior
+        };
+
+        // Specular reflection off of the boundary, or a ray that started inside the shape leaving it
+        let reflected = random_float_1() < fresnel_dielectric(cos_theta, eta);
+        if reflected || !rec.front_face {
+            let direction = if reflected {
+                reflect(&unit_direction, &rec.normal)
+            } else {
+                refract(&unit_direction, &rec.normal, 1.0 / eta)
+            };
+            let scattered = ScatterRecord {
+                ray: Ray {
+                    origin: rec.p,
+                    direction,
+                    time: r_in.time,
+                    wavelength: r_in.wavelength,
+                },
+                attenuation: Color::new(1.0, 1.0, 1.0),
+                specular: true,
+            };
+            return Some((scattered, rec.clone()));
+        }
+
+        let albedo = self.albedo.value(&rec.uv, &rec.p);
+        let sigma_t = Color::new(
+            1.0 / f64::max(self.mfp.x(), 1e-6),
+            1.0 / f64::max(self.mfp.y(), 1e-6),
+            1.0 / f64::max(self.mfp.z(), 1e-6),
+        );
+        let sigma_s = albedo * sigma_t;
+
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = Ray {
+            origin: rec.p,
+            direction: refract(&unit_direction, &rec.normal, 1.0 / eta),
+            time: r_in.time,
+            wavelength: r_in.wavelength,
+        };
+
+        for _ in 0..MAX_STEPS {
+            // Sample a distance using one channel, picked in proportion to its throughput so channels that are
+            // nearly absorbed stop driving the walk. The pdf is the average over the channels it could have come
+            // from, which keeps channels with very different mean free paths unbiased.
+            let total = throughput.x() + throughput.y() + throughput.z();
+            if total <= 0.0 {
+                return None;
+            }
+            let weights = throughput / total;
+            let u = random_float_1();
+            let channel = if u < weights.x() {
+                0
+            } else if u < weights.x() + weights.y() {
+                1
+            } else {
+                2
+            };
+            let distance = -f64::ln(1.0 - random_float_1()) / sigma_t[channel];
+
+            match scene.hit(&ray, 0.001, distance) {
+                Some(exit) => {
+                    let transmittance = transmittance(&sigma_t, exit.t);
+                    throughput = throughput * transmittance / dot(&weights, &transmittance);
+
+                    // The exit normal faces back into the medium
+                    let cos_theta = dot(&-ray.direction, &exit.normal);
+                    let eta = 1.0 / self.ior;
+                    if random_float_1() < fresnel_dielectric(cos_theta, eta) {
+                        ray = Ray {
+                            origin: exit.p,
+                            direction: reflect(&ray.direction, &exit.normal),
+                            time: ray.time,
+                            wavelength: ray.wavelength,
+                        };
+                        continue;
+                    }
+
+                    let scattered = ScatterRecord {
+                        ray: Ray {
+                            origin: exit.p,
+                            direction: refract(&ray.direction, &exit.normal, 1.0 / eta),
+                            time: ray.time,
+                            wavelength: ray.wavelength,
+                        },
+                        attenuation: throughput,
+                        specular: false,
+                    };
+
+                    let mut outside = exit.clone();
+                    outside.normal = -exit.normal;
+                    outside.front_face = true;
+                    return Some((scattered, outside));
+                }
+                None => {
+                    let transmittance = transmittance(&sigma_t, distance);
+                    throughput = throughput * sigma_s * transmittance
+                        / dot(&weights, &(sigma_t * transmittance));
+
+                    ray = Ray {
+                        origin: ray.at(distance),
+                        direction: random_unit_vector(),
+                        time: ray.time,
+                        wavelength: ray.wavelength,
+                    };
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns the multiple scattering albedo of a semi-infinite medium (van de Hulst), the fraction of light a
+    /// diffuse surface standing in for the walk reflects
+    fn reflectance(&self, rec: &HitRecord) -> Color {
+        let albedo = self.albedo.value(&rec.uv, &rec.p);
+        let multiple_scattering = |a: f64| {
+            let s = f64::sqrt(1.0 - f64::clamp(a, 0.0, 1.0));
+            (1.0 - s) * (1.0 - 0.139 * s) / (1.0 + 1.17 * s)
+        };
+
+        Color::new(
+            multiple_scattering(albedo.x()),
+            multiple_scattering(albedo.y()),
+            multiple_scattering(albedo.z()),
+        )
+    }
+}
+
+impl Material for Subsurface {
+    fn apply(&self, r_in: &Ray, rec: &HitRecord, scene: &Scene, depth: i32) -> Color {
+        match self.walk(r_in, rec, scene) {
+            Some((scattered, exit)) => {
+                let bounced = scattered.attenuation * scene.ray_color(&scattered.ray, depth);
+                if scattered.specular {
+                    return bounced;
+                }
+
+                // Light the exit point as if it were diffuse, seen along the ray leaving it
+                let direction = scattered.ray.direction;
+                let outgoing = Ray {
+                    origin: exit.p + direction,
+                    direction: -direction,
+                    time: r_in.time,
+                    wavelength: r_in.wavelength,
+                };
+                bounced
+                    + scattered.attenuation
+                        * scene.lights.apply(&outgoing, &exit, scene).contribution
+            }
+            None => zero_vec(),
+        }
+    }
+
+    /// Without the scene the walk can't be traced, so this approximates the material as a diffuse surface
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        Some(diffuse_scatter(r_in, rec, self.reflectance(rec)))
+    }
+
+    fn scatter_in_scene(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        scene: &Scene,
+    ) -> Option<ScatterRecord> {
+        self.walk(r_in, rec, scene).map(|(scattered, _)| scattered)
+    }
+
+    /// Point lights and photons see the same diffuse approximation as scatter
+    fn brdf(&self, _r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        if dot(direction, &rec.normal) <= 0.0 {
+            return zero_vec();
+        }
+        self.reflectance(rec)
+    }
+}
+
+/// Fraction of light surviving a distance through a medium with extinction sigma_t
+fn transmittance(sigma_t: &Color, distance: f64) -> Color {
+    Color::new(
+        f64::exp(-sigma_t.x() * distance),
+        f64::exp(-sigma_t.y() * distance),
+        f64::exp(-sigma_t.z() * distance),
+    )
+}
@@ -1,9 +1,16 @@
-use std::sync::Arc;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
 
 use crate::{
-    buffer::Buffer,
+    assets::{load_image, ImageChannel, ImageOptions, Pyramid},
+    color::{hsv_to_rgb, rgb_to_hsv, WorkingSpace},
+    image::ImageError,
+    mipmap::{FilterMode, MipMap, WrapMode},
+    noise::FractalNoise,
     perlin::Perlin,
-    vector::{Color, Point3},
+    projection::Space,
+    vector::{zero_vec, Color, Point3, Vec3},
 };
 
 pub trait Texture: Sync + Send {
@@ -15,11 +22,40 @@ pub trait Texture: Sync + Send {
 pub struct TextureCoord {
     pub u: f64,
     pub v: f64,
+    /// Change in u and v from one pixel to the next in x and y. Zero when the footprint is unknown, e.g. after a
+    /// bounce, in which case filtered textures use their sharpest level.
+    pub dudx: f64,
+    pub dudy: f64,
+    pub dvdx: f64,
+    pub dvdy: f64,
+    /// Change in the world space hit point from one pixel to the next, zero like the uv derivatives
+    pub dpdx: Vec3,
+    pub dpdy: Vec3,
+    /// Hit point and outward normal in the space of the shape, before any instance transforms
+    pub object_p: Point3,
+    pub object_normal: Vec3,
+    /// Outward normal in world space
+    pub normal: Vec3,
+    /// Time of the ray that found the hit, in seconds from the first frame
+    pub time: f64,
 }
 
 impl TextureCoord {
     pub fn new(u: f64, v: f64) -> TextureCoord {
-        TextureCoord { u, v }
+        TextureCoord {
+            u,
+            v,
+            dudx: 0.0,
+            dudy: 0.0,
+            dvdx: 0.0,
+            dvdy: 0.0,
+            dpdx: zero_vec(),
+            dpdy: zero_vec(),
+            object_p: zero_vec(),
+            object_normal: zero_vec(),
+            normal: zero_vec(),
+            time: 0.0,
+        }
     }
 }
 
@@ -87,6 +123,14 @@ impl NoiseTexture {
             scale,
         }
     }
+
+    /// Creates a marble texture that looks the same on every render for the same seed
+    pub fn with_seed(scale: f64, seed: u64) -> NoiseTexture {
+        NoiseTexture {
+            noise: Perlin::with_seed(seed),
+            scale,
+        }
+    }
 }
 
 impl Texture for NoiseTexture {
@@ -98,31 +142,496 @@ impl Texture for NoiseTexture {
 }
 
 pub struct ImageTexture {
-    buffer: Buffer,
-    width: u32,
-    height: u32,
+    mipmap: MipMap,
 }
 
 impl ImageTexture {
-    /// Create a new ImageTexture from a PNG image
-    pub fn new(path: String) -> ImageTexture {
-        let buffer = Buffer::new_from_png(path);
-        let width = buffer.width();
-        let height = buffer.height();
+    /// Create a new repeating, trilinear filtered ImageTexture from a png, jpeg, hdr or exr image. sRGB encoded
+    /// images are decoded to linear. Images are shared through the asset cache.
+    pub fn new(path: String) -> Result<ImageTexture, ImageError> {
+        let pyramid = load_image(&path, &ImageOptions::default())?;
+        Ok(ImageTexture::new_from_pyramid(
+            pyramid,
+            WrapMode::Repeat,
+            FilterMode::Trilinear,
+        ))
+    }
+
+    /// Create a new grey ImageTexture from the alpha channel of an image
+    pub fn new_alpha(path: String) -> Result<ImageTexture, ImageError> {
+        let options = ImageOptions {
+            channel: ImageChannel::Alpha,
+            ..ImageOptions::default()
+        };
+        Ok(ImageTexture::new_from_pyramid(
+            load_image(&path, &options)?,
+            WrapMode::Repeat,
+            FilterMode::Trilinear,
+        ))
+    }
 
+    pub fn new_from_pyramid(
+        pyramid: Arc<Pyramid>,
+        wrap: WrapMode,
+        filter: FilterMode,
+    ) -> ImageTexture {
         ImageTexture {
-            buffer,
-            width,
-            height,
+            mipmap: MipMap::new(pyramid, wrap, filter),
         }
     }
 }
 
 impl Texture for ImageTexture {
     fn value(&self, uv: &TextureCoord, _p: &Point3) -> Color {
-        let x = (uv.u * self.width as f64) as u32;
-        let y = (uv.v * self.height as f64) as u32;
+        self.mipmap.lookup(uv)
+    }
+}
+
+/// An image per frame, picked by the time of the lookup. Frames are loaded the first time they are looked up.
+pub struct ImageSequence {
+    paths: Vec<String>,
+    frames: Vec<OnceLock<ImageTexture>>,
+    options: ImageOptions,
+    wrap: WrapMode,
+    filter: FilterMode,
+    fps: f64,
+    /// Start over after the last frame instead of holding it
+    looping: bool,
+}
+
+impl ImageSequence {
+    /// Creates a sequence from the images numbered from first_frame on, as long as they exist. The frame number
+    /// replaces a printf style "%d" or "%04d" in the pattern. Time 0 shows the first frame.
+    pub fn new(
+        pattern: &str,
+        first_frame: i64,
+        fps: f64,
+        looping: bool,
+        options: ImageOptions,
+        wrap: WrapMode,
+        filter: FilterMode,
+    ) -> Result<ImageSequence, ImageError> {
+        let mut paths = Vec::new();
+        let mut frame = first_frame;
+        loop {
+            let path = sequence_path(pattern, frame);
+            if !Path::new(&path).exists() {
+                break;
+            }
+            paths.push(path);
+            frame += 1;
+        }
+        if paths.is_empty() {
+            return Err(ImageError::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no frames match {}", pattern),
+            )));
+        }
+
+        // Load the first frame now, so a broken sequence fails before rendering
+        let first = load_image(&paths[0], &options)?;
+        let mut frames = vec![OnceLock::from(ImageTexture::new_from_pyramid(
+            first, wrap, filter,
+        ))];
+        frames.extend((1..paths.len()).map(|_| OnceLock::new()));
+
+        Ok(ImageSequence {
+            paths,
+            frames,
+            options,
+            wrap,
+            filter,
+            fps,
+            looping,
+        })
+    }
+
+    /// Returns the frame shown at a time
+    fn frame(&self, time: f64) -> &ImageTexture {
+        let count = self.frames.len() as i64;
+        let frame = f64::floor(time * self.fps + 1e-9) as i64;
+        let index = if self.looping {
+            frame.rem_euclid(count)
+        } else {
+            frame.clamp(0, count - 1)
+        } as usize;
+
+        self.frames[index].get_or_init(|| {
+            let path = &self.paths[index];
+            let pyramid = load_image(path, &self.options)
+                .unwrap_or_else(|error| panic!("Failed to load image {}: {}", path, error));
+            ImageTexture::new_from_pyramid(pyramid, self.wrap, self.filter)
+        })
+    }
+}
+
+impl Texture for ImageSequence {
+    fn value(&self, uv: &TextureCoord, p: &Point3) -> Color {
+        self.frame(uv.time).value(uv, p)
+    }
+}
+
+/// Returns the path of a frame of an image sequence, replacing the first "%d" or "%0[width]d" in the pattern
+fn sequence_path(pattern: &str, frame: i64) -> String {
+    let start = match pattern.find('%') {
+        Some(start) => start,
+        None => return pattern.to_string(),
+    };
+    let rest = &pattern[start + 1..];
+    let end = match rest.find('d') {
+        Some(end) => end,
+        None => return pattern.to_string(),
+    };
+    let width = rest[..end].parse::<usize>().unwrap_or(0);
+
+    format!(
+        "{}{:0width$}{}",
+        &pattern[..start],
+        frame,
+        &rest[end + 1..],
+        width = width
+    )
+}
+
+/// Returns the point a procedural texture is evaluated at
+pub fn texture_point(uv: &TextureCoord, p: &Point3, space: Space) -> Point3 {
+    match space {
+        Space::Object => uv.object_p,
+        Space::World => *p,
+    }
+}
+
+/// Grey fractal noise in about [0, 1]
+pub struct FractalTexture {
+    noise: FractalNoise,
+    /// Frequency of the first octave
+    scale: f64,
+    space: Space,
+    /// Position along the fourth noise dimension. Changing it over frames makes the noise evolve in place.
+    w: Option<f64>,
+    /// Distance moved along the fourth dimension per second, so the noise evolves within a frame as well
+    evolution: f64,
+}
+
+impl FractalTexture {
+    pub fn new(
+        noise: FractalNoise,
+        scale: f64,
+        space: Space,
+        w: Option<f64>,
+        evolution: f64,
+    ) -> FractalTexture {
+        FractalTexture {
+            noise,
+            scale,
+            space,
+            w,
+            evolution,
+        }
+    }
+}
+
+impl Texture for FractalTexture {
+    fn value(&self, uv: &TextureCoord, p: &Point3) -> Color {
+        let point = self.scale * texture_point(uv, p, self.space);
+        let w = if self.evolution != 0.0 {
+            Some(self.w.unwrap_or(0.0) + self.evolution * uv.time)
+        } else {
+            self.w
+        };
+        let value = self.noise.value(&point, w);
+        Color::new(value, value, value)
+    }
+}
+
+/// Distorts another texture by offsetting the points and texture coordinates it is looked up at with noise
+pub struct DomainWarp {
+    texture: Arc<dyn Texture>,
+    noise: FractalNoise,
+    scale: f64,
+    /// Largest offset, in the units of the space the noise is evaluated in
+    strength: f64,
+    space: Space,
+}
+
+impl DomainWarp {
+    pub fn new(
+        texture: Arc<dyn Texture>,
+        noise: FractalNoise,
+        scale: f64,
+        strength: f64,
+        space: Space,
+    ) -> DomainWarp {
+        DomainWarp {
+            texture,
+            noise,
+            scale,
+            strength,
+            space,
+        }
+    }
+}
+
+impl Texture for DomainWarp {
+    fn value(&self, uv: &TextureCoord, p: &Point3) -> Color {
+        let point = self.scale * texture_point(uv, p, self.space);
+        let offset = self.strength * self.noise.vector(&point, None);
+
+        let mut warped = uv.clone();
+        warped.u += offset.x();
+        warped.v += offset.y();
+        let p = match self.space {
+            Space::Object => {
+                warped.object_p += offset;
+                *p
+            }
+            Space::World => *p + offset,
+        };
+        self.texture.value(&warped, &p)
+    }
+}
+
+/// How a color ramp blends between its stops
+#[derive(Clone, Copy)]
+pub enum RampInterpolation {
+    /// The color of the stop below, giving hard bands
+    Constant,
+    Linear,
+    /// Smoothstep between the stops
+    Smooth,
+}
+
+/// Maps the first channel of another texture to colors. Stops are (position, color) pairs, sorted by position.
+pub struct ColorRamp {
+    input: Arc<dyn Texture>,
+    stops: Vec<(f64, Color)>,
+    interpolation: RampInterpolation,
+}
+
+impl ColorRamp {
+    /// Creates a ramp from at least one stop, in any order
+    pub fn new(
+        input: Arc<dyn Texture>,
+        mut stops: Vec<(f64, Color)>,
+        interpolation: RampInterpolation,
+    ) -> ColorRamp {
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        ColorRamp {
+            input,
+            stops,
+            interpolation,
+        }
+    }
+
+    /// Returns the color of the ramp at a position
+    pub fn lookup(&self, t: f64) -> Color {
+        let first = self.stops[0];
+        let last = self.stops[self.stops.len() - 1];
+        if t <= first.0 {
+            return first.1;
+        }
+        if t >= last.0 {
+            return last.1;
+        }
 
-        self.buffer.at(x, y)
+        let upper = self.stops.iter().position(|stop| stop.0 > t).unwrap();
+        let (t0, c0) = self.stops[upper - 1];
+        let (t1, c1) = self.stops[upper];
+        let x = (t - t0) / (t1 - t0);
+        let x = match self.interpolation {
+            RampInterpolation::Constant => 0.0,
+            RampInterpolation::Linear => x,
+            RampInterpolation::Smooth => x * x * (3.0 - 2.0 * x),
+        };
+        (1.0 - x) * c0 + x * c1
+    }
+}
+
+impl Texture for ColorRamp {
+    fn value(&self, uv: &TextureCoord, p: &Point3) -> Color {
+        self.lookup(self.input.value(uv, p).x())
+    }
+}
+
+/// Blends two textures by a factor texture, channel by channel. A factor of 0 gives a, 1 gives b.
+pub struct MixTexture {
+    a: Arc<dyn Texture>,
+    b: Arc<dyn Texture>,
+    factor: Arc<dyn Texture>,
+}
+
+impl MixTexture {
+    pub fn new(a: Arc<dyn Texture>, b: Arc<dyn Texture>, factor: Arc<dyn Texture>) -> MixTexture {
+        MixTexture { a, b, factor }
+    }
+}
+
+impl Texture for MixTexture {
+    fn value(&self, uv: &TextureCoord, p: &Point3) -> Color {
+        let factor = self.factor.value(uv, p);
+        (Color::new(1.0, 1.0, 1.0) - factor) * self.a.value(uv, p) + factor * self.b.value(uv, p)
+    }
+}
+
+/// Arithmetic on two textures, channel by channel
+#[derive(Clone, Copy)]
+pub enum Operation {
+    Add,
+    Subtract,
+    Multiply,
+}
+
+pub struct Arithmetic {
+    a: Arc<dyn Texture>,
+    b: Arc<dyn Texture>,
+    operation: Operation,
+}
+
+impl Arithmetic {
+    pub fn new(a: Arc<dyn Texture>, b: Arc<dyn Texture>, operation: Operation) -> Arithmetic {
+        Arithmetic { a, b, operation }
+    }
+}
+
+impl Texture for Arithmetic {
+    fn value(&self, uv: &TextureCoord, p: &Point3) -> Color {
+        let a = self.a.value(uv, p);
+        let b = self.b.value(uv, p);
+        match self.operation {
+            Operation::Add => a + b,
+            Operation::Subtract => a - b,
+            Operation::Multiply => a * b,
+        }
+    }
+}
+
+/// Linearly maps each channel of a texture from one range to another, optionally clamping to the new range
+pub struct Remap {
+    input: Arc<dyn Texture>,
+    from: (f64, f64),
+    to: (f64, f64),
+    clamp: bool,
+}
+
+impl Remap {
+    pub fn new(input: Arc<dyn Texture>, from: (f64, f64), to: (f64, f64), clamp: bool) -> Remap {
+        Remap {
+            input,
+            from,
+            to,
+            clamp,
+        }
+    }
+
+    fn remap(&self, x: f64) -> f64 {
+        let t = if self.from.1 == self.from.0 {
+            0.0
+        } else {
+            (x - self.from.0) / (self.from.1 - self.from.0)
+        };
+        let y = self.to.0 + t * (self.to.1 - self.to.0);
+        if self.clamp {
+            f64::clamp(
+                y,
+                f64::min(self.to.0, self.to.1),
+                f64::max(self.to.0, self.to.1),
+            )
+        } else {
+            y
+        }
+    }
+}
+
+impl Texture for Remap {
+    fn value(&self, uv: &TextureCoord, p: &Point3) -> Color {
+        let color = self.input.value(uv, p);
+        Color::new(
+            self.remap(color.x()),
+            self.remap(color.y()),
+            self.remap(color.z()),
+        )
+    }
+}
+
+/// One minus a texture
+pub struct Invert {
+    input: Arc<dyn Texture>,
+}
+
+impl Invert {
+    pub fn new(input: Arc<dyn Texture>) -> Invert {
+        Invert { input }
+    }
+}
+
+impl Texture for Invert {
+    fn value(&self, uv: &TextureCoord, p: &Point3) -> Color {
+        Color::new(1.0, 1.0, 1.0) - self.input.value(uv, p)
+    }
+}
+
+/// Shifts the hue and scales the saturation and value of a texture
+pub struct HsvAdjust {
+    input: Arc<dyn Texture>,
+    /// Added to the hue, in turns
+    hue: f64,
+    saturation: f64,
+    value: f64,
+}
+
+impl HsvAdjust {
+    pub fn new(input: Arc<dyn Texture>, hue: f64, saturation: f64, value: f64) -> HsvAdjust {
+        HsvAdjust {
+            input,
+            hue,
+            saturation,
+            value,
+        }
+    }
+}
+
+impl Texture for HsvAdjust {
+    fn value(&self, uv: &TextureCoord, p: &Point3) -> Color {
+        let (hue, saturation, value) = rgb_to_hsv(self.input.value(uv, p));
+        hsv_to_rgb(
+            hue + self.hue,
+            f64::clamp(saturation * self.saturation, 0.0, 1.0),
+            value * self.value,
+        )
+    }
+}
+
+/// Channel of a color to extract
+#[derive(Clone, Copy)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    /// Luminance, weighted for the primaries of a working space
+    Luminance(WorkingSpace),
+}
+
+/// Grey texture from a single channel of another texture
+pub struct ChannelSelect {
+    input: Arc<dyn Texture>,
+    channel: Channel,
+}
+
+impl ChannelSelect {
+    pub fn new(input: Arc<dyn Texture>, channel: Channel) -> ChannelSelect {
+        ChannelSelect { input, channel }
+    }
+}
+
+impl Texture for ChannelSelect {
+    fn value(&self, uv: &TextureCoord, p: &Point3) -> Color {
+        let color = self.input.value(uv, p);
+        let value = match self.channel {
+            Channel::Red => color.x(),
+            Channel::Green => color.y(),
+            Channel::Blue => color.z(),
+            Channel::Luminance(working_space) => working_space.luminance(color),
+        };
+        Color::new(value, value, value)
     }
 }
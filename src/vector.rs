@@ -232,6 +232,21 @@ pub fn random_unit_vector() -> Vec3 {
     unit_vector(random_in_unit_sphere())
 }
 
+/// Random direction in the z hemisphere, with density proportional to the cosine of its angle to z
+pub fn random_cosine_direction() -> Vec3 {
+    let r1 = random_float_1();
+    let r2 = random_float_1();
+    let phi = 2.0 * std::f64::consts::PI * r1;
+
+    Vec3 {
+        e: [
+            f64::cos(phi) * f64::sqrt(r2),
+            f64::sin(phi) * f64::sqrt(r2),
+            f64::sqrt(1.0 - r2),
+        ],
+    }
+}
+
 /// Reflect a vector around an normal
 pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
     *v - *n * dot(v, n) * 2.0
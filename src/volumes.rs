@@ -4,12 +4,12 @@ use crate::{
     aabb::AABB,
     hit::{HitRecord, Hittable},
     lights::Light,
-    materials::Material,
+    materials::{Material, ScatterRecord},
     ray::Ray,
     scene::Scene,
     texture::Texture,
     utility::random_float_1,
-    vector::{random_in_unit_sphere, Color, Vec3},
+    vector::{random_in_unit_sphere, random_unit_vector, zero_vec, Color, Vec3},
 };
 
 struct Isotropic {
@@ -33,13 +33,26 @@ impl Material for Isotropic {
         let cr =
             self.albedo.value(&rec.uv, &rec.p) * scene.lights.apply(r_in, rec, scene).contribution;
 
-        let scattered = Ray::new(rec.p, random_in_unit_sphere());
+        let scattered = Ray::new(rec.p, random_in_unit_sphere(), r_in.time);
 
         let scattered_color = scene.ray_color(&scattered, depth);
 
         return cr * self.absorbance
             + (1.0 - self.absorbance) * self.albedo.value(&rec.uv, &rec.p) * scattered_color;
     }
+
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        Some(ScatterRecord {
+            ray: Ray::new(rec.p, random_unit_vector(), r_in.time),
+            attenuation: self.albedo.value(&rec.uv, &rec.p),
+            specular: false,
+        })
+    }
+
+    /// Scatters the same amount of light in every direction
+    fn brdf(&self, _r_in: &Ray, rec: &HitRecord, _direction: &Vec3) -> Color {
+        self.albedo.value(&rec.uv, &rec.p)
+    }
 }
 
 pub struct ConstantMedium {
@@ -64,8 +77,8 @@ impl ConstantMedium {
 }
 
 impl Hittable for ConstantMedium {
-    fn bounding_box(&self) -> AABB {
-        return self.boundary.bounding_box();
+    fn bounding_box(&self, time0: f64, time1: f64) -> AABB {
+        return self.boundary.bounding_box(time0, time1);
     }
 
     fn hit(&self, r: &crate::ray::Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
@@ -133,6 +146,8 @@ impl Hittable for ConstantMedium {
             t,
             front_face,
             uv: rec1.uv, // book doesn't cover how to set the uv pointer for this, so I just use rec1's uv
+            dpdu: zero_vec(),
+            dpdv: zero_vec(),
         })
     }
 }